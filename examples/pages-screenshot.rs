@@ -1,77 +1,34 @@
+use chrono::{NaiveDate, Utc};
 use embedded_graphics::image::ImageRaw;
 use embedded_graphics::pixelcolor::BinaryColor;
 use embedded_graphics::prelude::*;
 use std::path::Path;
 
+use energy_monitor::alert::{Alert, AlertState, Severity};
+use energy_monitor::analytics::consumption::{ConsumptionSummary, TariffEnergy};
+use energy_monitor::analytics::cost::CostSummary;
+use energy_monitor::analytics::reactive::{Direction, LinePowerFactor, PowerFactorSummary};
+use energy_monitor::analytics::voltage::{Excursions, PhaseVoltage, VoltageSummary};
 use energy_monitor::display::icons::*;
 use energy_monitor::display::pages::*;
 use energy_monitor::driver::linky::TariffPeriod;
 use energy_monitor::driver::ssd1305::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
-use image::{imageops::resize, ImageBuffer, Luma};
-
-// Inspired from https://github.com/embedded-graphics/embedded-graphics/blob/657fb4b/tools/png-target/src/lib.rs
-
-pub struct PngTarget {
-    image: ImageBuffer<Luma<u8>, Vec<u8>>,
-}
-
-impl PngTarget {
-    pub fn new(size: Size) -> Self {
-        Self {
-            image: ImageBuffer::new(size.width, size.height),
-        }
-    }
-
-    pub fn save<PATH: AsRef<Path>>(&self, path: PATH) -> image::ImageResult<()> {
-        let scale = 3;
-        let resized = resize(
-            &self.image,
-            self.image.width() * scale,
-            self.image.height() * scale,
-            image::imageops::FilterType::Nearest,
-        );
-        resized.save_with_format(path, image::ImageFormat::Png)
-    }
-
-    pub fn save_page<D, P: AsRef<Path>>(&mut self, drawable: &D, path: P)
-    where
-        D: Drawable<Color = BinaryColor>,
-    {
-        drawable.draw(self).unwrap();
-        self.save(path).unwrap();
-    }
-
-    pub fn save_image<P: AsRef<Path>>(&mut self, drawable: &ImageRaw<BinaryColor>, path: P) {
-        drawable.draw(self).unwrap();
-        self.save(path).unwrap();
-    }
-}
-
-impl DrawTarget for PngTarget {
-    type Color = BinaryColor;
-    type Error = std::convert::Infallible;
-
-    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
-    where
-        I: IntoIterator<Item = Pixel<Self::Color>>,
-    {
-        for Pixel(p, c) in pixels {
-            if let (Ok(x), Ok(y)) = (u32::try_from(p.x), u32::try_from(p.y)) {
-                if x < self.image.width() && y < self.image.height() {
-                    self.image
-                        .put_pixel(p.x as u32, p.y as u32, Luma([if c.is_on() { 255 } else { 0 }]));
-                }
-            }
-        }
-
-        Ok(())
-    }
+use energy_monitor::driver::virtual_display::PngTarget;
+use energy_monitor::metrics::{MetricsSnapshot, ProcessStats};
+use energy_monitor::settings::TariffPrices;
+use energy_monitor::shedding::{OutputReason, OutputState};
+
+fn save_page<D, P: AsRef<Path>>(display: &mut PngTarget, drawable: &D, path: P)
+where
+    D: Drawable<Color = BinaryColor>,
+{
+    drawable.draw(display).unwrap();
+    display.save(path).unwrap();
 }
 
-impl OriginDimensions for PngTarget {
-    fn size(&self) -> Size {
-        Size::new(self.image.width(), self.image.height())
-    }
+fn save_image<P: AsRef<Path>>(display: &mut PngTarget, drawable: &ImageRaw<BinaryColor>, path: P) {
+    drawable.draw(display).unwrap();
+    display.save(path).unwrap();
 }
 
 fn main() {
@@ -82,24 +39,193 @@ fn main() {
     page.rpict_status(true);
     page.linky_status(true);
     page.influxdb_status(true);
-    display.save_page(&page, Path::new("page-startup.png"));
+    save_page(&mut display, &page, Path::new("page-startup.png"));
 
     let mut page = RpictPage::new(8000.0);
     page.update(3076.0, 2229.0, 6403.0, 232.0, 232.0, 232.0);
     page.update(232.0, 1540.0, 5670.0, 232.0, 232.0, 232.0);
-    display.save_page(&page, Path::new("page-rpict.png"));
+    page.set_imbalance(Some(83.0));
+    save_page(&mut display, &page, Path::new("page-rpict.png"));
 
     let mut page = LinkyPage::new();
     page.update("005215329642".to_string(), 22_965_852, 7_431_234, TariffPeriod::HP);
-    display.save_page(&page, Path::new("page-linky.png"));
+    save_page(&mut display, &page, Path::new("page-linky.png"));
+
+    let mut page = ConsumptionPage::new();
+    page.update(ConsumptionSummary {
+        last_hour: TariffEnergy { hc_wh: 0, hp_wh: 1234 },
+        today: TariffEnergy {
+            hc_wh: 4200,
+            hp_wh: 8145,
+        },
+        yesterday: TariffEnergy {
+            hc_wh: 5100,
+            hp_wh: 9012,
+        },
+        this_month: TariffEnergy {
+            hc_wh: 98_765,
+            hp_wh: 131_402,
+        },
+        last_month: TariffEnergy {
+            hc_wh: 120_034,
+            hp_wh: 180_250,
+        },
+        average_power_w: Some(1234.0),
+        timestamp: Utc::now(),
+    });
+    save_page(&mut display, &page, Path::new("page-consumption.png"));
+
+    let mut page = CostPage::new();
+    page.update(CostSummary {
+        currency: "EUR".to_string(),
+        today: 3.27,
+        this_month: 45.81,
+        billing_period: 112.4,
+        billing_projection: 171.05,
+        billing_start: NaiveDate::from_ymd_opt(2023, 1, 15).unwrap(),
+        billing_end: NaiveDate::from_ymd_opt(2023, 3, 15).unwrap(),
+        prices: Some(TariffPrices {
+            from: NaiveDate::from_ymd_opt(2023, 2, 1).unwrap(),
+            subscription_per_month: 15.65,
+            hc_per_kwh: 0.1828,
+            hp_per_kwh: 0.246,
+            tempo: None,
+            ejp: None,
+        }),
+        tariff_period: TariffPeriod::HP,
+        tempo_color: None,
+        timestamp: Utc::now(),
+    });
+    save_page(&mut display, &page, Path::new("page-cost.png"));
+
+    let mut page = VoltagePage::new();
+    page.update(VoltageSummary {
+        start: Utc::now(),
+        lines: [
+            Some(PhaseVoltage {
+                min: 250.2,
+                avg: 257.6,
+                max: 259.9,
+                over: Excursions {
+                    count: 12,
+                    duration_secs: 410.0,
+                },
+                under: Excursions::default(),
+            }),
+            Some(PhaseVoltage {
+                min: 228.4,
+                avg: 236.1,
+                max: 254.3,
+                over: Excursions {
+                    count: 1,
+                    duration_secs: 5.0,
+                },
+                under: Excursions::default(),
+            }),
+            None,
+        ],
+        unbalance_percent: Some(4.3),
+        timestamp: Utc::now(),
+    });
+    save_page(&mut display, &page, Path::new("page-voltage.png"));
+
+    let mut page = PowerFactorPage::new();
+    page.update(PowerFactorSummary {
+        lines: [
+            LinePowerFactor {
+                real_power: 1843.0,
+                reactive_power: 612.4,
+                power_factor: Some(0.95),
+                direction: Direction::Import,
+                today_avg_power_factor: Some(0.91),
+            },
+            LinePowerFactor {
+                real_power: -2210.0,
+                reactive_power: 180.2,
+                power_factor: Some(0.99),
+                direction: Direction::Export,
+                today_avg_power_factor: Some(0.97),
+            },
+            LinePowerFactor {
+                real_power: 4.0,
+                reactive_power: 9.2,
+                power_factor: None,
+                direction: Direction::Import,
+                today_avg_power_factor: None,
+            },
+        ],
+        timestamp: Utc::now(),
+    });
+    save_page(&mut display, &page, Path::new("page-power-factor.png"));
+
+    let mut page = AlertPage::new();
+    page.update(Alert {
+        name: "near_overrun".to_string(),
+        severity: Severity::Warning,
+        state: AlertState::Firing,
+        message: "line 2 at 27.5 A, 92% of the 30 A subscribed".to_string(),
+        timestamp: Utc::now(),
+    });
+    page.update(Alert {
+        name: "overrun".to_string(),
+        severity: Severity::Critical,
+        state: AlertState::Firing,
+        message: "subscribed power exceeded, 32 A drawn".to_string(),
+        timestamp: Utc::now(),
+    });
+    save_page(&mut display, &page, Path::new("page-alert.png"));
+
+    let mut page = SheddingPage::new();
+    page.update(vec![
+        OutputState {
+            name: "water_heater".to_string(),
+            on: false,
+            reason: OutputReason::TempoRedDay,
+            timestamp: Utc::now(),
+        },
+        OutputState {
+            name: "heaters".to_string(),
+            on: true,
+            reason: OutputReason::Auto,
+            timestamp: Utc::now(),
+        },
+        OutputState {
+            name: "dryer".to_string(),
+            on: false,
+            reason: OutputReason::Shed,
+            timestamp: Utc::now(),
+        },
+    ]);
+    save_page(&mut display, &page, Path::new("page-shedding.png"));
+
+    let mut page = DiagnosticsPage::new();
+    page.update(MetricsSnapshot {
+        rpict_frames_decoded: 123_456,
+        rpict_frames_rejected: 12,
+        linky_frames_decoded: 45_678,
+        linky_frames_rejected: 3,
+        linky_checksum_errors: 7,
+        broadcast_lagged: 0,
+        influxdb_writes: 169_134,
+        influxdb_failures: 41,
+        influxdb_latency_ms: 18,
+        display_flushes: 98_765,
+        display_flush_ms: 9,
+        process: ProcessStats {
+            rss_kb: Some(5260),
+            cpu_percent: Some(1.7),
+        },
+        timestamp: Utc::now(),
+    });
+    save_page(&mut display, &page, Path::new("page-diagnostics.png"));
 
     // Save icons
     let mut display = PngTarget::new(Size::new(8, 8));
 
-    display.save_image(&RPICT_ON, Path::new("icon-rpict-on.png"));
-    display.save_image(&RPICT_OFF, Path::new("icon-rpict-off.png"));
-    display.save_image(&LINKY_ON, Path::new("icon-linky-on.png"));
-    display.save_image(&LINKY_OFF, Path::new("icon-linky-off.png"));
-    display.save_image(&INFLUXDB_ON, Path::new("icon-influxdb-on.png"));
-    display.save_image(&INFLUXDB_OFF, Path::new("icon-influxdb-off.png"));
+    save_image(&mut display, &RPICT_ON, Path::new("icon-rpict-on.png"));
+    save_image(&mut display, &RPICT_OFF, Path::new("icon-rpict-off.png"));
+    save_image(&mut display, &LINKY_ON, Path::new("icon-linky-on.png"));
+    save_image(&mut display, &LINKY_OFF, Path::new("icon-linky-off.png"));
+    save_image(&mut display, &INFLUXDB_ON, Path::new("icon-influxdb-on.png"));
+    save_image(&mut display, &INFLUXDB_OFF, Path::new("icon-influxdb-off.png"));
 }
@@ -0,0 +1,16 @@
+#![no_main]
+
+use bytes::BytesMut;
+use energy_monitor::driver::linky::LinkyCodec;
+use libfuzzer_sys::fuzz_target;
+use tokio_util::codec::Decoder;
+
+fuzz_target!(|data: &[u8]| {
+    let mut codec = LinkyCodec::default();
+    // feed data in two reads, as a frame can be split across serial reads
+    let (head, tail) = data.split_at(data.len() / 2);
+    let mut buffer = BytesMut::from(head);
+    while let Ok(Some(_)) = codec.decode(&mut buffer) {}
+    buffer.extend_from_slice(tail);
+    while let Ok(Some(_)) = codec.decode_eof(&mut buffer) {}
+});
@@ -1,6 +1,26 @@
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::TryRecvError;
+
+pub mod alert;
+pub mod analytics;
 pub mod button;
 pub mod datalogger;
 pub mod display;
 pub mod hmi;
 pub mod linky;
+pub mod metrics;
+pub mod notifier;
 pub mod rpict;
+pub mod shedding;
+pub mod systemd;
+
+/// Returns the next message still queued in a broadcast channel, skipping over lagged ones, to drain it on shutdown.
+pub(crate) fn next_queued<T: Clone>(rx: &mut broadcast::Receiver<T>) -> Option<T> {
+    loop {
+        match rx.try_recv() {
+            Ok(msg) => return Some(msg),
+            Err(TryRecvError::Lagged(_)) => continue,
+            Err(_) => return None,
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::time::{interval, MissedTickBehavior};
+
+use AlertMessage::*;
+
+use crate::actor::linky::{LinkyActorHandle, LinkyMessage};
+use crate::actor::next_queued;
+use crate::actor::rpict::{RpictActorHandle, RpictMessage};
+use crate::alert::imbalance::ImbalanceDetector;
+use crate::alert::overrun::OverrunDetector;
+use crate::alert::rule::RuleEngine;
+use crate::alert::{Alert, Severity};
+use crate::settings;
+
+#[derive(Clone, Debug)]
+pub enum AlertMessage {
+    /// An alert fired or resolved
+    NewAlert(Alert),
+}
+
+#[derive(Debug)]
+enum AlertCommand {
+    Shutdown(oneshot::Sender<()>),
+}
+
+/// Watches frames for conditions worth reacting to, announcing when they start and end.
+pub struct AlertActor {
+    rpict_rx: broadcast::Receiver<RpictMessage>,
+    linky_rx: broadcast::Receiver<LinkyMessage>,
+    rx: mpsc::Receiver<AlertCommand>,
+    tx: broadcast::Sender<AlertMessage>,
+    overrun: OverrunDetector,
+    imbalance: ImbalanceDetector,
+    rules: RuleEngine,
+}
+
+#[derive(Clone)]
+pub struct AlertActorHandle {
+    tx: broadcast::Sender<AlertMessage>,
+    command_tx: mpsc::Sender<AlertCommand>,
+}
+
+impl AlertActor {
+    /// Period of the rules evaluated without frames, eg. on the time since the last frame.
+    const TICK_PERIOD: Duration = Duration::from_secs(1);
+
+    fn publish(&self, alerts: Vec<Alert>) {
+        for alert in alerts {
+            match (alert.is_firing(), alert.severity) {
+                (true, Severity::Critical) => log::error!("Alert {} firing: {}", alert.name, alert.message),
+                (true, Severity::Warning) => log::warn!("Alert {} firing: {}", alert.name, alert.message),
+                (false, _) => log::info!("Alert {} resolved: {}", alert.name, alert.message),
+            }
+            self.tx.send(NewAlert(alert)).unwrap_or_default();
+        }
+    }
+
+    fn handle_rpict(&mut self, msg: RpictMessage) {
+        if let RpictMessage::NewFrame(frame) = msg {
+            let mut alerts = self.overrun.update_rpict(&frame);
+            alerts.extend(self.imbalance.update_rpict(&frame));
+            alerts.extend(self.rules.update_rpict(&frame));
+            self.publish(alerts);
+        }
+    }
+
+    fn handle_linky(&mut self, msg: LinkyMessage) {
+        if let LinkyMessage::NewFrame(frame) = msg {
+            let alerts = self.overrun.update_linky(&frame);
+            self.rules.update_linky(&frame);
+            self.publish(alerts);
+        }
+    }
+
+    async fn run(&mut self) {
+        let mut ticker = interval(Self::TICK_PERIOD);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                Ok(msg) = self.rpict_rx.recv() => self.handle_rpict(msg),
+                Ok(msg) = self.linky_rx.recv() => self.handle_linky(msg),
+                _ = ticker.tick() => {
+                    let alerts = self.rules.tick(Utc::now());
+                    self.publish(alerts);
+                },
+                Some(msg) = self.rx.recv() => match msg {
+                    AlertCommand::Shutdown(callback) => {
+                        log::debug!("Shutdown alert");
+                        while let Some(msg) = next_queued(&mut self.rpict_rx) {
+                            self.handle_rpict(msg);
+                        }
+                        while let Some(msg) = next_queued(&mut self.linky_rx) {
+                            self.handle_linky(msg);
+                        }
+                        callback.send(()).unwrap_or_default();
+                        break;
+                    },
+                },
+                else => break,
+            }
+        }
+    }
+
+    pub fn create(settings: &settings::Alerts, rpict: &RpictActorHandle, linky: &LinkyActorHandle) -> AlertActorHandle {
+        let (tx, _) = broadcast::channel(5);
+        let (command_tx, rx) = mpsc::channel(1);
+        let mut actor = AlertActor {
+            rpict_rx: rpict.subscribe(),
+            linky_rx: linky.subscribe(),
+            rx,
+            tx: tx.clone(),
+            overrun: OverrunDetector::new(settings),
+            imbalance: ImbalanceDetector::new(settings),
+            rules: RuleEngine::new(&settings.rules, Utc::now()),
+        };
+        tokio::task::spawn(async move { actor.run().await });
+        AlertActorHandle { tx, command_tx }
+    }
+}
+
+impl AlertActorHandle {
+    pub fn subscribe(&self) -> broadcast::Receiver<AlertMessage> {
+        self.tx.subscribe()
+    }
+
+    /// Checks the frames still queued then stops the actor.
+    pub async fn shutdown(&self) {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(AlertCommand::Shutdown(tx))
+            .await
+            .unwrap_or_default();
+        rx.await.unwrap_or_default()
+    }
+}
@@ -0,0 +1,255 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::time::{interval, MissedTickBehavior};
+
+use AnalyticsMessage::*;
+
+use crate::actor::linky::{LinkyActorHandle, LinkyMessage};
+use crate::actor::next_queued;
+use crate::actor::rpict::{RpictActorHandle, RpictMessage};
+use crate::analytics::consumption::ConsumptionSummary;
+use crate::analytics::cost::CostSummary;
+use crate::analytics::energy::Energy;
+use crate::analytics::imbalance::ImbalanceSummary;
+use crate::analytics::peak::Peaks;
+use crate::analytics::reactive::PowerFactorSummary;
+use crate::analytics::voltage::{VoltageQuality, VoltageSummary};
+use crate::driver::linky::{TariffPeriod, TempoColor};
+use crate::settings;
+use crate::state::{LinkyIndices, RuntimeState};
+
+#[derive(Clone, Debug)]
+pub enum AnalyticsMessage {
+    NewPeaks(Peaks),
+    /// Energy totals, sent periodically
+    NewEnergy(Energy),
+    /// Consumption derived from the Linky indices, sent periodically
+    NewConsumption(ConsumptionSummary),
+    /// Running electricity cost, sent periodically when a tariff is configured
+    NewCost(CostSummary),
+    /// Lines load share and current imbalance, sent periodically
+    NewImbalance(ImbalanceSummary),
+    /// Lines reactive power and power factor, sent periodically
+    NewPowerFactor(PowerFactorSummary),
+    /// Lines voltage quality, sent at the end of each 10 minutes window
+    NewVoltage(VoltageSummary),
+}
+
+#[derive(Debug)]
+enum AnalyticsCommand {
+    Shutdown(oneshot::Sender<()>),
+}
+
+/// Derives values from frames and keeps them across restarts in a state file.
+pub struct AnalyticsActor {
+    state_path: PathBuf,
+    save_interval: Duration,
+    tariff: Option<settings::Tariff>,
+    rpict_rx: broadcast::Receiver<RpictMessage>,
+    linky_rx: broadcast::Receiver<LinkyMessage>,
+    rx: mpsc::Receiver<AnalyticsCommand>,
+    tx: broadcast::Sender<AnalyticsMessage>,
+    // internal state
+    state: RuntimeState,
+    is_dirty: bool,
+    is_save_failing: bool,
+    tariff_period: TariffPeriod,
+    tempo_color: Option<TempoColor>,
+    voltage: VoltageQuality,
+}
+
+#[derive(Clone)]
+pub struct AnalyticsActorHandle {
+    tx: broadcast::Sender<AnalyticsMessage>,
+    command_tx: mpsc::Sender<AnalyticsCommand>,
+    /// State loaded at startup
+    restored: Arc<RuntimeState>,
+}
+
+impl AnalyticsActor {
+    const PUBLISH_PERIOD: Duration = Duration::from_secs(60);
+
+    fn handle_rpict(&mut self, msg: RpictMessage) {
+        if let RpictMessage::NewFrame(frame) = msg {
+            let values = [
+                frame.l1_apparent_power,
+                frame.l2_apparent_power,
+                frame.l3_apparent_power,
+            ];
+            if self.state.peaks.update(values, frame.timestamp) {
+                self.is_dirty = true;
+                self.tx.send(NewPeaks(self.state.peaks.clone())).unwrap_or_default();
+            }
+            let real_power = [frame.l1_real_power, frame.l2_real_power, frame.l3_real_power];
+            self.state.energy.update(real_power, frame.timestamp);
+            self.state.power_factor.update(real_power, values, frame.timestamp);
+            let irms = [frame.l1_irms, frame.l2_irms, frame.l3_irms];
+            self.state.imbalance.update(values, irms, frame.timestamp);
+            self.is_dirty = true;
+            let vrms = [frame.l1_vrms, frame.l2_vrms, frame.l3_vrms];
+            if let Some(summary) = self.voltage.update(vrms, frame.timestamp) {
+                self.tx.send(NewVoltage(summary)).unwrap_or_default();
+            }
+        }
+    }
+
+    fn publish(&self) {
+        if self.state.energy.timestamp().is_some() {
+            self.tx.send(NewEnergy(self.state.energy.clone())).unwrap_or_default();
+        }
+        let now = Utc::now();
+        let summary = self.state.imbalance.summary(now);
+        if let Some(summary) = self.state.power_factor.summary(now) {
+            self.tx.send(NewPowerFactor(summary)).unwrap_or_default();
+        }
+        // the month covers the day and the last frame, nothing to send without it
+        if summary.month.is_some() {
+            self.tx.send(NewImbalance(summary)).unwrap_or_default();
+        }
+        if let Some(summary) = self.state.consumption.summary(now) {
+            self.tx.send(NewConsumption(summary)).unwrap_or_default();
+        }
+        if let Some(tariff) = &self.tariff {
+            let summary = self
+                .state
+                .cost
+                .summary(tariff, self.tariff_period.clone(), self.tempo_color, now);
+            self.tx.send(NewCost(summary)).unwrap_or_default();
+        }
+    }
+
+    fn handle_linky(&mut self, msg: LinkyMessage) {
+        if let LinkyMessage::NewFrame(frame) = msg {
+            let energy = self
+                .state
+                .consumption
+                .update(&frame.adco, frame.hchc, frame.hchp, frame.timestamp);
+            if let Some(tariff) = &self.tariff {
+                self.state
+                    .cost
+                    .add(tariff, energy, frame.tempo, frame.is_ejp(), frame.timestamp);
+            }
+            self.tariff_period = frame.ptec();
+            self.tempo_color = frame.tempo_color();
+            self.state.linky = Some(LinkyIndices::from(&frame));
+            self.is_dirty = true;
+        }
+    }
+
+    fn save(&mut self) {
+        if !self.is_dirty {
+            return;
+        }
+        match self.state.save(&self.state_path) {
+            Ok(()) => {
+                if self.is_save_failing {
+                    log::info!("State saved to {} again", self.state_path.display());
+                } else {
+                    log::debug!("State saved to {}", self.state_path.display());
+                }
+                self.is_dirty = false;
+                self.is_save_failing = false;
+            }
+            // reported once, rather than on every save interval
+            Err(e) if self.is_save_failing => log::debug!("Cannot save state to {}: {e}", self.state_path.display()),
+            Err(e) => {
+                log::warn!("Cannot save state to {}: {e}", self.state_path.display());
+                self.is_save_failing = true;
+            }
+        }
+    }
+
+    async fn run(&mut self) {
+        let mut save_interval = interval(self.save_interval);
+        save_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut publish_interval = interval(Self::PUBLISH_PERIOD);
+        publish_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                Ok(msg) = self.rpict_rx.recv() => self.handle_rpict(msg),
+                Ok(msg) = self.linky_rx.recv() => self.handle_linky(msg),
+                _ = save_interval.tick() => self.save(),
+                _ = publish_interval.tick() => self.publish(),
+                Some(msg) = self.rx.recv() => match msg {
+                    AnalyticsCommand::Shutdown(callback) => {
+                        log::debug!("Shutdown analytics");
+                        while let Some(msg) = next_queued(&mut self.rpict_rx) {
+                            self.handle_rpict(msg);
+                        }
+                        while let Some(msg) = next_queued(&mut self.linky_rx) {
+                            self.handle_linky(msg);
+                        }
+                        // final totals, for the datalogger to drain
+                        self.publish();
+                        self.save();
+                        callback.send(()).unwrap_or_default();
+                        break;
+                    },
+                },
+                else => break,
+            }
+        }
+    }
+
+    pub fn create(
+        settings: &settings::State,
+        tariff: &Option<settings::Tariff>,
+        voltage_quality: &settings::VoltageQuality,
+        rpict: &RpictActorHandle,
+        linky: &LinkyActorHandle,
+    ) -> AnalyticsActorHandle {
+        let state = RuntimeState::load(&settings.path);
+        let restored = Arc::new(state.clone());
+        // a publication sends up to 5 messages in a row, on top of the peaks and voltage ones sent on frames
+        let (tx, _) = broadcast::channel(16);
+        let (command_tx, rx) = mpsc::channel(1);
+        let mut actor = AnalyticsActor {
+            state_path: settings.path.clone(),
+            save_interval: Duration::from_secs(settings.save_interval_secs),
+            tariff: tariff.clone(),
+            rpict_rx: rpict.subscribe(),
+            linky_rx: linky.subscribe(),
+            rx,
+            tx: tx.clone(),
+            state,
+            is_dirty: false,
+            is_save_failing: false,
+            tariff_period: TariffPeriod::Unknown,
+            tempo_color: None,
+            voltage: VoltageQuality::new(voltage_quality),
+        };
+        tokio::task::spawn(async move { actor.run().await });
+        AnalyticsActorHandle {
+            tx,
+            command_tx,
+            restored,
+        }
+    }
+}
+
+impl AnalyticsActorHandle {
+    pub fn subscribe(&self) -> broadcast::Receiver<AnalyticsMessage> {
+        self.tx.subscribe()
+    }
+
+    /// State loaded at startup, for subscribers to start from.
+    pub fn restored_state(&self) -> &RuntimeState {
+        &self.restored
+    }
+
+    /// Handles the frames still queued, publishes and saves the state then stops the actor.
+    pub async fn shutdown(&self) {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(AnalyticsCommand::Shutdown(tx))
+            .await
+            .unwrap_or_default();
+        rx.await.unwrap_or_default()
+    }
+}
@@ -15,17 +15,27 @@ pub enum ButtonMessage {
     Inactivity,
 }
 
+#[derive(Debug)]
+enum ButtonCommand {
+    SetTimings {
+        button_debounce_ms: u64,
+        sleep_timeout_secs: u64,
+    },
+}
+
 pub struct ButtonActor {
     button_debounce_ms: u64,
     sleep_timeout_secs: u64,
     pin: InputPin,
     pin_rx: mpsc::Receiver<Level>,
+    rx: mpsc::Receiver<ButtonCommand>,
     tx: broadcast::Sender<ButtonMessage>,
 }
 
 #[derive(Clone)]
 pub struct ButtonActorHandle {
     tx: broadcast::Sender<ButtonMessage>,
+    command_tx: mpsc::Sender<ButtonCommand>,
 }
 
 impl ButtonActor {
@@ -38,15 +48,27 @@ impl ButtonActor {
     }
 
     async fn run(&mut self) {
-        let mut timer = self.new_timer(self.sleep_timeout_secs * 2);
-        while let Some(_level) = self.pin_rx.recv().await {
-            timer.abort();
-            timer = self.new_timer(self.sleep_timeout_secs);
-            sleep(Duration::from_millis(self.button_debounce_ms)).await;
-            if self.pin.is_high() {
-                continue;
-            } // signal is debounced after this
-            self.tx.send(Press).unwrap_or_default();
+        let mut timer = self.new_timer(self.sleep_timeout_secs.saturating_mul(2));
+        loop {
+            tokio::select! {
+                Some(_level) = self.pin_rx.recv() => {
+                    timer.abort();
+                    timer = self.new_timer(self.sleep_timeout_secs);
+                    sleep(Duration::from_millis(self.button_debounce_ms)).await;
+                    if self.pin.is_high() {
+                        continue;
+                    } // signal is debounced after this
+                    self.tx.send(Press).unwrap_or_default();
+                },
+                Some(msg) = self.rx.recv() => match msg {
+                    ButtonCommand::SetTimings { button_debounce_ms, sleep_timeout_secs } => {
+                        log::debug!("Button debounce {button_debounce_ms}ms, sleep timeout {sleep_timeout_secs}s");
+                        self.button_debounce_ms = button_debounce_ms;
+                        self.sleep_timeout_secs = sleep_timeout_secs;
+                    },
+                },
+                else => break,
+            }
         }
     }
 
@@ -64,16 +86,17 @@ impl ButtonActor {
         })?;
         // listen to pin state changes
         let (tx, _) = broadcast::channel(1);
-        let tx2 = tx.clone();
+        let (command_tx, rx) = mpsc::channel(1);
         let mut actor = ButtonActor {
             button_debounce_ms,
             sleep_timeout_secs,
             pin,
             pin_rx,
-            tx,
+            rx,
+            tx: tx.clone(),
         };
         tokio::task::spawn(async move { actor.run().await });
-        Ok(ButtonActorHandle { tx: tx2 })
+        Ok(ButtonActorHandle { tx, command_tx })
     }
 }
 
@@ -81,4 +104,15 @@ impl ButtonActorHandle {
     pub fn subscribe(&self) -> broadcast::Receiver<ButtonMessage> {
         self.tx.subscribe()
     }
+
+    /// Applies new timings, the sleep timeout being used from the next button press.
+    pub async fn set_timings(&self, button_debounce_ms: u64, sleep_timeout_secs: u64) {
+        self.command_tx
+            .send(ButtonCommand::SetTimings {
+                button_debounce_ms,
+                sleep_timeout_secs,
+            })
+            .await
+            .unwrap_or_default();
+    }
 }
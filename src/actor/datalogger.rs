@@ -1,104 +1,254 @@
 use std::error::Error;
+use std::time::Instant;
 
 use tokio::sync::broadcast;
 use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
 
 use DataLoggerMessage::*;
 
+use crate::actor::alert::{AlertActorHandle, AlertMessage};
+use crate::actor::analytics::{AnalyticsActorHandle, AnalyticsMessage};
 use crate::actor::linky::{LinkyActorHandle, LinkyMessage};
+use crate::actor::metrics::{MetricsActorHandle, MetricsMessage};
+use crate::actor::next_queued;
 use crate::actor::rpict::{RpictActorHandle, RpictMessage};
+use crate::actor::shedding::{SheddingActorHandle, SheddingMessage};
+use crate::metrics;
+use crate::metrics::METRICS;
 
-use crate::service::influxdb::InfluxDBClient;
+use crate::service::influxdb::{InfluxDBClient, InfluxDbSerialize, LabelledRpictFrame};
 use crate::settings;
 
 #[derive(Clone, Debug)]
 pub enum DataLoggerMessage {
     InfluxDbConnected,
     InfluxDbDisconnected,
+    /// Target changed on reload, announced as connected on its first successful publish
+    InfluxDbTargetChanged,
+}
+
+#[derive(Debug)]
+enum DataLoggerCommand {
+    Reload(Option<settings::InfluxDB>, Box<[settings::Channel; 3]>),
+    Shutdown(oneshot::Sender<()>),
 }
 
 pub struct DataLoggerActor {
+    influxdb_settings: Option<settings::InfluxDB>,
+    channels: [settings::Channel; 3],
     influxdb: Option<InfluxDBClient>,
+    influxdb_connected: bool,
     rpict_rx: broadcast::Receiver<RpictMessage>,
     linky_rx: broadcast::Receiver<LinkyMessage>,
+    metrics_rx: broadcast::Receiver<MetricsMessage>,
+    analytics_rx: broadcast::Receiver<AnalyticsMessage>,
+    alert_rx: broadcast::Receiver<AlertMessage>,
+    shedding_rx: broadcast::Receiver<SheddingMessage>,
+    rx: mpsc::Receiver<DataLoggerCommand>,
     tx: broadcast::Sender<DataLoggerMessage>,
 }
 
 #[derive(Clone)]
 pub struct DataLoggerHandle {
     tx: broadcast::Sender<DataLoggerMessage>,
+    command_tx: mpsc::Sender<DataLoggerCommand>,
 }
 
 impl DataLoggerActor {
+    async fn publish(&mut self, payload: &(impl InfluxDbSerialize + ?Sized)) {
+        if let Some(client) = &self.influxdb {
+            let start = Instant::now();
+            let result = client.publish(payload).await;
+            metrics::set(&METRICS.influxdb_latency_ms, start.elapsed());
+            metrics::increment(&METRICS.influxdb_writes);
+            if result.is_ok() {
+                if !self.influxdb_connected {
+                    self.tx.send(InfluxDbConnected).unwrap_or_default();
+                    self.influxdb_connected = true;
+                }
+            } else {
+                metrics::increment(&METRICS.influxdb_failures);
+                if self.influxdb_connected {
+                    self.tx.send(InfluxDbDisconnected).unwrap_or_default();
+                    self.influxdb_connected = false;
+                }
+            }
+        }
+    }
+
+    async fn handle_rpict(&mut self, msg: RpictMessage) {
+        if let RpictMessage::NewFrame(frame) = msg {
+            log::trace!("New Rpict frame: {:?}", frame);
+            let channels = self.channels.clone();
+            self.publish(&LabelledRpictFrame {
+                frame: &frame,
+                channels: &channels,
+            })
+            .await;
+        }
+    }
+
+    async fn handle_linky(&mut self, msg: LinkyMessage) {
+        if let LinkyMessage::NewFrame(frame) = msg {
+            log::trace!("New Linky frame: {:?}", frame);
+            self.publish(&frame).await;
+        }
+    }
+
+    async fn handle_metrics(&mut self, msg: MetricsMessage) {
+        let MetricsMessage::NewSnapshot(snapshot) = msg;
+        self.publish(&snapshot).await;
+    }
+
+    async fn handle_analytics(&mut self, msg: AnalyticsMessage) {
+        match msg {
+            AnalyticsMessage::NewEnergy(energy) => self.publish(&energy).await,
+            AnalyticsMessage::NewConsumption(summary) => self.publish(&summary).await,
+            AnalyticsMessage::NewCost(summary) => self.publish(&summary).await,
+            AnalyticsMessage::NewVoltage(summary) => self.publish(&summary).await,
+            AnalyticsMessage::NewImbalance(summary) => self.publish(&summary).await,
+            AnalyticsMessage::NewPowerFactor(summary) => self.publish(&summary).await,
+            AnalyticsMessage::NewPeaks(_) => {}
+        }
+    }
+
+    async fn handle_alert(&mut self, msg: AlertMessage) {
+        let AlertMessage::NewAlert(alert) = msg;
+        self.publish(&alert).await;
+    }
+
+    async fn handle_shedding(&mut self, msg: SheddingMessage) {
+        let SheddingMessage::NewStates(states) = msg;
+        self.publish(states.as_slice()).await;
+    }
+
+    fn handle_reload(&mut self, influxdb_settings: Option<settings::InfluxDB>, channels: [settings::Channel; 3]) {
+        self.channels = channels;
+        if influxdb_settings == self.influxdb_settings {
+            return;
+        }
+        match influxdb_settings.as_ref().map(InfluxDBClient::new).transpose() {
+            Ok(influxdb) => {
+                log::info!("InfluxDB target reloaded");
+                self.influxdb_settings = influxdb_settings;
+                self.influxdb = influxdb;
+                // the new target is announced on its first successful publish
+                if self.influxdb_connected {
+                    self.tx.send(InfluxDbTargetChanged).unwrap_or_default();
+                    self.influxdb_connected = false;
+                }
+            }
+            Err(e) => log::error!("Cannot reload InfluxDB target, keeping the previous one: {e}"),
+        }
+    }
+
+    /// Publishes the messages still queued in the broadcast channels.
+    async fn drain(&mut self) {
+        while let Some(msg) = next_queued(&mut self.rpict_rx) {
+            self.handle_rpict(msg).await;
+        }
+        while let Some(msg) = next_queued(&mut self.linky_rx) {
+            self.handle_linky(msg).await;
+        }
+        while let Some(msg) = next_queued(&mut self.metrics_rx) {
+            self.handle_metrics(msg).await;
+        }
+        while let Some(msg) = next_queued(&mut self.analytics_rx) {
+            self.handle_analytics(msg).await;
+        }
+        while let Some(msg) = next_queued(&mut self.alert_rx) {
+            self.handle_alert(msg).await;
+        }
+        while let Some(msg) = next_queued(&mut self.shedding_rx) {
+            self.handle_shedding(msg).await;
+        }
+    }
+
     async fn run(&mut self) {
-        let mut influxdb_connected = false;
         loop {
             tokio::select! {
                 msg = self.rpict_rx.recv() => match msg {
-                    Ok(RpictMessage::NewFrame(frame)) => {
-                        log::trace!("New Rpict frame: {:?}", frame);
-                        if let Some(client) = &self.influxdb {
-                            if client.publish(&frame).await.is_ok() {
-                                if !influxdb_connected {
-                                    self.tx.send(InfluxDbConnected).unwrap_or_default();
-                                    influxdb_connected = true;
-                                }
-                            } else if influxdb_connected {
-                                self.tx.send(InfluxDbDisconnected).unwrap_or_default();
-                                influxdb_connected = false;
-                            }
-                        }
-                    },
+                    Ok(msg) => self.handle_rpict(msg).await,
                     Err(RecvError::Lagged(skipped)) => {
                         log::warn!("Lag while logging rpict data, skipped {:?} frames", skipped);
+                        metrics::add(&METRICS.broadcast_lagged, skipped);
                     },
                     _ => {}
                 },
                 msg = self.linky_rx.recv() => match msg {
-                    Ok(LinkyMessage::NewFrame(frame)) => {
-                        log::trace!("New Linky frame: {:?}", frame);
-                        if let Some(client) = &self.influxdb {
-                            if client.publish(&frame).await.is_ok() {
-                                if !influxdb_connected {
-                                    self.tx.send(InfluxDbConnected).unwrap_or_default();
-                                    influxdb_connected = true;
-                                }
-                            } else if influxdb_connected {
-                                self.tx.send(InfluxDbDisconnected).unwrap_or_default();
-                                influxdb_connected = false;
-                            }
-                        }
-                    },
+                    Ok(msg) => self.handle_linky(msg).await,
                     Err(RecvError::Lagged(skipped)) => {
                         log::warn!("Lag while logging linky data, skipped {:?} frames", skipped);
+                        metrics::add(&METRICS.broadcast_lagged, skipped);
                     },
                     _ => {}
                 },
+                Ok(msg) = self.metrics_rx.recv() => self.handle_metrics(msg).await,
+                msg = self.analytics_rx.recv() => match msg {
+                    Ok(msg) => self.handle_analytics(msg).await,
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("Lag while logging analytics, skipped {:?} messages", skipped);
+                        metrics::add(&METRICS.broadcast_lagged, skipped);
+                    },
+                    _ => {}
+                },
+                Ok(msg) = self.alert_rx.recv() => self.handle_alert(msg).await,
+                Ok(msg) = self.shedding_rx.recv() => self.handle_shedding(msg).await,
+                Some(msg) = self.rx.recv() => match msg {
+                    DataLoggerCommand::Reload(influxdb_settings, channels) => {
+                        self.handle_reload(influxdb_settings, *channels)
+                    },
+                    DataLoggerCommand::Shutdown(callback) => {
+                        log::debug!("Shutdown datalogger");
+                        self.drain().await;
+                        callback.send(()).unwrap_or_default();
+                        break;
+                    },
+                },
                 else => break,
             }
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn create(
         influxdb_settings: &Option<settings::InfluxDB>,
+        channels: &[settings::Channel; 3],
         rpict: &RpictActorHandle,
         linky: &LinkyActorHandle,
+        metrics: &MetricsActorHandle,
+        analytics: &AnalyticsActorHandle,
+        alert: &AlertActorHandle,
+        shedding: &SheddingActorHandle,
     ) -> Result<DataLoggerHandle, Box<dyn Error>> {
-        let influxdb = influxdb_settings
-            .clone()
-            .map(|settings| InfluxDBClient::new(&settings).unwrap());
+        let influxdb = influxdb_settings.as_ref().map(InfluxDBClient::new).transpose()?;
         let rpict_rx = rpict.subscribe();
         let linky_rx = linky.subscribe();
+        let metrics_rx = metrics.subscribe();
+        let analytics_rx = analytics.subscribe();
+        let alert_rx = alert.subscribe();
+        let shedding_rx = shedding.subscribe();
         // fork
         let (tx, _) = broadcast::channel(1);
+        let (command_tx, rx) = mpsc::channel(1);
         let mut actor = DataLoggerActor {
+            influxdb_settings: influxdb_settings.clone(),
+            channels: channels.clone(),
             influxdb,
+            influxdb_connected: false,
             rpict_rx,
             linky_rx,
+            metrics_rx,
+            analytics_rx,
+            alert_rx,
+            shedding_rx,
+            rx,
             tx: tx.clone(),
         };
         tokio::task::spawn(async move { actor.run().await });
-        Ok(DataLoggerHandle { tx })
+        Ok(DataLoggerHandle { tx, command_tx })
     }
 }
 
@@ -106,4 +256,25 @@ impl DataLoggerHandle {
     pub fn subscribe(&self) -> broadcast::Receiver<DataLoggerMessage> {
         self.tx.subscribe()
     }
+
+    /// Switches to another InfluxDB target, or stops publishing if none is given, and relabels channels.
+    pub async fn reload(&self, influxdb_settings: &Option<settings::InfluxDB>, channels: &[settings::Channel; 3]) {
+        self.command_tx
+            .send(DataLoggerCommand::Reload(
+                influxdb_settings.clone(),
+                Box::new(channels.clone()),
+            ))
+            .await
+            .unwrap_or_default();
+    }
+
+    /// Publishes pending frames then stops the actor.
+    pub async fn shutdown(&self) {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(DataLoggerCommand::Shutdown(tx))
+            .await
+            .unwrap_or_default();
+        rx.await.unwrap_or_default()
+    }
 }
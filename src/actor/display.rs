@@ -1,4 +1,4 @@
-use std::error::Error;
+use std::time::Instant;
 
 use embedded_graphics::pixelcolor::BinaryColor;
 use embedded_graphics::Drawable;
@@ -7,8 +7,14 @@ use tokio::sync::oneshot;
 
 use DisplayMessage::*;
 
-use crate::display::pages::{LinkyPage, Page, RpictPage, StartupPage};
-use crate::driver::ssd1305::Ssd1305;
+use crate::display::pages::{
+    AlertPage, ConsumptionPage, CostPage, DiagnosticsPage, LinkyPage, Page, PowerFactorPage, RpictPage, SheddingPage,
+    StartupPage, VoltagePage,
+};
+use crate::driver::display::DisplayDriver;
+use crate::driver::error::CommError;
+use crate::metrics;
+use crate::metrics::METRICS;
 
 #[derive(Debug)]
 pub enum DisplayMessage {
@@ -17,12 +23,19 @@ pub enum DisplayMessage {
     DisplayStartupPage { page: StartupPage, replace: bool },
     DisplayRpictPage { page: RpictPage, replace: bool },
     DisplayLinkyPage { page: LinkyPage, replace: bool },
+    DisplayConsumptionPage { page: ConsumptionPage, replace: bool },
+    DisplayCostPage { page: CostPage, replace: bool },
+    DisplayVoltagePage { page: VoltagePage, replace: bool },
+    DisplayPowerFactorPage { page: PowerFactorPage, replace: bool },
+    DisplayAlertPage { page: AlertPage, replace: bool },
+    DisplaySheddingPage { page: SheddingPage, replace: bool },
+    DisplayDiagnosticsPage { page: DiagnosticsPage, replace: bool },
     Shutdown(oneshot::Sender<()>),
 }
 
-pub struct DisplayActor {
+pub struct DisplayActor<D: DisplayDriver> {
     rx: mpsc::Receiver<DisplayMessage>,
-    driver: Ssd1305,
+    driver: D,
     current_page: Page,
 }
 
@@ -31,30 +44,35 @@ pub struct DisplayActorHandle {
     tx: mpsc::Sender<DisplayMessage>,
 }
 
-impl DisplayActor {
-    pub fn create() -> Result<DisplayActorHandle, Box<dyn Error>> {
+impl<D: DisplayDriver + Send + 'static> DisplayActor<D> {
+    pub fn create(driver: D) -> DisplayActorHandle {
         let (tx, rx) = mpsc::channel(1);
-        let driver = Ssd1305::new()?;
         let mut actor = DisplayActor {
             rx,
             driver,
             current_page: Page::Startup,
         };
         tokio::task::spawn_blocking(move || actor.run());
-        Ok(DisplayActorHandle { tx })
+        DisplayActorHandle { tx }
+    }
+
+    fn log_error(result: Result<(), CommError>) {
+        if let Err(e) = result {
+            log::error!("Display error: {e}");
+        }
     }
 
     fn run(&mut self) {
-        self.driver.begin().unwrap();
+        Self::log_error(self.driver.begin());
         while let Some(msg) = self.rx.blocking_recv() {
             match msg {
                 SetDisplayOn => {
                     log::debug!("Display on");
-                    self.driver.display_on().unwrap();
+                    Self::log_error(self.driver.display_on());
                 }
                 SetDisplayOff => {
                     log::debug!("Display off");
-                    self.driver.display_off().unwrap();
+                    Self::log_error(self.driver.display_off());
                 }
                 DisplayStartupPage { page, replace } => {
                     self.update_display(Page::Startup, page, replace);
@@ -65,10 +83,31 @@ impl DisplayActor {
                 DisplayLinkyPage { page, replace } => {
                     self.update_display(Page::Linky, page, replace);
                 }
+                DisplayConsumptionPage { page, replace } => {
+                    self.update_display(Page::Consumption, page, replace);
+                }
+                DisplayCostPage { page, replace } => {
+                    self.update_display(Page::Cost, page, replace);
+                }
+                DisplayVoltagePage { page, replace } => {
+                    self.update_display(Page::Voltage, page, replace);
+                }
+                DisplayPowerFactorPage { page, replace } => {
+                    self.update_display(Page::PowerFactor, page, replace);
+                }
+                DisplayAlertPage { page, replace } => {
+                    self.update_display(Page::Alert, page, replace);
+                }
+                DisplaySheddingPage { page, replace } => {
+                    self.update_display(Page::Shedding, page, replace);
+                }
+                DisplayDiagnosticsPage { page, replace } => {
+                    self.update_display(Page::Diagnostics, page, replace);
+                }
                 Shutdown(callback) => {
                     log::debug!("Shutdown display");
-                    self.driver.display_off().unwrap();
-                    self.driver.clear().unwrap();
+                    Self::log_error(self.driver.display_off());
+                    Self::log_error(self.driver.clear_display());
                     callback.send(()).unwrap_or_default();
                     return;
                 }
@@ -82,8 +121,12 @@ impl DisplayActor {
         }
         if self.current_page == page {
             log::debug!("Update display with {:?} page", self.current_page);
+            // drawing only updates the driver buffer, it can't fail
             drawable.draw(&mut self.driver).unwrap();
-            self.driver.flush().unwrap();
+            let start = Instant::now();
+            Self::log_error(self.driver.flush());
+            metrics::set(&METRICS.display_flush_ms, start.elapsed());
+            metrics::increment(&METRICS.display_flushes);
         }
     }
 }
@@ -123,6 +166,62 @@ impl DisplayActorHandle {
         self.tx.send(message).await.unwrap_or_default();
     }
 
+    pub async fn display_consumption_page(&self, page: &ConsumptionPage, replace: bool) {
+        let message = DisplayConsumptionPage {
+            page: page.clone(),
+            replace,
+        };
+        self.tx.send(message).await.unwrap_or_default();
+    }
+
+    pub async fn display_cost_page(&self, page: &CostPage, replace: bool) {
+        let message = DisplayCostPage {
+            page: page.clone(),
+            replace,
+        };
+        self.tx.send(message).await.unwrap_or_default();
+    }
+
+    pub async fn display_voltage_page(&self, page: &VoltagePage, replace: bool) {
+        let message = DisplayVoltagePage {
+            page: page.clone(),
+            replace,
+        };
+        self.tx.send(message).await.unwrap_or_default();
+    }
+
+    pub async fn display_power_factor_page(&self, page: &PowerFactorPage, replace: bool) {
+        let message = DisplayPowerFactorPage {
+            page: page.clone(),
+            replace,
+        };
+        self.tx.send(message).await.unwrap_or_default();
+    }
+
+    pub async fn display_alert_page(&self, page: &AlertPage, replace: bool) {
+        let message = DisplayAlertPage {
+            page: page.clone(),
+            replace,
+        };
+        self.tx.send(message).await.unwrap_or_default();
+    }
+
+    pub async fn display_shedding_page(&self, page: &SheddingPage, replace: bool) {
+        let message = DisplaySheddingPage {
+            page: page.clone(),
+            replace,
+        };
+        self.tx.send(message).await.unwrap_or_default();
+    }
+
+    pub async fn display_diagnostics_page(&self, page: &DiagnosticsPage, replace: bool) {
+        let message = DisplayDiagnosticsPage {
+            page: page.clone(),
+            replace,
+        };
+        self.tx.send(message).await.unwrap_or_default();
+    }
+
     pub async fn shutdown(&self) {
         let (tx, rx) = oneshot::channel();
         self.tx.send(Shutdown(tx)).await.unwrap_or_default();
@@ -1,25 +1,44 @@
 use std::error::Error;
 use std::iter::{Cycle, Skip};
+use std::time::Duration;
 use std::vec::IntoIter;
 
 use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
+use tokio::time::{sleep_until, Instant};
 
 use HmiMessage::*;
 
-use crate::actor::button::{ButtonActor, ButtonMessage};
+use crate::actor::alert::{AlertActorHandle, AlertMessage};
+use crate::actor::analytics::{AnalyticsActorHandle, AnalyticsMessage};
+use crate::actor::button::{ButtonActor, ButtonActorHandle, ButtonMessage};
 use crate::actor::datalogger::{DataLoggerHandle, DataLoggerMessage};
 use crate::actor::display::{DisplayActor, DisplayActorHandle};
 use crate::actor::linky::{LinkyActorHandle, LinkyMessage};
+use crate::actor::metrics::{MetricsActorHandle, MetricsMessage};
 use crate::actor::rpict::{RpictActorHandle, RpictMessage};
-use crate::display::pages::{LinkyPage, Page, RpictPage, StartupPage};
+use crate::actor::shedding::{SheddingActorHandle, SheddingMessage};
+use crate::analytics::imbalance::current_imbalance_percent;
+use crate::analytics::peak::{PeakPeriod, Peaks};
+use crate::display::pages::{
+    AlertPage, ConsumptionPage, CostPage, DiagnosticsPage, LinkyPage, Page, PowerFactorPage, RpictPage, SheddingPage,
+    StartupPage, VoltagePage,
+};
+use crate::driver::linky::TariffPeriod;
+use crate::driver::ssd1305::Ssd1305;
+use crate::driver::virtual_display::{VirtualDisplay, VirtualOutput};
+use crate::metrics;
+use crate::metrics::METRICS;
 use crate::settings;
+use crate::settings::DisplayBackend;
 
 type Carrousel = Skip<Cycle<IntoIter<Page>>>;
 
 #[derive(Debug)]
 enum HmiMessage {
+    Reload(settings::Hmi, Box<[settings::Channel; 3]>),
     Shutdown(oneshot::Sender<()>),
 }
 
@@ -29,14 +48,32 @@ pub struct HmiActor {
     linky_rx: broadcast::Receiver<LinkyMessage>,
     button_rx: broadcast::Receiver<ButtonMessage>,
     datalogger_rx: broadcast::Receiver<DataLoggerMessage>,
+    metrics_rx: broadcast::Receiver<MetricsMessage>,
+    analytics_rx: broadcast::Receiver<AnalyticsMessage>,
+    alert_rx: broadcast::Receiver<AlertMessage>,
+    shedding_rx: broadcast::Receiver<SheddingMessage>,
     rx: mpsc::Receiver<HmiMessage>,
     display: DisplayActorHandle,
+    button: Option<ButtonActorHandle>,
     // internal state
     startup_page: StartupPage,
     rpict_page: RpictPage,
     linky_page: LinkyPage,
+    consumption_page: ConsumptionPage,
+    cost_page: CostPage,
+    voltage_page: VoltagePage,
+    power_factor_page: PowerFactorPage,
+    alert_page: AlertPage,
+    shedding_page: SheddingPage,
+    diagnostics_page: DiagnosticsPage,
     carousel: Carrousel,
+    /// Page the user cycled to, shown again once an alert flash ends
+    current_page: Page,
     is_display_active: bool,
+    /// When to leave the alert page, and blank the display again if the alert woke it up
+    alert_flash_end: Option<Instant>,
+    peaks: Peaks,
+    peak_period: PeakPeriod,
 }
 
 #[derive(Clone)]
@@ -45,6 +82,9 @@ pub struct HmiActorHandle {
 }
 
 impl HmiActor {
+    /// How long an alert is shown when it wakes the display up.
+    const ALERT_FLASH_DURATION: Duration = Duration::from_secs(10);
+
     async fn handle_rpict(&mut self, msg: RpictMessage) {
         match msg {
             RpictMessage::Connected => {
@@ -67,6 +107,8 @@ impl HmiActor {
                     frame.l2_vrms,
                     frame.l3_vrms,
                 );
+                self.rpict_page
+                    .set_imbalance(current_imbalance_percent([frame.l1_irms, frame.l2_irms, frame.l3_irms]));
                 self.display.display_rpict_page(&self.rpict_page, false).await;
             }
         }
@@ -105,6 +147,103 @@ impl HmiActor {
                 self.startup_page.influxdb_status(false);
                 self.display.display_startup_page(&self.startup_page, false).await;
             }
+            DataLoggerMessage::InfluxDbTargetChanged => {
+                self.startup_page.influxdb_status(false);
+                self.display.display_startup_page(&self.startup_page, false).await;
+            }
+        }
+    }
+
+    async fn handle_metrics(&mut self, msg: MetricsMessage) {
+        let MetricsMessage::NewSnapshot(snapshot) = msg;
+        self.diagnostics_page.update(snapshot);
+        self.display
+            .display_diagnostics_page(&self.diagnostics_page, false)
+            .await;
+    }
+
+    async fn handle_analytics(&mut self, msg: AnalyticsMessage) {
+        match msg {
+            AnalyticsMessage::NewPeaks(peaks) => {
+                self.peaks = peaks;
+                self.rpict_page.set_peaks(self.peaks.get(self.peak_period).values());
+            }
+            AnalyticsMessage::NewEnergy(_) | AnalyticsMessage::NewImbalance(_) => {}
+            AnalyticsMessage::NewConsumption(summary) => {
+                self.consumption_page.update(summary);
+                self.display
+                    .display_consumption_page(&self.consumption_page, false)
+                    .await;
+            }
+            AnalyticsMessage::NewCost(summary) => {
+                self.cost_page.update(summary);
+                self.display.display_cost_page(&self.cost_page, false).await;
+            }
+            AnalyticsMessage::NewVoltage(summary) => {
+                self.voltage_page.update(summary);
+                self.display.display_voltage_page(&self.voltage_page, false).await;
+            }
+            AnalyticsMessage::NewPowerFactor(summary) => {
+                self.power_factor_page.update(summary);
+                self.display
+                    .display_power_factor_page(&self.power_factor_page, false)
+                    .await;
+            }
+        }
+    }
+
+    async fn handle_alert(&mut self, msg: AlertMessage) {
+        let AlertMessage::NewAlert(alert) = msg;
+        let is_firing = alert.is_firing();
+        self.alert_page.update(alert);
+        self.display.display_alert_page(&self.alert_page, is_firing).await;
+        if is_firing {
+            if !self.is_display_active {
+                self.display.set_display_on().await;
+            }
+            self.alert_flash_end = Some(Instant::now() + Self::ALERT_FLASH_DURATION);
+        }
+    }
+
+    async fn handle_shedding(&mut self, msg: SheddingMessage) {
+        let SheddingMessage::NewStates(states) = msg;
+        self.shedding_page.update(states);
+        self.display.display_shedding_page(&self.shedding_page, false).await;
+    }
+
+    /// Goes back to the page the user was on, blanking the display again if the alert woke it up.
+    async fn end_alert_flash(&mut self) {
+        self.alert_flash_end = None;
+        if !self.is_display_active {
+            self.display.set_display_off().await;
+        }
+        self.display_page(self.current_page).await;
+    }
+
+    async fn display_page(&self, page: Page) {
+        match page {
+            Page::Startup => self.display.display_startup_page(&self.startup_page, true).await,
+            Page::Rpict => self.display.display_rpict_page(&self.rpict_page, true).await,
+            Page::Linky => self.display.display_linky_page(&self.linky_page, true).await,
+            Page::Consumption => {
+                self.display
+                    .display_consumption_page(&self.consumption_page, true)
+                    .await
+            }
+            Page::Cost => self.display.display_cost_page(&self.cost_page, true).await,
+            Page::Voltage => self.display.display_voltage_page(&self.voltage_page, true).await,
+            Page::PowerFactor => {
+                self.display
+                    .display_power_factor_page(&self.power_factor_page, true)
+                    .await
+            }
+            Page::Alert => self.display.display_alert_page(&self.alert_page, true).await,
+            Page::Shedding => self.display.display_shedding_page(&self.shedding_page, true).await,
+            Page::Diagnostics => {
+                self.display
+                    .display_diagnostics_page(&self.diagnostics_page, true)
+                    .await
+            }
         }
     }
 
@@ -113,13 +252,13 @@ impl HmiActor {
             ButtonMessage::Press => {
                 log::debug!("Button press");
                 if self.is_display_active {
-                    match self.carousel.next().unwrap() {
-                        Page::Startup => self.display.display_startup_page(&self.startup_page, true).await,
-                        Page::Rpict => self.display.display_rpict_page(&self.rpict_page, true).await,
-                        Page::Linky => self.display.display_linky_page(&self.linky_page, true).await,
-                    }
+                    self.current_page = self.carousel.next().unwrap();
+                    // the user moved on, the alert page isn't flashing anymore
+                    self.alert_flash_end = None;
+                    self.display_page(self.current_page).await;
                 } else {
                     self.is_display_active = true;
+                    self.alert_flash_end = None;
                     self.display.set_display_on().await;
                 }
             }
@@ -131,16 +270,45 @@ impl HmiActor {
         }
     }
 
+    async fn handle_reload(&mut self, settings: settings::Hmi, channels: [settings::Channel; 3]) {
+        log::debug!("Reload hmi settings");
+        set_channels(&mut self.rpict_page, &settings, channels);
+        self.peak_period = settings.peak_period;
+        self.rpict_page.set_peaks(self.peaks.get(self.peak_period).values());
+        self.display.display_rpict_page(&self.rpict_page, false).await;
+        if let Some(button) = &self.button {
+            button
+                .set_timings(settings.button_debounce_ms, settings.sleep_timeout_secs)
+                .await;
+        }
+    }
+
     async fn run(&mut self) {
         self.display.display_startup_page(&self.startup_page, true).await;
         self.display.set_display_on().await;
         loop {
+            let alert_flash_end = self.alert_flash_end;
             tokio::select! {
                 Ok(msg) = self.rpict_rx.recv() => self.handle_rpict(msg).await,
                 Ok(msg) = self.linky_rx.recv() => self.handle_linky(msg).await,
                 Ok(msg) = self.datalogger_rx.recv() => self.handle_datalogger(msg).await,
                 Ok(msg) = self.button_rx.recv() => self.handle_button(msg).await,
+                Ok(msg) = self.metrics_rx.recv() => self.handle_metrics(msg).await,
+                msg = self.analytics_rx.recv() => match msg {
+                    Ok(msg) => self.handle_analytics(msg).await,
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("Lag while displaying analytics, skipped {:?} messages", skipped);
+                        metrics::add(&METRICS.broadcast_lagged, skipped);
+                    },
+                    _ => {}
+                },
+                Ok(msg) = self.alert_rx.recv() => self.handle_alert(msg).await,
+                Ok(msg) = self.shedding_rx.recv() => self.handle_shedding(msg).await,
+                _ = sleep_until(alert_flash_end.unwrap_or_else(Instant::now)), if alert_flash_end.is_some() => {
+                    self.end_alert_flash().await
+                },
                 Some(msg) = self.rx.recv() => match msg {
+                    HmiMessage::Reload(settings, channels) => self.handle_reload(settings, *channels).await,
                     HmiMessage::Shutdown(callback) => {
                         log::debug!("Shutdown hmi");
                         self.display.shutdown().await;
@@ -153,32 +321,84 @@ impl HmiActor {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn create(
         settings: &settings::Hmi,
+        channels: &[settings::Channel; 3],
         rpict: &RpictActorHandle,
         linky: &LinkyActorHandle,
         datalogger: &DataLoggerHandle,
+        metrics: &MetricsActorHandle,
+        analytics: &AnalyticsActorHandle,
+        alert: &AlertActorHandle,
+        shedding: &SheddingActorHandle,
     ) -> Result<HmiActorHandle, Box<dyn Error>> {
         let settings = settings.clone();
         let rpict_rx = rpict.subscribe();
         let linky_rx = linky.subscribe();
         let datalogger_rx = datalogger.subscribe();
-        // child actors
-        let button_rx = ButtonActor::create(
+        let metrics_rx = metrics.subscribe();
+        let analytics_rx = analytics.subscribe();
+        let alert_rx = alert.subscribe();
+        let shedding_rx = shedding.subscribe();
+        // child actors, display first so that nothing is left running if it is unavailable
+        let display = match &settings.display_backend {
+            DisplayBackend::Ssd1305 => DisplayActor::create(Ssd1305::new()?),
+            DisplayBackend::Png => DisplayActor::create(VirtualDisplay::new(VirtualOutput::Png(
+                settings.display_png_path.clone(),
+            ))),
+            DisplayBackend::Terminal => DisplayActor::create(VirtualDisplay::new(VirtualOutput::Terminal)),
+        };
+        let button = match ButtonActor::create(
             settings.button_bcm_pin,
             settings.button_debounce_ms,
             settings.sleep_timeout_secs,
-        )?
-        .subscribe();
-        let display = DisplayActor::create()?;
+        ) {
+            Ok(button) => Some(button),
+            // virtual displays are meant for development hosts, which usually have no GPIO
+            Err(e) if settings.display_backend != DisplayBackend::Ssd1305 => {
+                log::warn!("Cannot start push button, pages won't cycle: {e}");
+                None
+            }
+            Err(e) => return Err(e),
+        };
+        let button_rx = button
+            .as_ref()
+            .map_or_else(|| broadcast::channel(1).1, ButtonActorHandle::subscribe);
         // pages declaration
         let startup_page = StartupPage::new(env!("CARGO_PKG_VERSION"));
-        let rpict_page = RpictPage::new(settings.max_line_power_watts);
-        let linky_page = LinkyPage::new();
-        let carousel: Carrousel = vec![Page::Startup, Page::Rpict, Page::Linky]
-            .into_iter()
-            .cycle()
-            .skip(1);
+        let mut rpict_page = RpictPage::new(settings.max_line_power_watts);
+        set_channels(&mut rpict_page, &settings, channels.clone());
+        let mut linky_page = LinkyPage::new();
+        // start from the state restored by the analytics actor
+        let restored = analytics.restored_state();
+        if let Some(linky) = &restored.linky {
+            linky_page.update(linky.adco.clone(), linky.hchp, linky.hchc, TariffPeriod::Unknown);
+        }
+        let peaks = restored.peaks.clone();
+        rpict_page.set_peaks(peaks.get(settings.peak_period).values());
+        let consumption_page = ConsumptionPage::new();
+        let cost_page = CostPage::new();
+        let voltage_page = VoltagePage::new();
+        let power_factor_page = PowerFactorPage::new();
+        let alert_page = AlertPage::new();
+        let shedding_page = SheddingPage::new();
+        let diagnostics_page = DiagnosticsPage::new();
+        let carousel: Carrousel = vec![
+            Page::Startup,
+            Page::Rpict,
+            Page::Linky,
+            Page::Consumption,
+            Page::Cost,
+            Page::Voltage,
+            Page::PowerFactor,
+            Page::Alert,
+            Page::Shedding,
+            Page::Diagnostics,
+        ]
+        .into_iter()
+        .cycle()
+        .skip(1);
         // fork
         let (tx, rx) = mpsc::channel(1);
         let mut actor = HmiActor {
@@ -186,20 +406,50 @@ impl HmiActor {
             linky_rx,
             button_rx,
             datalogger_rx,
+            metrics_rx,
+            analytics_rx,
+            alert_rx,
+            shedding_rx,
             rx,
             display,
+            button,
             startup_page,
             rpict_page,
             linky_page,
+            consumption_page,
+            cost_page,
+            voltage_page,
+            power_factor_page,
+            alert_page,
+            shedding_page,
+            diagnostics_page,
             carousel,
+            current_page: Page::Startup,
             is_display_active: true,
+            alert_flash_end: None,
+            peaks,
+            peak_period: settings.peak_period,
         };
         tokio::task::spawn(async move { actor.run().await });
         Ok(HmiActorHandle { tx })
     }
 }
 
+/// Labels the gauges and scales them to each channel max power.
+fn set_channels(page: &mut RpictPage, settings: &settings::Hmi, channels: [settings::Channel; 3]) {
+    page.set_max_powers(channels.each_ref().map(|channel| channel.max_power_watts(settings)));
+    page.set_labels(channels.map(|channel| channel.label));
+}
+
 impl HmiActorHandle {
+    /// Applies the settings that don't need a restart: channels, power scale and button timings.
+    pub async fn reload(&self, settings: &settings::Hmi, channels: &[settings::Channel; 3]) {
+        self.tx
+            .send(Reload(settings.clone(), Box::new(channels.clone())))
+            .await
+            .unwrap_or_default();
+    }
+
     pub async fn shutdown(&self) {
         let (tx, rx) = oneshot::channel();
         self.tx.send(Shutdown(tx)).await.unwrap_or_default();
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::sync::broadcast;
+use tokio::time::{interval, Instant};
+
+use MetricsMessage::*;
+
+use crate::metrics::{MetricsSnapshot, ProcessSampler, METRICS};
+
+#[derive(Clone, Debug)]
+pub enum MetricsMessage {
+    NewSnapshot(MetricsSnapshot),
+}
+
+/// Periodically publishes a snapshot of the pipeline metrics.
+pub struct MetricsActor {
+    sampler: ProcessSampler,
+    tx: broadcast::Sender<MetricsMessage>,
+}
+
+#[derive(Clone)]
+pub struct MetricsActorHandle {
+    tx: broadcast::Sender<MetricsMessage>,
+}
+
+impl MetricsActor {
+    const PERIOD: Duration = Duration::from_secs(10);
+
+    async fn run(&mut self) {
+        let mut ticker = interval(Self::PERIOD);
+        let mut last_tick = Instant::now();
+        loop {
+            ticker.tick().await;
+            let process = self.sampler.sample(last_tick.elapsed());
+            last_tick = Instant::now();
+            let snapshot = METRICS.snapshot(process, Utc::now());
+            log::trace!("New metrics snapshot: {:?}", snapshot);
+            self.tx.send(NewSnapshot(snapshot)).unwrap_or_default();
+        }
+    }
+
+    pub fn create() -> MetricsActorHandle {
+        let (tx, _) = broadcast::channel(1);
+        let mut actor = MetricsActor {
+            sampler: ProcessSampler::default(),
+            tx: tx.clone(),
+        };
+        tokio::task::spawn(async move { actor.run().await });
+        MetricsActorHandle { tx }
+    }
+}
+
+impl MetricsActorHandle {
+    pub fn subscribe(&self) -> broadcast::Receiver<MetricsMessage> {
+        self.tx.subscribe()
+    }
+}
@@ -0,0 +1,234 @@
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use tokio::sync::broadcast::error::TryRecvError;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+use crate::actor::alert::{AlertActorHandle, AlertMessage};
+use crate::actor::datalogger::{DataLoggerHandle, DataLoggerMessage};
+use crate::actor::linky::{LinkyActorHandle, LinkyMessage};
+use crate::actor::rpict::{RpictActorHandle, RpictMessage};
+use crate::alert::connection::{ConnectionMonitor, Source};
+use crate::alert::Alert;
+use crate::service::notification::{NotificationClient, NotificationError, RateLimiter};
+use crate::settings;
+
+/// Forwards alerts and connection losses to the configured notifiers, each one sending from its own queue
+/// so that a slow or failing backend doesn't hold back the others.
+pub struct NotifierActor {
+    alert_rx: broadcast::Receiver<AlertMessage>,
+    rpict_rx: broadcast::Receiver<RpictMessage>,
+    linky_rx: broadcast::Receiver<LinkyMessage>,
+    datalogger_rx: broadcast::Receiver<DataLoggerMessage>,
+    rx: mpsc::Receiver<NotifierCommand>,
+    connections: ConnectionMonitor,
+    queues: Vec<(String, mpsc::Sender<Alert>)>,
+    dispatchers: Vec<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+enum NotifierCommand {
+    Shutdown(oneshot::Sender<()>),
+}
+
+#[derive(Clone)]
+pub struct NotifierActorHandle {
+    command_tx: mpsc::Sender<NotifierCommand>,
+}
+
+/// Sends the alerts queued for one notifier.
+struct Dispatcher {
+    settings: settings::Notifier,
+    client: NotificationClient,
+    limiter: RateLimiter,
+    dropped: u32,
+    rx: mpsc::Receiver<Alert>,
+}
+
+impl Dispatcher {
+    /// Delay before the first retry, doubled on each following one.
+    const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+    fn accepts(&self, alert: &Alert) -> bool {
+        alert.severity >= self.settings.min_severity
+            && (self.settings.events.is_empty() || self.settings.events.contains(&alert.name))
+    }
+
+    async fn send(&mut self, alert: Alert) {
+        let name = &self.settings.name;
+        if !self.limiter.try_acquire(Instant::now()) {
+            self.dropped += 1;
+            log::warn!("Notifier {name} rate limited, dropping {} {}", alert.name, alert.state);
+            return;
+        }
+        if self.dropped > 0 {
+            log::info!(
+                "Notifier {name} sending again, {} notification(s) dropped",
+                self.dropped
+            );
+            self.dropped = 0;
+        }
+        let mut attempt = 0;
+        loop {
+            match self.client.send(&alert).await {
+                Ok(()) => {
+                    log::debug!("Notifier {name} sent {} {}", alert.name, alert.state);
+                    break;
+                }
+                Err(NotificationError::Transient(e)) if attempt < self.settings.retries => {
+                    let delay = Self::RETRY_DELAY * 2u32.pow(attempt);
+                    log::warn!(
+                        "Notifier {name} failed to send {}: {e}, retrying in {delay:?}",
+                        alert.name
+                    );
+                    sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    log::error!("Notifier {name} failed to send {}: {e}", alert.name);
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn run(&mut self) {
+        while let Some(alert) = self.rx.recv().await {
+            if self.accepts(&alert) {
+                self.send(alert).await;
+            }
+        }
+    }
+}
+
+impl NotifierActor {
+    /// Alerts waiting for a notifier busy retrying, beyond which new ones are dropped.
+    const QUEUE_SIZE: usize = 16;
+
+    fn dispatch(&self, alert: Alert) {
+        for (name, queue) in &self.queues {
+            if queue.try_send(alert.clone()).is_err() {
+                log::warn!("Notifier {name} queue full, dropping {} {}", alert.name, alert.state);
+            }
+        }
+    }
+
+    fn handle_connection(&mut self, source: Source, connected: bool) {
+        if let Some(alert) = self.connections.update(source, connected, Utc::now()) {
+            self.dispatch(alert);
+        }
+    }
+
+    /// Sends the alerts still queued, the dispatchers stopping once their queue is empty.
+    async fn flush(&mut self) {
+        loop {
+            match self.alert_rx.try_recv() {
+                Ok(AlertMessage::NewAlert(alert)) => self.dispatch(alert),
+                Err(TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+        self.queues.clear();
+        for dispatcher in self.dispatchers.drain(..) {
+            dispatcher.await.unwrap_or_default();
+        }
+    }
+
+    async fn run(&mut self) {
+        loop {
+            tokio::select! {
+                Ok(msg) = self.alert_rx.recv() => {
+                    let AlertMessage::NewAlert(alert) = msg;
+                    self.dispatch(alert);
+                },
+                Ok(msg) = self.rpict_rx.recv() => match msg {
+                    RpictMessage::Connected => self.handle_connection(Source::Rpict, true),
+                    RpictMessage::Disconnected => self.handle_connection(Source::Rpict, false),
+                    RpictMessage::NewFrame(_) => {}
+                },
+                Ok(msg) = self.linky_rx.recv() => match msg {
+                    LinkyMessage::Connected => self.handle_connection(Source::Linky, true),
+                    LinkyMessage::Disconnected => self.handle_connection(Source::Linky, false),
+                    LinkyMessage::NewFrame(_) => {}
+                },
+                Ok(msg) = self.datalogger_rx.recv() => match msg {
+                    DataLoggerMessage::InfluxDbConnected => self.handle_connection(Source::InfluxDb, true),
+                    DataLoggerMessage::InfluxDbDisconnected => self.handle_connection(Source::InfluxDb, false),
+                    // not a connection loss, the new target is reported once it is reached
+                    DataLoggerMessage::InfluxDbTargetChanged => {}
+                },
+                Some(msg) = self.rx.recv() => match msg {
+                    NotifierCommand::Shutdown(callback) => {
+                        log::debug!("Shutdown notifier");
+                        self.flush().await;
+                        callback.send(()).unwrap_or_default();
+                        break;
+                    },
+                },
+                else => break,
+            }
+        }
+    }
+
+    /// Starts the notifiers that can be set up, the others being reported and skipped.
+    /// Nothing is started without any notifier.
+    pub fn create(
+        settings: &[settings::Notifier],
+        alert: &AlertActorHandle,
+        rpict: &RpictActorHandle,
+        linky: &LinkyActorHandle,
+        datalogger: &DataLoggerHandle,
+    ) -> Option<NotifierActorHandle> {
+        let mut queues = Vec::new();
+        let mut dispatchers = Vec::new();
+        for notifier in settings {
+            let client = match NotificationClient::new(notifier) {
+                Ok(client) => client,
+                Err(e) => {
+                    log::error!("Cannot set up notifier {}, skipping it: {e}", notifier.name);
+                    continue;
+                }
+            };
+            let (tx, rx) = mpsc::channel(Self::QUEUE_SIZE);
+            let mut dispatcher = Dispatcher {
+                settings: notifier.clone(),
+                client,
+                limiter: RateLimiter::new(notifier.max_per_hour),
+                dropped: 0,
+                rx,
+            };
+            dispatchers.push(tokio::task::spawn(async move { dispatcher.run().await }));
+            queues.push((notifier.name.clone(), tx));
+        }
+        if queues.is_empty() {
+            return None;
+        }
+        let (command_tx, rx) = mpsc::channel(1);
+        let mut actor = NotifierActor {
+            alert_rx: alert.subscribe(),
+            rpict_rx: rpict.subscribe(),
+            linky_rx: linky.subscribe(),
+            datalogger_rx: datalogger.subscribe(),
+            rx,
+            connections: ConnectionMonitor::default(),
+            queues,
+            dispatchers,
+        };
+        tokio::task::spawn(async move { actor.run().await });
+        Some(NotifierActorHandle { command_tx })
+    }
+}
+
+impl NotifierActorHandle {
+    /// Sends the queued notifications, retries included, then stops the actor.
+    pub async fn shutdown(&self) {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(NotifierCommand::Shutdown(tx))
+            .await
+            .unwrap_or_default();
+        rx.await.unwrap_or_default()
+    }
+}
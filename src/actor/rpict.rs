@@ -1,6 +1,9 @@
-use std::thread::sleep;
 use std::time::Duration;
+
+use futures::StreamExt;
 use tokio::sync::broadcast;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
 
 use RpictMessage::*;
 
@@ -13,35 +16,69 @@ pub enum RpictMessage {
     NewFrame(RpictFrame),
 }
 
-pub struct RpictActor;
+#[derive(Debug)]
+enum RpictCommand {
+    Shutdown(oneshot::Sender<()>),
+}
+
+pub struct RpictActor {
+    serial_path: String,
+    rx: mpsc::Receiver<RpictCommand>,
+    tx: broadcast::Sender<RpictMessage>,
+}
 
 #[derive(Clone)]
 pub struct RpictActorHandle {
     tx: broadcast::Sender<RpictMessage>,
+    command_tx: mpsc::Sender<RpictCommand>,
 }
 
 impl RpictActor {
-    pub fn create(serial_path: &str) -> RpictActorHandle {
-        let serial_path = serial_path.to_owned();
-        let (tx, _) = broadcast::channel(5);
-        let tx2 = tx.clone();
-        tokio::task::spawn_blocking(move || {
-            sleep(Duration::from_secs(1));
-            let iter = Rpict::builder().with_port_path(serial_path).build();
-            if let Err(e) = iter {
-                log::debug!("Cannot connect Rpict: {:?}", e);
-                tx.send(Disconnected).unwrap_or_default();
+    async fn run(&mut self) {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let mut frames = match Rpict::builder().with_port_path(self.serial_path.clone()).build() {
+            Ok(frames) => frames,
+            Err(e) => {
+                log::error!("Cannot connect Rpict: {e}");
+                self.tx.send(Disconnected).unwrap_or_default();
                 return;
-            } else {
-                tx.send(Connected).unwrap_or_default();
             }
-            for frame in iter.unwrap() {
-                if tx.send(NewFrame(frame)).is_err() {
-                    break;
-                }
+        };
+        self.tx.send(Connected).unwrap_or_default();
+        loop {
+            tokio::select! {
+                frame = frames.next() => match frame {
+                    Some(frame) => {
+                        self.tx.send(NewFrame(frame)).unwrap_or_default();
+                    }
+                    None => {
+                        // the frame stream only ends on a read error
+                        self.tx.send(Disconnected).unwrap_or_default();
+                        break;
+                    }
+                },
+                Some(msg) = self.rx.recv() => match msg {
+                    RpictCommand::Shutdown(callback) => {
+                        log::debug!("Rpict reader stopped");
+                        callback.send(()).unwrap_or_default();
+                        break;
+                    },
+                },
+                else => break,
             }
-        });
-        RpictActorHandle { tx: tx2 }
+        }
+    }
+
+    pub fn create(serial_path: &str) -> RpictActorHandle {
+        let (tx, _) = broadcast::channel(5);
+        let (command_tx, rx) = mpsc::channel(1);
+        let mut actor = RpictActor {
+            serial_path: serial_path.to_owned(),
+            rx,
+            tx: tx.clone(),
+        };
+        tokio::task::spawn(async move { actor.run().await });
+        RpictActorHandle { tx, command_tx }
     }
 }
 
@@ -49,4 +86,14 @@ impl RpictActorHandle {
     pub fn subscribe(&self) -> broadcast::Receiver<RpictMessage> {
         self.tx.subscribe()
     }
+
+    /// Stops reading frames and closes the serial port.
+    pub async fn shutdown(&self) {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(RpictCommand::Shutdown(tx))
+            .await
+            .unwrap_or_default();
+        rx.await.unwrap_or_default()
+    }
 }
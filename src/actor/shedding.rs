@@ -0,0 +1,173 @@
+use std::error::Error;
+use std::time::Duration;
+
+use chrono::Utc;
+use rppal::gpio::{Gpio, Level, OutputPin};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::{interval, MissedTickBehavior};
+
+use SheddingMessage::*;
+
+use crate::actor::linky::{LinkyActorHandle, LinkyMessage};
+use crate::actor::rpict::{RpictActorHandle, RpictMessage};
+use crate::settings;
+use crate::shedding::{LoadShedder, OutputState};
+
+#[derive(Clone, Debug)]
+pub enum SheddingMessage {
+    /// Level of every output, sent when any changed
+    NewStates(Vec<OutputState>),
+}
+
+#[derive(Debug)]
+enum SheddingCommand {
+    Reload(settings::LoadShedding),
+    Shutdown(oneshot::Sender<()>),
+}
+
+/// Drives the load shedding relays, see [`LoadShedder`].
+pub struct SheddingActor {
+    shedder: LoadShedder,
+    pins: Vec<(OutputPin, bool)>,
+    rpict_rx: broadcast::Receiver<RpictMessage>,
+    linky_rx: broadcast::Receiver<LinkyMessage>,
+    rx: mpsc::Receiver<SheddingCommand>,
+    tx: broadcast::Sender<SheddingMessage>,
+}
+
+#[derive(Clone)]
+pub struct SheddingActorHandle {
+    tx: broadcast::Sender<SheddingMessage>,
+    command_tx: mpsc::Sender<SheddingCommand>,
+}
+
+impl SheddingActor {
+    const TICK_PERIOD: Duration = Duration::from_secs(1);
+
+    fn level(on: bool, active_low: bool) -> Level {
+        if on != active_low {
+            Level::High
+        } else {
+            Level::Low
+        }
+    }
+
+    fn apply(&mut self, states: &[OutputState]) {
+        for ((pin, active_low), state) in self.pins.iter_mut().zip(states) {
+            pin.write(Self::level(state.on, *active_low));
+        }
+    }
+
+    fn tick(&mut self) {
+        if let Some(states) = self.shedder.tick(Utc::now()) {
+            for state in &states {
+                log::debug!(
+                    "Output {} {} ({})",
+                    state.name,
+                    if state.on { "on" } else { "off" },
+                    state.reason
+                );
+            }
+            self.apply(&states);
+            self.tx.send(NewStates(states)).unwrap_or_default();
+        }
+    }
+
+    async fn run(&mut self) {
+        let mut ticker = interval(Self::TICK_PERIOD);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                Ok(msg) = self.rpict_rx.recv() => if let RpictMessage::NewFrame(frame) = msg {
+                    self.shedder.update_rpict(&frame);
+                },
+                Ok(msg) = self.linky_rx.recv() => if let LinkyMessage::NewFrame(frame) = msg {
+                    self.shedder.update_linky(&frame);
+                },
+                _ = ticker.tick() => self.tick(),
+                Some(msg) = self.rx.recv() => match msg {
+                    SheddingCommand::Reload(settings) => {
+                        log::debug!("Reload load shedding settings");
+                        self.shedder.reload(&settings);
+                        self.tick();
+                    },
+                    SheddingCommand::Shutdown(callback) => {
+                        log::debug!("Shutdown load shedding");
+                        // loads run as if the monitor wasn't there
+                        for (pin, active_low) in &mut self.pins {
+                            pin.write(Self::level(true, *active_low));
+                        }
+                        callback.send(()).unwrap_or_default();
+                        break;
+                    },
+                },
+                else => break,
+            }
+        }
+    }
+
+    pub fn create(
+        settings: &settings::LoadShedding,
+        subscribed_current_amps: Option<f32>,
+        rpict: &RpictActorHandle,
+        linky: &LinkyActorHandle,
+    ) -> Result<SheddingActorHandle, Box<dyn Error>> {
+        // without outputs, hosts without GPIO can run
+        let pins = if settings.outputs.is_empty() {
+            Vec::new()
+        } else {
+            let gpio = Gpio::new()?;
+            settings
+                .outputs
+                .iter()
+                .map(|output| {
+                    // loads are off until the first decision
+                    let pin = gpio.get(output.bcm_pin)?;
+                    let mut pin = match Self::level(false, output.active_low) {
+                        Level::High => pin.into_output_high(),
+                        Level::Low => pin.into_output_low(),
+                    };
+                    // keep the last level once stopped rather than a floating pin
+                    pin.set_reset_on_drop(false);
+                    Ok((pin, output.active_low))
+                })
+                .collect::<Result<Vec<_>, rppal::gpio::Error>>()?
+        };
+        let (tx, _) = broadcast::channel(5);
+        let (command_tx, rx) = mpsc::channel(1);
+        let mut actor = SheddingActor {
+            shedder: LoadShedder::new(settings, subscribed_current_amps, Utc::now()),
+            pins,
+            rpict_rx: rpict.subscribe(),
+            linky_rx: linky.subscribe(),
+            rx,
+            tx: tx.clone(),
+        };
+        tokio::task::spawn(async move { actor.run().await });
+        Ok(SheddingActorHandle { tx, command_tx })
+    }
+}
+
+impl SheddingActorHandle {
+    pub fn subscribe(&self) -> broadcast::Receiver<SheddingMessage> {
+        self.tx.subscribe()
+    }
+
+    /// Applies new thresholds, modes and rules, pins being only read at startup.
+    pub async fn reload(&self, settings: &settings::LoadShedding) {
+        self.command_tx
+            .send(SheddingCommand::Reload(settings.clone()))
+            .await
+            .unwrap_or_default();
+    }
+
+    /// Switches the loads on then stops the actor.
+    pub async fn shutdown(&self) {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(SheddingCommand::Shutdown(tx))
+            .await
+            .unwrap_or_default();
+        rx.await.unwrap_or_default()
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+use tokio::time::{interval_at, Instant, Interval};
+
+use crate::actor::datalogger::{DataLoggerHandle, DataLoggerMessage};
+use crate::actor::linky::{LinkyActorHandle, LinkyMessage};
+use crate::actor::rpict::{RpictActorHandle, RpictMessage};
+use crate::service::systemd::{watchdog_timeout, Notifier};
+
+/// Reports connection states to systemd and pings its watchdog while both
+/// RPICT and Linky pipelines keep producing frames.
+pub struct SystemdActor {
+    notifier: Notifier,
+    rpict_rx: broadcast::Receiver<RpictMessage>,
+    linky_rx: broadcast::Receiver<LinkyMessage>,
+    datalogger_rx: broadcast::Receiver<DataLoggerMessage>,
+    // internal state
+    is_rpict_connected: bool,
+    is_linky_connected: bool,
+    is_influxdb_connected: bool,
+    last_rpict_frame: Option<Instant>,
+    last_linky_frame: Option<Instant>,
+}
+
+impl SystemdActor {
+    fn notify(&self, state: &str) {
+        if let Err(e) = self.notifier.notify(state) {
+            log::warn!("Cannot notify systemd: {e}");
+        }
+    }
+
+    fn notify_status(&self) {
+        const STATUS: fn(bool) -> &'static str = |connected| if connected { "connected" } else { "disconnected" };
+        self.notify(&format!(
+            "STATUS=RPICT {}, Linky {}, InfluxDB {}",
+            STATUS(self.is_rpict_connected),
+            STATUS(self.is_linky_connected),
+            STATUS(self.is_influxdb_connected),
+        ));
+    }
+
+    fn is_fresh(last_frame: Option<Instant>, timeout: Duration) -> bool {
+        last_frame.is_some_and(|instant| instant.elapsed() < timeout)
+    }
+
+    /// Waits for the next watchdog tick, forever when the watchdog is disabled.
+    async fn next_tick(watchdog: &mut Option<Interval>) {
+        match watchdog {
+            Some(watchdog) => {
+                watchdog.tick().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn run(&mut self, watchdog_timeout: Option<Duration>) {
+        let timeout = watchdog_timeout.unwrap_or(Duration::MAX);
+        // the first ping is delayed by a period, as no frame can be fresh right at startup
+        let mut watchdog = watchdog_timeout.map(|timeout| interval_at(Instant::now() + timeout / 2, timeout / 2));
+        loop {
+            tokio::select! {
+                Ok(msg) = self.rpict_rx.recv() => match msg {
+                    RpictMessage::Connected => {
+                        self.is_rpict_connected = true;
+                        self.notify_status();
+                    }
+                    RpictMessage::Disconnected => {
+                        self.is_rpict_connected = false;
+                        self.notify_status();
+                    }
+                    RpictMessage::NewFrame(_) => self.last_rpict_frame = Some(Instant::now()),
+                },
+                Ok(msg) = self.linky_rx.recv() => match msg {
+                    LinkyMessage::Connected => {
+                        self.is_linky_connected = true;
+                        self.notify_status();
+                    }
+                    LinkyMessage::Disconnected => {
+                        self.is_linky_connected = false;
+                        self.notify_status();
+                    }
+                    LinkyMessage::NewFrame(_) => self.last_linky_frame = Some(Instant::now()),
+                },
+                Ok(msg) = self.datalogger_rx.recv() => {
+                    self.is_influxdb_connected = matches!(msg, DataLoggerMessage::InfluxDbConnected);
+                    self.notify_status();
+                },
+                _ = Self::next_tick(&mut watchdog), if watchdog.is_some() => {
+                    if Self::is_fresh(self.last_rpict_frame, timeout) && Self::is_fresh(self.last_linky_frame, timeout) {
+                        self.notify("WATCHDOG=1");
+                    } else {
+                        log::warn!("No fresh frame from RPICT or Linky, skipping watchdog ping");
+                    }
+                },
+                else => break,
+            }
+        }
+    }
+
+    pub fn create(
+        notifier: &Notifier,
+        rpict: &RpictActorHandle,
+        linky: &LinkyActorHandle,
+        datalogger: &DataLoggerHandle,
+    ) {
+        let watchdog_timeout = watchdog_timeout();
+        log::debug!("systemd watchdog timeout: {:?}", watchdog_timeout);
+        let mut actor = SystemdActor {
+            notifier: notifier.clone(),
+            rpict_rx: rpict.subscribe(),
+            linky_rx: linky.subscribe(),
+            datalogger_rx: datalogger.subscribe(),
+            is_rpict_connected: false,
+            is_linky_connected: false,
+            is_influxdb_connected: false,
+            last_rpict_frame: None,
+            last_linky_frame: None,
+        };
+        actor.notify_status();
+        tokio::task::spawn(async move { actor.run(watchdog_timeout).await });
+    }
+}
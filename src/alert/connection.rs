@@ -0,0 +1,105 @@
+use chrono::{DateTime, Utc};
+
+use crate::alert::{Alert, AlertState, Severity};
+
+/// A pipeline reporting its connection state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Rpict,
+    Linky,
+    InfluxDb,
+}
+
+impl Source {
+    pub const ALL: [Source; 3] = [Source::Rpict, Source::Linky, Source::InfluxDb];
+
+    /// Name of the alert raised while the source is disconnected.
+    pub fn alert_name(&self) -> &'static str {
+        match self {
+            Source::Rpict => "rpict_disconnected",
+            Source::Linky => "linky_disconnected",
+            Source::InfluxDb => "influxdb_disconnected",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Source::Rpict => "RPICT",
+            Source::Linky => "Linky",
+            Source::InfluxDb => "InfluxDB",
+        }
+    }
+
+    /// Sensors going silent stop all measures, while InfluxDB going down only loses history.
+    fn severity(&self) -> Severity {
+        match self {
+            Source::Rpict | Source::Linky => Severity::Critical,
+            Source::InfluxDb => Severity::Warning,
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Turns connection state changes into alerts, firing on the first disconnection and resolving
+/// on the next connection. A connection not preceded by a disconnection raises nothing.
+#[derive(Default)]
+pub struct ConnectionMonitor {
+    is_disconnected: [bool; 3],
+}
+
+impl ConnectionMonitor {
+    pub fn update(&mut self, source: Source, connected: bool, timestamp: DateTime<Utc>) -> Option<Alert> {
+        let is_disconnected = &mut self.is_disconnected[source.index()];
+        if *is_disconnected != connected {
+            return None;
+        }
+        *is_disconnected = !connected;
+        let (state, message) = if connected {
+            (AlertState::Resolved, format!("{} connected again", source.label()))
+        } else {
+            (AlertState::Firing, format!("{} disconnected", source.label()))
+        };
+        Some(Alert {
+            name: source.alert_name().to_string(),
+            severity: source.severity(),
+            state,
+            message,
+            timestamp,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_connection_alerts() {
+        // Given
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut monitor = ConnectionMonitor::default();
+        // When
+        let connected = monitor.update(Source::Rpict, true, now);
+        let disconnected = monitor.update(Source::Rpict, false, now);
+        let retried = monitor.update(Source::Rpict, false, now);
+        let other = monitor.update(Source::InfluxDb, false, now);
+        let reconnected = monitor.update(Source::Rpict, true, now);
+        // Then
+        assert_eq!(connected, None);
+        let disconnected = disconnected.unwrap();
+        assert_eq!(disconnected.name, "rpict_disconnected");
+        assert_eq!(disconnected.severity, Severity::Critical);
+        assert_eq!(disconnected.state, AlertState::Firing);
+        assert_eq!(disconnected.message, "RPICT disconnected");
+        assert_eq!(retried, None);
+        assert_eq!(other.unwrap().severity, Severity::Warning);
+        let reconnected = reconnected.unwrap();
+        assert_eq!(reconnected.state, AlertState::Resolved);
+        assert_eq!(reconnected.message, "RPICT connected again");
+    }
+}
@@ -0,0 +1,162 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::alert::{Alert, AlertState, Severity};
+use crate::analytics::imbalance::{imbalance_percent, most_loaded};
+use crate::driver::rpict::RpictFrame;
+use crate::settings;
+
+/// Detects a line staying much more loaded than the others, from the RPICT RMS current.
+pub struct ImbalanceDetector {
+    warning_percent: f32,
+    duration: Duration,
+    min_current_amps: f32,
+    /// Most loaded line and since when the imbalance lasts, before firing
+    pending: Option<(usize, DateTime<Utc>)>,
+    /// Line reported as most loaded
+    firing: Option<usize>,
+}
+
+impl ImbalanceDetector {
+    pub const PHASE_IMBALANCE: &'static str = "phase_imbalance";
+    /// Drop below the warning threshold, in imbalance points, resolving the warning.
+    const HYSTERESIS_PERCENT: f32 = 10.0;
+
+    pub fn new(settings: &settings::Alerts) -> Self {
+        Self {
+            warning_percent: settings.imbalance_warning_percent,
+            duration: Duration::seconds(settings.imbalance_for_secs as i64),
+            min_current_amps: settings.imbalance_min_current_amps,
+            pending: None,
+            firing: None,
+        }
+    }
+
+    pub fn update_rpict(&mut self, frame: &RpictFrame) -> Option<Alert> {
+        let irms = [frame.l1_irms, frame.l2_irms, frame.l3_irms];
+        let mean = irms.iter().sum::<f32>() / 3.0;
+        let imbalance = imbalance_percent(irms);
+        let threshold = match self.firing {
+            Some(_) => self.warning_percent - Self::HYSTERESIS_PERCENT,
+            None => self.warning_percent,
+        };
+        let is_met = mean >= self.min_current_amps && imbalance.is_some_and(|imbalance| imbalance >= threshold);
+        let line = most_loaded(irms);
+        match (self.firing, is_met) {
+            (Some(line), false) => {
+                self.firing = None;
+                self.pending = None;
+                let message = format!("line {} no longer much more loaded than the others", line + 1);
+                Some(self.alert(false, message, frame.timestamp))
+            }
+            (Some(_), true) => None,
+            (None, true) => match self.pending {
+                Some((pending, since)) if pending == line => {
+                    if frame.timestamp - since < self.duration {
+                        return None;
+                    }
+                    self.firing = Some(line);
+                    let message = format!(
+                        "line {} at {:.1} A against {mean:.1} A on average, {:.0}% imbalance",
+                        line + 1,
+                        irms[line],
+                        imbalance.unwrap_or_default()
+                    );
+                    Some(self.alert(true, message, frame.timestamp))
+                }
+                _ => {
+                    self.pending = Some((line, frame.timestamp));
+                    None
+                }
+            },
+            (None, false) => {
+                self.pending = None;
+                None
+            }
+        }
+    }
+
+    fn alert(&self, firing: bool, message: String, timestamp: DateTime<Utc>) -> Alert {
+        Alert {
+            name: Self::PHASE_IMBALANCE.to_string(),
+            severity: Severity::Warning,
+            state: if firing {
+                AlertState::Firing
+            } else {
+                AlertState::Resolved
+            },
+            message,
+            timestamp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn settings() -> settings::Alerts {
+        settings::Alerts {
+            overrun_warning_percent: 90.0,
+            subscribed_current_amps: None,
+            imbalance_warning_percent: 50.0,
+            imbalance_for_secs: 60,
+            imbalance_min_current_amps: 5.0,
+            rules: Vec::new(),
+        }
+    }
+
+    fn rpict(irms: [f32; 3], timestamp: DateTime<Utc>) -> RpictFrame {
+        RpictFrame {
+            node_id: 11,
+            l1_real_power: 0.0,
+            l1_apparent_power: 0.0,
+            l1_irms: irms[0],
+            l1_vrms: 230.0,
+            l1_power_factor: 1.0,
+            l2_real_power: 0.0,
+            l2_apparent_power: 0.0,
+            l2_irms: irms[1],
+            l2_vrms: 230.0,
+            l2_power_factor: 1.0,
+            l3_real_power: 0.0,
+            l3_apparent_power: 0.0,
+            l3_irms: irms[2],
+            l3_vrms: 230.0,
+            l3_power_factor: 1.0,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn test_phase_imbalance() {
+        // Given
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let at = |secs| now + Duration::seconds(secs);
+        let mut detector = ImbalanceDetector::new(&settings());
+        // When
+        let idle = detector.update_rpict(&rpict([3.0, 0.0, 0.0], at(0)));
+        let started = detector.update_rpict(&rpict([20.0, 5.0, 5.0], at(10)));
+        let other_line = detector.update_rpict(&rpict([5.0, 20.0, 5.0], at(40)));
+        let pending = detector.update_rpict(&rpict([5.0, 20.0, 5.0], at(90)));
+        let firing = detector.update_rpict(&rpict([5.0, 20.0, 5.0], at(100)));
+        let within_hysteresis = detector.update_rpict(&rpict([7.5, 14.5, 8.0], at(110)));
+        let resolved = detector.update_rpict(&rpict([10.0, 12.0, 8.0], at(120)));
+        // Then
+        assert_eq!(idle, None);
+        assert_eq!(started, None);
+        assert_eq!(other_line, None);
+        assert_eq!(pending, None);
+        let firing = firing.unwrap();
+        assert_eq!(firing.state, AlertState::Firing);
+        assert_eq!(
+            firing.message,
+            "line 2 at 20.0 A against 10.0 A on average, 100% imbalance"
+        );
+        assert_eq!(within_hysteresis, None);
+        let resolved = resolved.unwrap();
+        assert_eq!(resolved.state, AlertState::Resolved);
+        assert_eq!(resolved.message, "line 2 no longer much more loaded than the others");
+    }
+}
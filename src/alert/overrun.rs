@@ -0,0 +1,269 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::alert::{Alert, AlertState, Severity};
+use crate::driver::linky::LinkyFrame;
+use crate::driver::rpict::RpictFrame;
+use crate::settings;
+
+/// Lines current read from one source.
+#[derive(Clone, Debug, PartialEq)]
+struct LineCurrents {
+    amps: [Option<f32>; 3],
+    timestamp: DateTime<Utc>,
+}
+
+/// Detects subscribed power overruns, reported by the Linky ADPS group, and lines drawing
+/// close to the subscribed current, from the Linky IINST groups and the RPICT RMS current.
+pub struct OverrunDetector {
+    warning_ratio: f32,
+    subscribed_current_amps: Option<f32>,
+    linky_currents: Option<LineCurrents>,
+    rpict_currents: Option<LineCurrents>,
+    is_overrun: bool,
+    is_near_overrun: bool,
+}
+
+impl OverrunDetector {
+    pub const OVERRUN: &'static str = "overrun";
+    pub const NEAR_OVERRUN: &'static str = "near_overrun";
+    /// Currents older than this are ignored, so that a silent source doesn't hold an alert.
+    const MAX_AGE_SECS: i64 = 10;
+    /// Drop below the warning threshold, as a share of the subscribed current, resolving the warning.
+    const HYSTERESIS_RATIO: f32 = 0.05;
+
+    pub fn new(settings: &settings::Alerts) -> Self {
+        Self {
+            warning_ratio: settings.overrun_warning_percent / 100.0,
+            subscribed_current_amps: settings.subscribed_current_amps,
+            linky_currents: None,
+            rpict_currents: None,
+            is_overrun: false,
+            is_near_overrun: false,
+        }
+    }
+
+    pub fn update_linky(&mut self, frame: &LinkyFrame) -> Vec<Alert> {
+        if self.subscribed_current_amps.is_none() {
+            self.subscribed_current_amps = frame.isousc.map(f32::from);
+        }
+        let mut alerts = Vec::new();
+        // ADPS is only sent while the overrun lasts
+        if frame.adps.is_some() != self.is_overrun {
+            self.is_overrun = frame.adps.is_some();
+            let message = match frame.adps {
+                Some(adps) => format!("subscribed power exceeded, {adps} A drawn"),
+                None => "subscribed power no longer exceeded".to_string(),
+            };
+            alerts.push(self.alert(
+                Self::OVERRUN,
+                Severity::Critical,
+                self.is_overrun,
+                message,
+                frame.timestamp,
+            ));
+        }
+        self.linky_currents = Some(LineCurrents {
+            amps: frame.iinst.map(|iinst| iinst.map(f32::from)),
+            timestamp: frame.timestamp,
+        });
+        alerts.extend(self.check_near_overrun(frame.timestamp));
+        alerts
+    }
+
+    pub fn update_rpict(&mut self, frame: &RpictFrame) -> Vec<Alert> {
+        self.rpict_currents = Some(LineCurrents {
+            amps: [frame.l1_irms, frame.l2_irms, frame.l3_irms].map(|irms| irms.is_finite().then_some(irms)),
+            timestamp: frame.timestamp,
+        });
+        self.check_near_overrun(frame.timestamp).into_iter().collect()
+    }
+
+    /// Compares the highest recent current of each line to the warning threshold.
+    fn check_near_overrun(&mut self, now: DateTime<Utc>) -> Option<Alert> {
+        let subscribed = self.subscribed_current_amps?;
+        let max_age = Duration::seconds(Self::MAX_AGE_SECS);
+        let mut lines = [None::<f32>; 3];
+        for currents in [&self.linky_currents, &self.rpict_currents]
+            .into_iter()
+            .flatten()
+            .filter(|currents| now - currents.timestamp <= max_age)
+        {
+            for (line, amps) in lines.iter_mut().zip(currents.amps) {
+                *line = match (*line, amps) {
+                    (Some(line), Some(amps)) => Some(line.max(amps)),
+                    (line, amps) => line.or(amps),
+                };
+            }
+        }
+        let (index, amps) = lines
+            .iter()
+            .enumerate()
+            .filter_map(|(index, amps)| amps.map(|amps| (index, amps)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+        let threshold = subscribed * self.warning_ratio;
+        let is_near_overrun = if self.is_near_overrun {
+            amps >= threshold - subscribed * Self::HYSTERESIS_RATIO
+        } else {
+            amps >= threshold
+        };
+        if is_near_overrun == self.is_near_overrun {
+            return None;
+        }
+        self.is_near_overrun = is_near_overrun;
+        let message = format!(
+            "line {} at {amps:.1} A, {:.0}% of the {subscribed} A subscribed",
+            index + 1,
+            amps / subscribed * 100.0
+        );
+        Some(self.alert(Self::NEAR_OVERRUN, Severity::Warning, is_near_overrun, message, now))
+    }
+
+    fn alert(&self, name: &str, severity: Severity, firing: bool, message: String, timestamp: DateTime<Utc>) -> Alert {
+        Alert {
+            name: name.to_string(),
+            severity,
+            state: if firing {
+                AlertState::Firing
+            } else {
+                AlertState::Resolved
+            },
+            message,
+            timestamp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn settings() -> settings::Alerts {
+        settings::Alerts {
+            overrun_warning_percent: 90.0,
+            subscribed_current_amps: None,
+            imbalance_warning_percent: 50.0,
+            imbalance_for_secs: 900,
+            imbalance_min_current_amps: 5.0,
+            rules: Vec::new(),
+        }
+    }
+
+    fn linky(iinst: [Option<u16>; 3], adps: Option<u16>, timestamp: DateTime<Utc>) -> LinkyFrame {
+        LinkyFrame {
+            adco: "041876097767".to_string(),
+            ptec: "HP".to_string(),
+            isousc: Some(30),
+            iinst,
+            adps,
+            timestamp,
+            ..LinkyFrame::default()
+        }
+    }
+
+    fn rpict(irms: [f32; 3], timestamp: DateTime<Utc>) -> RpictFrame {
+        RpictFrame {
+            node_id: 11,
+            l1_real_power: 0.0,
+            l1_apparent_power: 0.0,
+            l1_irms: irms[0],
+            l1_vrms: 230.0,
+            l1_power_factor: 1.0,
+            l2_real_power: 0.0,
+            l2_apparent_power: 0.0,
+            l2_irms: irms[1],
+            l2_vrms: 230.0,
+            l2_power_factor: 1.0,
+            l3_real_power: 0.0,
+            l3_apparent_power: 0.0,
+            l3_irms: irms[2],
+            l3_vrms: 230.0,
+            l3_power_factor: 1.0,
+            timestamp,
+        }
+    }
+
+    fn states(alerts: &[Alert]) -> Vec<(&str, AlertState)> {
+        alerts.iter().map(|alert| (alert.name.as_str(), alert.state)).collect()
+    }
+
+    #[test]
+    fn test_overrun_from_adps() {
+        // Given
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut detector = OverrunDetector::new(&settings());
+        // When
+        let started = detector.update_linky(&linky([Some(32), None, None], Some(32), now));
+        let lasting = detector.update_linky(&linky([Some(31), None, None], Some(31), now + Duration::seconds(1)));
+        let ended = detector.update_linky(&linky([Some(10), None, None], None, now + Duration::seconds(2)));
+        // Then
+        assert_eq!(
+            states(&started),
+            vec![
+                (OverrunDetector::OVERRUN, AlertState::Firing),
+                (OverrunDetector::NEAR_OVERRUN, AlertState::Firing)
+            ]
+        );
+        assert_eq!(started[0].severity, Severity::Critical);
+        assert_eq!(started[0].message, "subscribed power exceeded, 32 A drawn");
+        assert!(lasting.is_empty());
+        assert_eq!(
+            states(&ended),
+            vec![
+                (OverrunDetector::OVERRUN, AlertState::Resolved),
+                (OverrunDetector::NEAR_OVERRUN, AlertState::Resolved)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_near_overrun_from_rpict_with_hysteresis() {
+        // Given
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut detector = OverrunDetector::new(&settings());
+        // no subscribed current known until the Linky tells it
+        assert!(detector.update_rpict(&rpict([28.0, 1.0, 1.0], now)).is_empty());
+        assert_eq!(
+            detector
+                .update_linky(&linky([Some(1), Some(1), Some(1)], None, now))
+                .len(),
+            1
+        );
+        detector.update_rpict(&rpict([1.0, 1.0, 1.0], now));
+        // When
+        let firing = detector.update_rpict(&rpict([1.0, 27.5, 1.0], now + Duration::seconds(1)));
+        let within_hysteresis = detector.update_rpict(&rpict([1.0, 26.0, 1.0], now + Duration::seconds(2)));
+        let resolved = detector.update_rpict(&rpict([1.0, 25.0, 1.0], now + Duration::seconds(3)));
+        // Then
+        assert_eq!(
+            states(&firing),
+            vec![(OverrunDetector::NEAR_OVERRUN, AlertState::Firing)]
+        );
+        assert_eq!(firing[0].message, "line 2 at 27.5 A, 92% of the 30 A subscribed");
+        assert!(within_hysteresis.is_empty());
+        assert_eq!(
+            states(&resolved),
+            vec![(OverrunDetector::NEAR_OVERRUN, AlertState::Resolved)]
+        );
+    }
+
+    #[test]
+    fn test_near_overrun_ignores_stale_currents() {
+        // Given
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut detector = OverrunDetector::new(&settings::Alerts {
+            overrun_warning_percent: 80.0,
+            subscribed_current_amps: Some(45.0),
+            ..settings()
+        });
+        detector.update_linky(&linky([Some(40), None, None], None, now));
+        // When the Linky stops sending frames
+        let alerts = detector.update_rpict(&rpict([10.0, 0.0, 0.0], now + Duration::seconds(30)));
+        // Then
+        assert_eq!(
+            states(&alerts),
+            vec![(OverrunDetector::NEAR_OVERRUN, AlertState::Resolved)]
+        );
+    }
+}
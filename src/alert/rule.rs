@@ -0,0 +1,379 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::alert::{Alert, AlertState};
+use crate::driver::linky::LinkyFrame;
+use crate::driver::rpict::RpictFrame;
+use crate::settings::AlertRule;
+
+/// A value alert rules can watch, read from RPICT frames or from the time since the last frame.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    L1RealPower,
+    L1ApparentPower,
+    L1Irms,
+    L1Vrms,
+    L1PowerFactor,
+    L2RealPower,
+    L2ApparentPower,
+    L2Irms,
+    L2Vrms,
+    L2PowerFactor,
+    L3RealPower,
+    L3ApparentPower,
+    L3Irms,
+    L3Vrms,
+    L3PowerFactor,
+    TotalRealPower,
+    TotalApparentPower,
+    RpictFrameAgeSecs,
+    LinkyFrameAgeSecs,
+}
+
+impl Metric {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::L1RealPower => "l1_real_power",
+            Metric::L1ApparentPower => "l1_apparent_power",
+            Metric::L1Irms => "l1_irms",
+            Metric::L1Vrms => "l1_vrms",
+            Metric::L1PowerFactor => "l1_power_factor",
+            Metric::L2RealPower => "l2_real_power",
+            Metric::L2ApparentPower => "l2_apparent_power",
+            Metric::L2Irms => "l2_irms",
+            Metric::L2Vrms => "l2_vrms",
+            Metric::L2PowerFactor => "l2_power_factor",
+            Metric::L3RealPower => "l3_real_power",
+            Metric::L3ApparentPower => "l3_apparent_power",
+            Metric::L3Irms => "l3_irms",
+            Metric::L3Vrms => "l3_vrms",
+            Metric::L3PowerFactor => "l3_power_factor",
+            Metric::TotalRealPower => "total_real_power",
+            Metric::TotalApparentPower => "total_apparent_power",
+            Metric::RpictFrameAgeSecs => "rpict_frame_age_secs",
+            Metric::LinkyFrameAgeSecs => "linky_frame_age_secs",
+        }
+    }
+
+    /// Value read from an RPICT frame, none for frame ages.
+    fn rpict_value(&self, frame: &RpictFrame) -> Option<f32> {
+        let value = match self {
+            Metric::L1RealPower => frame.l1_real_power,
+            Metric::L1ApparentPower => frame.l1_apparent_power,
+            Metric::L1Irms => frame.l1_irms,
+            Metric::L1Vrms => frame.l1_vrms,
+            Metric::L1PowerFactor => frame.l1_power_factor,
+            Metric::L2RealPower => frame.l2_real_power,
+            Metric::L2ApparentPower => frame.l2_apparent_power,
+            Metric::L2Irms => frame.l2_irms,
+            Metric::L2Vrms => frame.l2_vrms,
+            Metric::L2PowerFactor => frame.l2_power_factor,
+            Metric::L3RealPower => frame.l3_real_power,
+            Metric::L3ApparentPower => frame.l3_apparent_power,
+            Metric::L3Irms => frame.l3_irms,
+            Metric::L3Vrms => frame.l3_vrms,
+            Metric::L3PowerFactor => frame.l3_power_factor,
+            Metric::TotalRealPower => frame.l1_real_power + frame.l2_real_power + frame.l3_real_power,
+            Metric::TotalApparentPower => frame.l1_apparent_power + frame.l2_apparent_power + frame.l3_apparent_power,
+            Metric::RpictFrameAgeSecs | Metric::LinkyFrameAgeSecs => return None,
+        };
+        value.is_finite().then_some(value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RuleState {
+    Inactive,
+    /// Condition met since then, not for long enough yet or within the cooldown
+    Pending(DateTime<Utc>),
+    Firing,
+}
+
+#[derive(Clone, Debug)]
+struct RuleStatus {
+    state: RuleState,
+    last_fired: Option<DateTime<Utc>>,
+}
+
+/// Evaluates the alert rules of the settings: frame values on each RPICT frame, frame ages on each tick.
+pub struct RuleEngine {
+    rules: Vec<AlertRule>,
+    statuses: Vec<RuleStatus>,
+    /// Reference of frame ages until a first frame is received
+    started: DateTime<Utc>,
+    last_rpict: Option<DateTime<Utc>>,
+    last_linky: Option<DateTime<Utc>>,
+}
+
+impl RuleEngine {
+    pub fn new(rules: &[AlertRule], now: DateTime<Utc>) -> Self {
+        Self {
+            rules: rules.to_vec(),
+            statuses: vec![
+                RuleStatus {
+                    state: RuleState::Inactive,
+                    last_fired: None,
+                };
+                rules.len()
+            ],
+            started: now,
+            last_rpict: None,
+            last_linky: None,
+        }
+    }
+
+    pub fn update_rpict(&mut self, frame: &RpictFrame) -> Vec<Alert> {
+        self.last_rpict = Some(frame.timestamp);
+        (0..self.rules.len())
+            .filter_map(|index| {
+                let value = self.rules[index].metric.rpict_value(frame)?;
+                self.evaluate(index, value, frame.timestamp)
+            })
+            .collect()
+    }
+
+    pub fn update_linky(&mut self, frame: &LinkyFrame) {
+        self.last_linky = Some(frame.timestamp);
+    }
+
+    /// Evaluates the rules on frame ages.
+    pub fn tick(&mut self, now: DateTime<Utc>) -> Vec<Alert> {
+        let age =
+            |last: Option<DateTime<Utc>>, started| (now - last.unwrap_or(started)).num_milliseconds() as f32 / 1000.0;
+        let rpict_age = age(self.last_rpict, self.started);
+        let linky_age = age(self.last_linky, self.started);
+        (0..self.rules.len())
+            .filter_map(|index| {
+                let value = match self.rules[index].metric {
+                    Metric::RpictFrameAgeSecs => rpict_age,
+                    Metric::LinkyFrameAgeSecs => linky_age,
+                    _ => return None,
+                };
+                self.evaluate(index, value, now)
+            })
+            .collect()
+    }
+
+    /// Moves a rule through its states, returning the alert of a transition to or from firing.
+    fn evaluate(&mut self, index: usize, value: f32, now: DateTime<Utc>) -> Option<Alert> {
+        let rule = &self.rules[index];
+        let status = &mut self.statuses[index];
+        // the condition holds within the hysteresis margin once firing
+        let is_met = |margin: f32| match (rule.above, rule.below) {
+            (Some(above), _) => value > above - margin,
+            (_, Some(below)) => value < below + margin,
+            _ => false,
+        };
+        let alert = |state, message| Alert {
+            name: rule.name.clone(),
+            severity: rule.severity,
+            state,
+            message,
+            timestamp: now,
+        };
+        match status.state {
+            RuleState::Firing if !is_met(rule.hysteresis) => {
+                status.state = RuleState::Inactive;
+                let message = format!("{} back to {value:.1}", rule.metric.name());
+                Some(alert(AlertState::Resolved, message))
+            }
+            RuleState::Firing => None,
+            _ if !is_met(0.0) => {
+                status.state = RuleState::Inactive;
+                None
+            }
+            RuleState::Inactive | RuleState::Pending(_) => {
+                let since = match status.state {
+                    RuleState::Pending(since) => since,
+                    _ => now,
+                };
+                let is_held = now - since >= Duration::seconds(rule.for_secs as i64);
+                let is_cooled_down = status
+                    .last_fired
+                    .is_none_or(|last_fired| now - last_fired >= Duration::seconds(rule.cooldown_secs as i64));
+                if !(is_held && is_cooled_down) {
+                    status.state = RuleState::Pending(since);
+                    return None;
+                }
+                status.state = RuleState::Firing;
+                status.last_fired = Some(now);
+                let (comparison, threshold) = match (rule.above, rule.below) {
+                    (Some(above), _) => ("above", above),
+                    (_, below) => ("below", below.unwrap_or_default()),
+                };
+                let mut message = format!("{} at {value:.1}, {comparison} {threshold}", rule.metric.name());
+                if rule.for_secs > 0 {
+                    message.push_str(&format!(" for {} s", rule.for_secs));
+                }
+                Some(alert(AlertState::Firing, message))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::alert::Severity;
+
+    fn rule(name: &str, metric: Metric, above: Option<f32>, below: Option<f32>) -> AlertRule {
+        AlertRule {
+            name: name.to_string(),
+            severity: Severity::Warning,
+            metric,
+            above,
+            below,
+            for_secs: 0,
+            hysteresis: 0.0,
+            cooldown_secs: 0,
+        }
+    }
+
+    fn rpict(apparent_power: [f32; 3], vrms: [f32; 3], timestamp: DateTime<Utc>) -> RpictFrame {
+        RpictFrame {
+            node_id: 11,
+            l1_real_power: apparent_power[0],
+            l1_apparent_power: apparent_power[0],
+            l1_irms: apparent_power[0] / vrms[0],
+            l1_vrms: vrms[0],
+            l1_power_factor: 1.0,
+            l2_real_power: apparent_power[1],
+            l2_apparent_power: apparent_power[1],
+            l2_irms: apparent_power[1] / vrms[1],
+            l2_vrms: vrms[1],
+            l2_power_factor: 1.0,
+            l3_real_power: apparent_power[2],
+            l3_apparent_power: apparent_power[2],
+            l3_irms: apparent_power[2] / vrms[2],
+            l3_vrms: vrms[2],
+            l3_power_factor: 1.0,
+            timestamp,
+        }
+    }
+
+    /// Feeds one frame per second, returning the seconds at which alerts were raised.
+    fn run(engine: &mut RuleEngine, frames: &[RpictFrame]) -> Vec<(i64, AlertState)> {
+        let start = frames[0].timestamp;
+        frames
+            .iter()
+            .flat_map(|frame| engine.update_rpict(frame))
+            .map(|alert| ((alert.timestamp - start).num_seconds(), alert.state))
+            .collect()
+    }
+
+    #[test]
+    fn test_rule_for_duration_and_hysteresis() {
+        // Given "total apparent power > 9 kW for 30 s", resolving below 8.5 kW
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut engine = RuleEngine::new(
+            &[AlertRule {
+                for_secs: 30,
+                hysteresis: 500.0,
+                ..rule("high_power", Metric::TotalApparentPower, Some(9000.0), None)
+            }],
+            now,
+        );
+        let power = |second: i64| match second {
+            0..=9 => 9500.0,   // too short to fire
+            10..=11 => 1000.0, // resets the pending duration
+            12..=59 => 9600.0, // fires after 30 s, at 42
+            60..=69 => 8800.0, // within the hysteresis margin
+            _ => 3000.0,       // resolves at 70
+        };
+        let frames = (0..80)
+            .map(|second| {
+                let power = power(second) / 3.0;
+                rpict([power; 3], [230.0; 3], now + Duration::seconds(second))
+            })
+            .collect::<Vec<_>>();
+        // When
+        let alerts = run(&mut engine, &frames);
+        // Then
+        assert_eq!(alerts, vec![(42, AlertState::Firing), (70, AlertState::Resolved)]);
+    }
+
+    #[test]
+    fn test_rule_cooldown() {
+        // Given "l2 Vrms < 207 V", not raised again within 60 s
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut engine = RuleEngine::new(
+            &[AlertRule {
+                cooldown_secs: 60,
+                ..rule("low_voltage", Metric::L2Vrms, None, Some(207.0))
+            }],
+            now,
+        );
+        let vrms = |second: i64| if second % 20 < 5 { 205.0 } else { 230.0 };
+        let frames = (0..100)
+            .map(|second| rpict([0.0; 3], [230.0, vrms(second), 230.0], now + Duration::seconds(second)))
+            .collect::<Vec<_>>();
+        // When
+        let alerts = run(&mut engine, &frames);
+        // Then the dips at 20 and 40 are swallowed, the one at 60 fires as the cooldown ends
+        assert_eq!(
+            alerts,
+            vec![
+                (0, AlertState::Firing),
+                (5, AlertState::Resolved),
+                (60, AlertState::Firing),
+                (65, AlertState::Resolved),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rule_power_factor_message() {
+        // Given "power factor < 0.5 for 10 min"
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut engine = RuleEngine::new(
+            &[AlertRule {
+                for_secs: 600,
+                ..rule("poor_power_factor", Metric::L1PowerFactor, None, Some(0.5))
+            }],
+            now,
+        );
+        let frame = |minute: i64| RpictFrame {
+            l1_power_factor: 0.42,
+            ..rpict([500.0; 3], [230.0; 3], now + Duration::minutes(minute))
+        };
+        // When
+        let alerts = (0..=10)
+            .flat_map(|minute| engine.update_rpict(&frame(minute)))
+            .collect::<Vec<_>>();
+        // Then
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].timestamp, now + Duration::minutes(10));
+        assert_eq!(alerts[0].message, "l1_power_factor at 0.4, below 0.5 for 600 s");
+    }
+
+    #[test]
+    fn test_rule_frame_age() {
+        // Given "no Linky frame for 5 min"
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut engine = RuleEngine::new(
+            &[rule("linky_silent", Metric::LinkyFrameAgeSecs, Some(300.0), None)],
+            now,
+        );
+        let linky = |timestamp| LinkyFrame {
+            timestamp,
+            ..LinkyFrame::default()
+        };
+        // When & Then
+        assert!(engine.tick(now + Duration::seconds(300)).is_empty());
+        engine.update_linky(&linky(now + Duration::seconds(200)));
+        assert!(engine.tick(now + Duration::seconds(400)).is_empty());
+        let alerts = engine.tick(now + Duration::seconds(501));
+        assert_eq!(alerts[0].state, AlertState::Firing);
+        assert_eq!(alerts[0].message, "linky_frame_age_secs at 301.0, above 300");
+        engine.update_linky(&linky(now + Duration::seconds(510)));
+        let alerts = engine.tick(now + Duration::seconds(511));
+        assert_eq!(alerts[0].state, AlertState::Resolved);
+        // RPICT frames don't evaluate frame ages
+        assert!(engine
+            .update_rpict(&rpict([0.0; 3], [230.0; 3], now + Duration::seconds(900)))
+            .is_empty());
+    }
+}
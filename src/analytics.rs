@@ -0,0 +1,7 @@
+pub mod consumption;
+pub mod cost;
+pub mod energy;
+pub mod imbalance;
+pub mod peak;
+pub mod reactive;
+pub mod voltage;
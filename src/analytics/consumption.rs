@@ -0,0 +1,366 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, DurationRound, Months, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::analytics::peak::PeakPeriod;
+
+/// Energy drawn from the grid, per tariff period.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TariffEnergy {
+    pub hc_wh: u64,
+    pub hp_wh: u64,
+}
+
+impl TariffEnergy {
+    fn add(&mut self, other: TariffEnergy) {
+        self.hc_wh += other.hc_wh;
+        self.hp_wh += other.hp_wh;
+    }
+
+    pub fn total_wh(&self) -> u64 {
+        self.hc_wh + self.hp_wh
+    }
+}
+
+/// Energy drawn during a period.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct PeriodConsumption {
+    pub start: Option<NaiveDate>,
+    pub energy: TariffEnergy,
+}
+
+impl PeriodConsumption {
+    /// Starts a new period, returning the one that just ended.
+    fn roll(&mut self, start: Option<NaiveDate>) -> PeriodConsumption {
+        std::mem::replace(
+            self,
+            PeriodConsumption {
+                start,
+                ..Self::default()
+            },
+        )
+    }
+}
+
+/// Energy drawn during one minute, the last hour being a sliding window of them.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct MinuteConsumption {
+    pub start: DateTime<Utc>,
+    pub energy: TariffEnergy,
+}
+
+/// Last index read, the reference of the next delta.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct MeterIndex {
+    pub adco: String,
+    pub hchc: u32,
+    pub hchp: u32,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Consumption derived from the Linky cumulative indices.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct Consumption {
+    pub today: PeriodConsumption,
+    pub yesterday: PeriodConsumption,
+    pub this_month: PeriodConsumption,
+    pub last_month: PeriodConsumption,
+    pub minutes: VecDeque<MinuteConsumption>,
+    /// Start of the continuous reading window used for the last hour average power
+    pub window_start: Option<DateTime<Utc>>,
+    pub last_index: Option<MeterIndex>,
+}
+
+/// Derived values, as published to the HMI and sinks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConsumptionSummary {
+    pub last_hour: TariffEnergy,
+    pub today: TariffEnergy,
+    pub yesterday: TariffEnergy,
+    pub this_month: TariffEnergy,
+    pub last_month: TariffEnergy,
+    /// Average power over the last hour, or since readings resumed if later
+    pub average_power_w: Option<f32>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Consumption {
+    /// Longest interval between two frames still counted in the last hour, the energy drawn during
+    /// longer gaps is only added to the day and month totals.
+    const MAX_GAP_SECS: i64 = 300;
+
+    fn hour() -> Duration {
+        Duration::hours(1)
+    }
+
+    /// Adds the energy drawn since the previous index, which is returned unless the indices were reset
+    /// or the frames stopped across a day change.
+    pub fn update(&mut self, adco: &str, hchc: u32, hchp: u32, timestamp: DateTime<Utc>) -> Option<TariffEnergy> {
+        self.roll_periods(timestamp);
+        let index = MeterIndex {
+            adco: adco.to_string(),
+            hchc,
+            hchp,
+            timestamp,
+        };
+        let Some(last) = self.last_index.replace(index) else {
+            self.window_start = Some(timestamp);
+            return None;
+        };
+        let elapsed = timestamp - last.timestamp;
+        if elapsed <= Duration::zero() {
+            // out of order or duplicated frame, keep the most recent reference
+            self.last_index = Some(last);
+            return None;
+        }
+        if last.adco != adco || hchc < last.hchc || hchp < last.hchp {
+            log::warn!(
+                "Linky indices went from {}/{} ({}) to {hchc}/{hchp} ({adco}), restarting consumption",
+                last.hchc,
+                last.hchp,
+                last.adco
+            );
+            self.minutes.clear();
+            self.window_start = Some(timestamp);
+            return None;
+        }
+        let delta = TariffEnergy {
+            hc_wh: (hchc - last.hchc) as u64,
+            hp_wh: (hchp - last.hchp) as u64,
+        };
+        let is_gap = elapsed > Duration::seconds(Self::MAX_GAP_SECS);
+        if is_gap && PeakPeriod::Day.start(last.timestamp) != PeakPeriod::Day.start(timestamp) {
+            // the share of each day is unknown, rather than crediting it all to the new day
+            log::warn!(
+                "No Linky frame from {} to {timestamp}, dropping {} Wh HC and {} Wh HP drawn across the day change",
+                last.timestamp,
+                delta.hc_wh,
+                delta.hp_wh
+            );
+            self.minutes.clear();
+            self.window_start = Some(timestamp);
+            return None;
+        }
+        self.today.energy.add(delta);
+        self.this_month.energy.add(delta);
+        if is_gap {
+            self.minutes.clear();
+            self.window_start = Some(timestamp);
+            return Some(delta);
+        }
+        let minute = timestamp.duration_trunc(Duration::minutes(1)).unwrap_or(timestamp);
+        match self.minutes.back_mut() {
+            Some(last_minute) if last_minute.start == minute => last_minute.energy.add(delta),
+            _ => self.minutes.push_back(MinuteConsumption {
+                start: minute,
+                energy: delta,
+            }),
+        }
+        while self
+            .minutes
+            .front()
+            .is_some_and(|oldest| oldest.start <= timestamp - Self::hour())
+        {
+            self.minutes.pop_front();
+        }
+        Some(delta)
+    }
+
+    /// Moves today and this month totals to yesterday and last month when the period changes.
+    fn roll_periods(&mut self, timestamp: DateTime<Utc>) {
+        let today = PeakPeriod::Day.start(timestamp);
+        if today != self.today.start {
+            let ended = self.today.roll(today);
+            let yesterday = today.and_then(|today| today.pred_opt());
+            self.yesterday = if ended.start.is_some() && ended.start == yesterday {
+                ended
+            } else {
+                PeriodConsumption {
+                    start: yesterday,
+                    ..PeriodConsumption::default()
+                }
+            };
+        }
+        let this_month = PeakPeriod::Month.start(timestamp);
+        if this_month != self.this_month.start {
+            let ended = self.this_month.roll(this_month);
+            let last_month = previous_month(this_month);
+            self.last_month = if ended.start.is_some() && ended.start == last_month {
+                ended
+            } else {
+                PeriodConsumption {
+                    start: last_month,
+                    ..PeriodConsumption::default()
+                }
+            };
+        }
+    }
+
+    /// Summarizes the consumption as of `now`, nothing until an index was read.
+    pub fn summary(&self, now: DateTime<Utc>) -> Option<ConsumptionSummary> {
+        let last_index = self.last_index.as_ref()?;
+        let mut last_hour = TariffEnergy::default();
+        self.minutes
+            .iter()
+            .filter(|minute| minute.start > now - Self::hour())
+            .for_each(|minute| last_hour.add(minute.energy));
+        let average_power_w = self.window_start.and_then(|window_start| {
+            let window = (now - window_start).min(Self::hour());
+            let hours = window.num_milliseconds() as f32 / 3_600_000.0;
+            (hours > 0.0).then(|| last_hour.total_wh() as f32 / hours)
+        });
+        // periods that ended without any frame since are reported as past ones
+        let today = PeakPeriod::Day.start(now);
+        let (today_energy, yesterday_energy) = if self.today.start == today {
+            (self.today.energy, self.yesterday.energy)
+        } else if self.today.start.is_some() && self.today.start == today.and_then(|today| today.pred_opt()) {
+            (TariffEnergy::default(), self.today.energy)
+        } else {
+            (TariffEnergy::default(), TariffEnergy::default())
+        };
+        let this_month = PeakPeriod::Month.start(now);
+        let (this_month_energy, last_month_energy) = if self.this_month.start == this_month {
+            (self.this_month.energy, self.last_month.energy)
+        } else if self.this_month.start.is_some() && self.this_month.start == previous_month(this_month) {
+            (TariffEnergy::default(), self.this_month.energy)
+        } else {
+            (TariffEnergy::default(), TariffEnergy::default())
+        };
+        Some(ConsumptionSummary {
+            last_hour,
+            today: today_energy,
+            yesterday: yesterday_energy,
+            this_month: this_month_energy,
+            last_month: last_month_energy,
+            average_power_w,
+            timestamp: last_index.timestamp.max(now),
+        })
+    }
+}
+
+fn previous_month(month: Option<NaiveDate>) -> Option<NaiveDate> {
+    month.and_then(|month| month.checked_sub_months(Months::new(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+
+    use super::*;
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, min: u32) -> DateTime<Utc> {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, min, 0)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_consumption_last_hour() {
+        // Given
+        let now = local(2023, 3, 14, 12, 0);
+        let mut consumption = Consumption::default();
+        // When
+        consumption.update("041876097767", 1000, 5000, now - Duration::minutes(90));
+        consumption.update("041876097767", 1100, 5000, now - Duration::minutes(89));
+        (0..=88).for_each(|minute| {
+            let timestamp = now - Duration::minutes(88) + Duration::minutes(minute);
+            consumption.update("041876097767", 1100, 5000 + 20 * minute as u32, timestamp);
+        });
+        let summary = consumption.summary(now).unwrap();
+        // Then
+        assert_eq!(summary.last_hour, TariffEnergy { hc_wh: 0, hp_wh: 1200 });
+        assert_eq!(summary.average_power_w, Some(1200.0));
+        assert_eq!(
+            summary.today,
+            TariffEnergy {
+                hc_wh: 100,
+                hp_wh: 1760
+            }
+        );
+        assert_eq!(summary.yesterday, TariffEnergy::default());
+        assert_eq!(summary.this_month.total_wh(), 1860);
+    }
+
+    #[test]
+    fn test_consumption_period_rollover() {
+        // Given
+        let day = local(2023, 3, 31, 22, 0);
+        let mut consumption = Consumption::default();
+        consumption.update("041876097767", 1000, 5000, day);
+        consumption.update("041876097767", 1500, 5000, day + Duration::minutes(1));
+        // When
+        consumption.update("041876097767", 1500, 5000, day + Duration::hours(3));
+        consumption.update(
+            "041876097767",
+            1800,
+            5100,
+            day + Duration::hours(3) + Duration::minutes(1),
+        );
+        let summary = consumption
+            .summary(day + Duration::hours(3) + Duration::minutes(1))
+            .unwrap();
+        // Then
+        assert_eq!(summary.today, TariffEnergy { hc_wh: 300, hp_wh: 100 });
+        assert_eq!(summary.yesterday, TariffEnergy { hc_wh: 500, hp_wh: 0 });
+        assert_eq!(summary.this_month, TariffEnergy { hc_wh: 300, hp_wh: 100 });
+        assert_eq!(summary.last_month, TariffEnergy { hc_wh: 500, hp_wh: 0 });
+        // When no frame is received for two days
+        let summary = consumption
+            .summary(day + Duration::days(2) + Duration::hours(3))
+            .unwrap();
+        // Then
+        assert_eq!(summary.today, TariffEnergy::default());
+        assert_eq!(summary.yesterday, TariffEnergy::default());
+        assert_eq!(summary.this_month, TariffEnergy { hc_wh: 300, hp_wh: 100 });
+        assert_eq!(summary.last_hour, TariffEnergy::default());
+    }
+
+    #[test]
+    fn test_consumption_gap_across_midnight() {
+        // Given
+        let evening = local(2023, 3, 31, 22, 0);
+        let mut consumption = Consumption::default();
+        consumption.update("041876097767", 1000, 5000, evening);
+        consumption.update("041876097767", 1500, 5000, evening + Duration::minutes(1));
+        // When frames stop for 5 hours, across midnight and the month change
+        let dropped = consumption.update("041876097767", 2100, 5200, evening + Duration::hours(5));
+        consumption.update(
+            "041876097767",
+            2200,
+            5200,
+            evening + Duration::hours(5) + Duration::minutes(1),
+        );
+        let summary = consumption
+            .summary(evening + Duration::hours(5) + Duration::minutes(1))
+            .unwrap();
+        // Then
+        assert_eq!(dropped, None);
+        assert_eq!(summary.today, TariffEnergy { hc_wh: 100, hp_wh: 0 });
+        assert_eq!(summary.yesterday, TariffEnergy { hc_wh: 500, hp_wh: 0 });
+        assert_eq!(summary.this_month, TariffEnergy { hc_wh: 100, hp_wh: 0 });
+        assert_eq!(summary.last_month, TariffEnergy { hc_wh: 500, hp_wh: 0 });
+        assert_eq!(summary.last_hour, TariffEnergy { hc_wh: 100, hp_wh: 0 });
+    }
+
+    #[test]
+    fn test_consumption_meter_reset_and_gap() {
+        // Given
+        let now = local(2023, 3, 14, 12, 0);
+        let mut consumption = Consumption::default();
+        consumption.update("041876097767", 1000, 5000, now);
+        consumption.update("041876097767", 1010, 5000, now + Duration::minutes(1));
+        // When the meter is replaced
+        consumption.update("041876097767", 20, 0, now + Duration::minutes(2));
+        consumption.update("041876097767", 30, 0, now + Duration::minutes(3));
+        // and frames are missing for a while
+        consumption.update("041876097767", 130, 0, now + Duration::minutes(30));
+        let summary = consumption.summary(now + Duration::minutes(30)).unwrap();
+        // Then
+        assert_eq!(summary.today, TariffEnergy { hc_wh: 120, hp_wh: 0 });
+        assert_eq!(summary.last_hour, TariffEnergy::default());
+        assert_eq!(summary.average_power_w, None);
+    }
+}
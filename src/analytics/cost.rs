@@ -0,0 +1,434 @@
+use chrono::{DateTime, Datelike, Local, Months, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::analytics::consumption::TariffEnergy;
+use crate::driver::linky::{TariffPeriod, TempoColor, TempoIndices};
+use crate::settings::{EjpPrices, PeriodPrices, Tariff, TariffPrices, TempoPrices};
+
+/// Cost of the energy drawn during a period, the subscription being added when summarizing.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct PeriodCost {
+    pub start: Option<NaiveDate>,
+    pub energy_cost: f64,
+}
+
+impl PeriodCost {
+    fn add(&mut self, start: NaiveDate, energy_cost: f64) {
+        if self.start != Some(start) {
+            *self = PeriodCost {
+                start: Some(start),
+                energy_cost: 0.0,
+            };
+        }
+        self.energy_cost += energy_cost;
+    }
+
+    /// Energy cost if the period is the one starting at `start`, none otherwise.
+    fn energy_cost(&self, start: NaiveDate) -> f64 {
+        if self.start == Some(start) {
+            self.energy_cost
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Energy drawn per Tempo day color: blue, white and red.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TempoEnergy {
+    pub hc_wh: [u64; 3],
+    pub hp_wh: [u64; 3],
+}
+
+impl TempoEnergy {
+    /// Energy drawn between two readings, none if an index went backwards.
+    fn between(last: &TempoIndices, indices: &TempoIndices) -> Option<Self> {
+        let delta = |last: [u32; 3], current: [u32; 3]| -> Option<[u64; 3]> {
+            let mut delta = [0; 3];
+            for (index, value) in delta.iter_mut().enumerate() {
+                *value = current[index].checked_sub(last[index])? as u64;
+            }
+            Some(delta)
+        };
+        Some(TempoEnergy {
+            hc_wh: delta(last.hc, indices.hc)?,
+            hp_wh: delta(last.hp, indices.hp)?,
+        })
+    }
+
+    fn cost(&self, prices: &TempoPrices) -> f64 {
+        [TempoColor::Blue, TempoColor::White, TempoColor::Red]
+            .iter()
+            .enumerate()
+            .map(|(index, color)| {
+                let prices = prices.of(*color);
+                self.hc_wh[index] as f64 / 1000.0 * prices.hc_per_kwh
+                    + self.hp_wh[index] as f64 / 1000.0 * prices.hp_per_kwh
+            })
+            .sum()
+    }
+}
+
+/// Running electricity cost, priced from the Linky index deltas.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct Cost {
+    pub today: PeriodCost,
+    pub this_month: PeriodCost,
+    pub billing: PeriodCost,
+    /// Last Tempo indices read, the reference of the next delta
+    pub last_tempo_indices: Option<TempoIndices>,
+    #[serde(skip)]
+    warned_no_tempo_prices: bool,
+    #[serde(skip)]
+    warned_no_ejp_prices: bool,
+}
+
+/// Costs as of a date, subscription included, as published to the HMI and sinks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CostSummary {
+    pub currency: String,
+    pub today: f64,
+    pub this_month: f64,
+    pub billing_period: f64,
+    /// Billing period cost if consumption goes on at the same rate
+    pub billing_projection: f64,
+    pub billing_start: NaiveDate,
+    pub billing_end: NaiveDate,
+    pub prices: Option<TariffPrices>,
+    pub tariff_period: TariffPeriod,
+    pub tempo_color: Option<TempoColor>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl CostSummary {
+    /// HC/HP prices in effect, those of the day color on Tempo meters, none when EJP prices apply.
+    pub fn period_prices(&self) -> Option<PeriodPrices> {
+        if self.ejp_prices().is_some() {
+            return None;
+        }
+        self.prices
+            .as_ref()
+            .map(|prices| prices.period_prices(self.tempo_color))
+    }
+
+    /// Normal hours and peak days prices in effect on EJP meters.
+    pub fn ejp_prices(&self) -> Option<EjpPrices> {
+        match self.tariff_period {
+            TariffPeriod::HN | TariffPeriod::PM => self.prices.as_ref().and_then(|prices| prices.ejp.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl Cost {
+    /// Prices the energy drawn at `timestamp`, energy drawn before the first price date is free.
+    /// On Tempo meters, the energy of each day color is priced from the deltas of its indices.
+    /// On EJP meters, the HC/HP energy is the normal hours and peak days one.
+    pub fn add(
+        &mut self,
+        tariff: &Tariff,
+        energy: Option<TariffEnergy>,
+        tempo: Option<TempoIndices>,
+        is_ejp: bool,
+        timestamp: DateTime<Utc>,
+    ) {
+        let tempo_energy = tempo.and_then(|indices| {
+            let last = self.last_tempo_indices.replace(indices)?;
+            TempoEnergy::between(&last, &indices)
+        });
+        // none when consumption restarted, from the first reading or an index reset
+        let Some(energy) = energy else {
+            return;
+        };
+        let date = timestamp.with_timezone(&Local).date_naive();
+        let Some(prices) = prices_at(tariff, date) else {
+            return;
+        };
+        let cost = match (&prices.tempo, tempo_energy) {
+            (Some(tempo_prices), Some(tempo_energy)) => tempo_energy.cost(tempo_prices),
+            // first Tempo reading, no reference to split the energy by color
+            (Some(_), None) if tempo.is_some() => return,
+            (tempo_prices, tempo_energy) => {
+                if tempo_prices.is_none() && tempo_energy.is_some() && !self.warned_no_tempo_prices {
+                    log::warn!("Tempo meter but no tariff Tempo prices in effect, pricing at the HC/HP ones");
+                    self.warned_no_tempo_prices = true;
+                }
+                let (hc_per_kwh, hp_per_kwh) = match &prices.ejp {
+                    Some(ejp_prices) if is_ejp => (ejp_prices.hn_per_kwh, ejp_prices.pm_per_kwh),
+                    _ => {
+                        if is_ejp && !self.warned_no_ejp_prices {
+                            log::warn!("EJP meter but no tariff EJP prices in effect, pricing at the HC/HP ones");
+                            self.warned_no_ejp_prices = true;
+                        }
+                        (prices.hc_per_kwh, prices.hp_per_kwh)
+                    }
+                };
+                energy.hc_wh as f64 / 1000.0 * hc_per_kwh + energy.hp_wh as f64 / 1000.0 * hp_per_kwh
+            }
+        };
+        self.today.add(date, cost);
+        self.this_month.add(month_start(date), cost);
+        self.billing.add(billing_start(tariff, date), cost);
+    }
+
+    pub fn summary(
+        &self,
+        tariff: &Tariff,
+        tariff_period: TariffPeriod,
+        tempo_color: Option<TempoColor>,
+        now: DateTime<Utc>,
+    ) -> CostSummary {
+        let now_local = now.with_timezone(&Local).naive_local();
+        let date = now_local.date();
+        let prices = prices_at(tariff, date).cloned();
+        let daily_fee = prices
+            .as_ref()
+            .map_or(0.0, |prices| prices.subscription_per_month * 12.0 / 365.0);
+        // days are counted as started, as on bills
+        let days_since = |start: NaiveDate| (date - start).num_days() as f64 + 1.0;
+        let month_start = month_start(date);
+        let billing_start = billing_start(tariff, date);
+        let billing_end = billing_start + Months::new(tariff.billing_months);
+        let billing_days = (billing_end - billing_start).num_days() as f64;
+        let billing_energy_cost = self.billing.energy_cost(billing_start);
+        // the rate is measured over at least a day, so that a new period isn't projected from a few minutes
+        let elapsed_days =
+            ((now_local - billing_start.and_time(Default::default())).num_seconds() as f64 / 86400.0).max(1.0);
+        CostSummary {
+            currency: tariff.currency.clone(),
+            today: self.today.energy_cost(date) + daily_fee,
+            this_month: self.this_month.energy_cost(month_start) + daily_fee * days_since(month_start),
+            billing_period: billing_energy_cost + daily_fee * days_since(billing_start),
+            billing_projection: billing_energy_cost / elapsed_days * billing_days + daily_fee * billing_days,
+            billing_start,
+            billing_end,
+            prices,
+            tariff_period,
+            tempo_color,
+            timestamp: now,
+        }
+    }
+}
+
+/// Prices in effect on a date, none before the first effective date.
+fn prices_at(tariff: &Tariff, date: NaiveDate) -> Option<&TariffPrices> {
+    tariff.prices.iter().rev().find(|prices| prices.from <= date)
+}
+
+fn month_start(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap_or(date)
+}
+
+/// Start of the billing period a date belongs to, periods following each other from `billing_start`.
+fn billing_start(tariff: &Tariff, date: NaiveDate) -> NaiveDate {
+    let anchor = tariff.billing_start;
+    let month_index = |date: NaiveDate| date.year() * 12 + date.month0() as i32;
+    let mut months = month_index(date) - month_index(anchor);
+    if date.day() < anchor.day() {
+        months -= 1;
+    }
+    let periods = months.div_euclid(tariff.billing_months as i32);
+    let offset = periods * tariff.billing_months as i32;
+    if offset >= 0 {
+        anchor + Months::new(offset as u32)
+    } else {
+        anchor - Months::new(offset.unsigned_abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn tariff() -> Tariff {
+        let prices = |from: &str, hc_per_kwh, hp_per_kwh| TariffPrices {
+            from: from.parse().unwrap(),
+            subscription_per_month: 36.5,
+            hc_per_kwh,
+            hp_per_kwh,
+            tempo: None,
+            ejp: None,
+        };
+        Tariff {
+            currency: "EUR".to_string(),
+            billing_start: "2023-01-15".parse().unwrap(),
+            billing_months: 2,
+            prices: vec![prices("2023-02-01", 0.10, 0.20), prices("2023-03-10", 0.20, 0.40)],
+        }
+    }
+
+    fn local(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn test_billing_start() {
+        let tariff = tariff();
+        assert_eq!(billing_start(&tariff, date("2023-01-15")), date("2023-01-15"));
+        assert_eq!(billing_start(&tariff, date("2023-03-14")), date("2023-01-15"));
+        assert_eq!(billing_start(&tariff, date("2023-03-15")), date("2023-03-15"));
+        assert_eq!(billing_start(&tariff, date("2024-01-20")), date("2024-01-15"));
+        assert_eq!(billing_start(&tariff, date("2022-12-01")), date("2022-11-15"));
+    }
+
+    #[test]
+    fn test_prices_at() {
+        let tariff = tariff();
+        assert_eq!(prices_at(&tariff, date("2023-01-31")), None);
+        assert_eq!(prices_at(&tariff, date("2023-02-01")).unwrap().hc_per_kwh, 0.10);
+        assert_eq!(prices_at(&tariff, date("2023-03-10")).unwrap().hc_per_kwh, 0.20);
+    }
+
+    #[test]
+    fn test_cost_summary() {
+        // Given
+        let tariff = tariff();
+        let mut cost = Cost::default();
+        // When
+        cost.add(
+            &tariff,
+            Some(TariffEnergy { hc_wh: 1000, hp_wh: 0 }),
+            None,
+            false,
+            local(2023, 1, 20, 12),
+        );
+        cost.add(
+            &tariff,
+            Some(TariffEnergy {
+                hc_wh: 10_000,
+                hp_wh: 5000,
+            }),
+            None,
+            false,
+            local(2023, 3, 9, 12),
+        );
+        cost.add(
+            &tariff,
+            Some(TariffEnergy {
+                hc_wh: 1000,
+                hp_wh: 1000,
+            }),
+            None,
+            false,
+            local(2023, 3, 10, 12),
+        );
+        let summary = cost.summary(&tariff, TariffPeriod::HP, None, local(2023, 3, 10, 12));
+        // Then
+        let daily_fee = 36.5 * 12.0 / 365.0;
+        assert_close(summary.today, 0.6 + daily_fee);
+        assert_close(summary.this_month, 2.6 + 10.0 * daily_fee);
+        assert_close(summary.billing_period, 2.6 + 55.0 * daily_fee);
+        assert_eq!(summary.billing_start, date("2023-01-15"));
+        assert_eq!(summary.billing_end, date("2023-03-15"));
+        assert_close(summary.billing_projection, 2.6 / 54.5 * 59.0 + 59.0 * daily_fee);
+        assert_eq!(summary.prices.unwrap().hp_per_kwh, 0.40);
+        // When the day is over
+        let summary = cost.summary(&tariff, TariffPeriod::HC, None, local(2023, 3, 11, 1));
+        // Then
+        assert_close(summary.today, daily_fee);
+    }
+
+    #[test]
+    fn test_cost_tempo() {
+        // Given
+        let mut tariff = tariff();
+        let period_prices = |hc_per_kwh, hp_per_kwh| PeriodPrices { hc_per_kwh, hp_per_kwh };
+        tariff.prices[1].tempo = Some(TempoPrices {
+            blue: period_prices(0.10, 0.20),
+            white: period_prices(0.30, 0.40),
+            red: period_prices(0.50, 1.00),
+        });
+        let indices = |hc, hp| TempoIndices { hc, hp };
+        let energy = |hc_wh, hp_wh| Some(TariffEnergy { hc_wh, hp_wh });
+        let mut cost = Cost::default();
+        // When
+        cost.add(
+            &tariff,
+            None,
+            Some(indices([1000, 0, 0], [0, 0, 0])),
+            false,
+            local(2023, 3, 10, 5),
+        );
+        // 1 kWh blue HC then 2 kWh red HP
+        cost.add(
+            &tariff,
+            energy(1000, 0),
+            Some(indices([2000, 0, 0], [0, 0, 0])),
+            false,
+            local(2023, 3, 10, 6),
+        );
+        cost.add(
+            &tariff,
+            energy(0, 2000),
+            Some(indices([2000, 0, 0], [0, 0, 2000])),
+            false,
+            local(2023, 3, 10, 12),
+        );
+        let summary = cost.summary(&tariff, TariffPeriod::HP, Some(TempoColor::Red), local(2023, 3, 10, 12));
+        // Then
+        let daily_fee = 36.5 * 12.0 / 365.0;
+        assert_close(summary.today, 0.1 + 2.0 + daily_fee);
+        assert_eq!(summary.period_prices().unwrap().hp_per_kwh, 1.00);
+        // When the indices went backwards, eg. on a meter change
+        cost.add(
+            &tariff,
+            None,
+            Some(indices([0, 0, 0], [0, 0, 0])),
+            false,
+            local(2023, 3, 10, 13),
+        );
+        cost.add(
+            &tariff,
+            energy(0, 1000),
+            Some(indices([0, 0, 0], [0, 1000, 0])),
+            false,
+            local(2023, 3, 10, 14),
+        );
+        let summary = cost.summary(&tariff, TariffPeriod::HP, None, local(2023, 3, 10, 14));
+        // Then
+        assert_close(summary.today, 0.1 + 2.0 + 0.4 + daily_fee);
+        assert_eq!(summary.period_prices().unwrap().hp_per_kwh, 0.40);
+    }
+
+    #[test]
+    fn test_cost_ejp() {
+        // Given
+        let mut tariff = tariff();
+        tariff.prices[1].ejp = Some(EjpPrices {
+            hn_per_kwh: 0.10,
+            pm_per_kwh: 1.00,
+        });
+        let energy = |hc_wh, hp_wh| Some(TariffEnergy { hc_wh, hp_wh });
+        let mut cost = Cost::default();
+        // When 1 kWh in normal hours then 2 kWh on a peak day
+        cost.add(&tariff, energy(1000, 0), None, true, local(2023, 3, 10, 6));
+        cost.add(&tariff, energy(0, 2000), None, true, local(2023, 3, 10, 12));
+        let summary = cost.summary(&tariff, TariffPeriod::PM, None, local(2023, 3, 10, 12));
+        // Then
+        let daily_fee = 36.5 * 12.0 / 365.0;
+        assert_close(summary.today, 0.1 + 2.0 + daily_fee);
+        assert_eq!(summary.ejp_prices().unwrap().pm_per_kwh, 1.00);
+        assert_eq!(summary.period_prices(), None);
+        // When no EJP prices are in effect, at the HC/HP ones
+        cost.add(&tariff, energy(1000, 1000), None, true, local(2023, 3, 9, 12));
+        let summary = cost.summary(&tariff, TariffPeriod::HN, None, local(2023, 3, 9, 12));
+        // Then
+        assert_close(summary.today, 0.1 + 0.2 + daily_fee);
+        assert_eq!(summary.ejp_prices(), None);
+        assert_eq!(summary.period_prices().unwrap().hp_per_kwh, 0.20);
+    }
+}
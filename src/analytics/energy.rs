@@ -0,0 +1,218 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::analytics::peak::PeakPeriod;
+
+/// Energy flowing through a line, imported (positive power) and exported (negative power) being summed apart.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct LineEnergy {
+    pub import_wh: f64,
+    pub export_wh: f64,
+}
+
+impl LineEnergy {
+    fn add(&mut self, other: LineEnergy) {
+        self.import_wh += other.import_wh;
+        self.export_wh += other.export_wh;
+    }
+}
+
+/// Energy of each line during a period.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct PeriodEnergy {
+    pub start: Option<NaiveDate>,
+    pub lines: [LineEnergy; 3],
+}
+
+impl PeriodEnergy {
+    fn add(&mut self, period: PeakPeriod, energies: [LineEnergy; 3], timestamp: DateTime<Utc>) {
+        let start = period.start(timestamp);
+        if start != self.start {
+            *self = PeriodEnergy {
+                start,
+                ..Self::default()
+            };
+        }
+        for (line, energy) in self.lines.iter_mut().zip(energies) {
+            line.add(energy);
+        }
+    }
+
+    pub fn total(&self) -> LineEnergy {
+        let mut total = LineEnergy::default();
+        self.lines.iter().for_each(|line| total.add(*line));
+        total
+    }
+}
+
+/// Last real power values, the start of the next integration step.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct PowerSample {
+    pub real_power: [f32; 3],
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Line energy integrated from the real power, over the day, the month and the lifetime of the device.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct Energy {
+    pub day: PeriodEnergy,
+    pub month: PeriodEnergy,
+    pub lifetime: PeriodEnergy,
+    pub last_sample: Option<PowerSample>,
+}
+
+impl Energy {
+    /// Longest interval between two frames still integrated, longer ones are counted as outages.
+    const MAX_GAP_SECS: i64 = 60;
+
+    /// Integrates the real power since the previous sample, with the trapezoidal rule.
+    pub fn update(&mut self, real_power: [f32; 3], timestamp: DateTime<Utc>) {
+        if real_power.iter().any(|value| !value.is_finite()) {
+            return;
+        }
+        if let Some(last) = &self.last_sample {
+            let elapsed = timestamp - last.timestamp;
+            if elapsed <= Duration::zero() {
+                // out of order or duplicated frame
+                return;
+            }
+            if elapsed <= Duration::seconds(Self::MAX_GAP_SECS) {
+                let hours = elapsed.num_milliseconds() as f64 / 3_600_000.0;
+                let mut energies = [LineEnergy::default(); 3];
+                for ((energy, start), end) in energies.iter_mut().zip(last.real_power).zip(real_power) {
+                    *energy = integrate(start as f64, end as f64, hours);
+                }
+                self.day.add(PeakPeriod::Day, energies, timestamp);
+                self.month.add(PeakPeriod::Month, energies, timestamp);
+                self.lifetime.add(PeakPeriod::AllTime, energies, timestamp);
+            }
+        }
+        self.last_sample = Some(PowerSample { real_power, timestamp });
+    }
+
+    /// Clears all totals, including the lifetime ones.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.last_sample.as_ref().map(|sample| sample.timestamp)
+    }
+}
+
+/// Area under a linear power ramp, split at the zero crossing between import and export.
+fn integrate(start: f64, end: f64, hours: f64) -> LineEnergy {
+    let (import_wh, export_wh) = if start >= 0.0 && end >= 0.0 {
+        ((start + end) / 2.0 * hours, 0.0)
+    } else if start <= 0.0 && end <= 0.0 {
+        (0.0, -(start + end) / 2.0 * hours)
+    } else {
+        let crossing = start / (start - end) * hours;
+        let positive = start.max(end);
+        let negative = start.min(end);
+        let positive_hours = if start > 0.0 { crossing } else { hours - crossing };
+        (
+            positive / 2.0 * positive_hours,
+            -negative / 2.0 * (hours - positive_hours),
+        )
+    };
+    LineEnergy { import_wh, export_wh }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_integrate() {
+        assert_eq!(
+            integrate(1000.0, 3000.0, 0.5),
+            LineEnergy {
+                import_wh: 1000.0,
+                export_wh: 0.0
+            }
+        );
+        assert_eq!(
+            integrate(-200.0, -200.0, 1.0),
+            LineEnergy {
+                import_wh: 0.0,
+                export_wh: 200.0
+            }
+        );
+        // crosses zero at mid-interval
+        assert_eq!(
+            integrate(100.0, -100.0, 1.0),
+            LineEnergy {
+                import_wh: 25.0,
+                export_wh: 25.0
+            }
+        );
+        assert_eq!(
+            integrate(-100.0, 300.0, 1.0),
+            LineEnergy {
+                import_wh: 112.5,
+                export_wh: 12.5
+            }
+        );
+    }
+
+    #[test]
+    fn test_energy_update() {
+        // Given
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut energy = Energy::default();
+        // When
+        energy.update([3600.0, -360.0, 0.0], now);
+        energy.update([3600.0, -360.0, 0.0], now + Duration::seconds(10));
+        energy.update([3600.0, -360.0, 0.0], now + Duration::seconds(5));
+        energy.update([f32::NAN, 0.0, 0.0], now + Duration::seconds(15));
+        // Then
+        assert_eq!(
+            energy.day.lines,
+            [
+                LineEnergy {
+                    import_wh: 10.0,
+                    export_wh: 0.0
+                },
+                LineEnergy {
+                    import_wh: 0.0,
+                    export_wh: 1.0
+                },
+                LineEnergy::default(),
+            ]
+        );
+        assert_eq!(
+            energy.lifetime.total(),
+            LineEnergy {
+                import_wh: 10.0,
+                export_wh: 1.0
+            }
+        );
+        assert_eq!(energy.timestamp(), Some(now + Duration::seconds(10)));
+    }
+
+    #[test]
+    fn test_energy_gap_and_rollover() {
+        // Given
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut energy = Energy::default();
+        energy.update([3600.0, 0.0, 0.0], now);
+        energy.update([3600.0, 0.0, 0.0], now + Duration::seconds(10));
+        // When
+        energy.update([3600.0, 0.0, 0.0], now + Duration::hours(1));
+        energy.update([3600.0, 0.0, 0.0], now + Duration::hours(1) + Duration::seconds(10));
+        energy.update([3600.0, 0.0, 0.0], now + Duration::days(1));
+        energy.update([3600.0, 0.0, 0.0], now + Duration::days(1) + Duration::seconds(10));
+        // Then
+        assert_eq!(energy.day.lines[0].import_wh, 10.0);
+        assert_eq!(energy.month.lines[0].import_wh, 30.0);
+        assert_eq!(energy.lifetime.lines[0].import_wh, 30.0);
+        // When
+        energy.reset();
+        // Then
+        assert_eq!(energy, Energy::default());
+    }
+}
@@ -0,0 +1,180 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::analytics::peak::PeakPeriod;
+
+/// Average current below which samples are left out, the imbalance of a near idle supply being meaningless.
+const MIN_CURRENT_AMPS: f32 = 1.0;
+
+/// Largest deviation of a line from the mean of the three, in percent of that mean.
+pub fn imbalance_percent(values: [f32; 3]) -> Option<f32> {
+    if values.iter().any(|value| !value.is_finite()) {
+        return None;
+    }
+    let mean = values.iter().sum::<f32>() / 3.0;
+    (mean > 0.0).then(|| values.iter().map(|value| (value - mean).abs()).fold(0.0, f32::max) / mean * 100.0)
+}
+
+/// Imbalance of the lines current, none while the supply is near idle.
+pub fn current_imbalance_percent(irms: [f32; 3]) -> Option<f32> {
+    if irms.iter().sum::<f32>() / 3.0 < MIN_CURRENT_AMPS {
+        return None;
+    }
+    imbalance_percent(irms)
+}
+
+/// Share of each line in the total, in percent.
+pub fn load_shares(values: [f32; 3]) -> Option<[f32; 3]> {
+    let total = values.iter().sum::<f32>();
+    (total.is_finite() && total > 0.0).then(|| values.map(|value| value / total * 100.0))
+}
+
+/// Index of the line carrying the most.
+pub fn most_loaded(values: [f32; 3]) -> usize {
+    (0..3).max_by(|&a, &b| values[a].total_cmp(&values[b])).unwrap_or(0)
+}
+
+/// Phase balance sums over a period, averaged when summarizing.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct PeriodImbalance {
+    pub start: Option<NaiveDate>,
+    pub samples: u64,
+    pub share_sums: [f64; 3],
+    pub imbalance_sum: f64,
+    pub max_imbalance_percent: f32,
+    /// Samples where each line was the most loaded
+    pub most_loaded_samples: [u64; 3],
+}
+
+/// Phase balance averaged over a period.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImbalanceStats {
+    pub shares: [f32; 3],
+    pub avg_imbalance_percent: f32,
+    pub max_imbalance_percent: f32,
+    /// Share of the time each line was the most loaded, in percent
+    pub most_loaded_percent: [f32; 3],
+}
+
+impl PeriodImbalance {
+    fn update(&mut self, period: PeakPeriod, shares: [f32; 3], imbalance: f32, timestamp: DateTime<Utc>) {
+        let start = period.start(timestamp);
+        if start != self.start {
+            *self = PeriodImbalance {
+                start,
+                ..Self::default()
+            };
+        }
+        self.samples += 1;
+        for (sum, share) in self.share_sums.iter_mut().zip(shares) {
+            *sum += share as f64;
+        }
+        self.imbalance_sum += imbalance as f64;
+        self.max_imbalance_percent = self.max_imbalance_percent.max(imbalance);
+        self.most_loaded_samples[most_loaded(shares)] += 1;
+    }
+
+    /// Averages if the period is the current one and has samples.
+    pub fn stats(&self, period: PeakPeriod, now: DateTime<Utc>) -> Option<ImbalanceStats> {
+        if self.samples == 0 || self.start != period.start(now) {
+            return None;
+        }
+        let samples = self.samples as f64;
+        Some(ImbalanceStats {
+            shares: self.share_sums.map(|sum| (sum / samples) as f32),
+            avg_imbalance_percent: (self.imbalance_sum / samples) as f32,
+            max_imbalance_percent: self.max_imbalance_percent,
+            most_loaded_percent: self
+                .most_loaded_samples
+                .map(|count| (count as f64 / samples * 100.0) as f32),
+        })
+    }
+}
+
+/// Load share of the lines, from the apparent power, and their current imbalance, tracked over the day and the month.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct Imbalance {
+    pub day: PeriodImbalance,
+    pub month: PeriodImbalance,
+    #[serde(skip)]
+    last: Option<([f32; 3], f32)>,
+}
+
+/// Phase balance as published to the HMI and sinks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImbalanceSummary {
+    /// Last load shares and current imbalance, none while the supply is near idle
+    pub shares: Option<[f32; 3]>,
+    pub imbalance_percent: Option<f32>,
+    pub day: Option<ImbalanceStats>,
+    pub month: Option<ImbalanceStats>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Imbalance {
+    /// Records the lines apparent power and current.
+    pub fn update(&mut self, apparent_power: [f32; 3], irms: [f32; 3], timestamp: DateTime<Utc>) {
+        self.last = None;
+        let (Some(shares), Some(imbalance)) = (load_shares(apparent_power), current_imbalance_percent(irms)) else {
+            return;
+        };
+        self.day.update(PeakPeriod::Day, shares, imbalance, timestamp);
+        self.month.update(PeakPeriod::Month, shares, imbalance, timestamp);
+        self.last = Some((shares, imbalance));
+    }
+
+    pub fn summary(&self, now: DateTime<Utc>) -> ImbalanceSummary {
+        ImbalanceSummary {
+            shares: self.last.map(|(shares, _)| shares),
+            imbalance_percent: self.last.map(|(_, imbalance)| imbalance),
+            day: self.day.stats(PeakPeriod::Day, now),
+            month: self.month.stats(PeakPeriod::Month, now),
+            timestamp: now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    #[test]
+    fn test_imbalance_percent() {
+        assert_eq!(imbalance_percent([10.0, 10.0, 10.0]), Some(0.0));
+        assert_eq!(imbalance_percent([20.0, 5.0, 5.0]), Some(100.0));
+        assert_eq!(imbalance_percent([30.0, 0.0, 0.0]), Some(200.0));
+        assert_eq!(imbalance_percent([0.0, 0.0, 0.0]), None);
+        assert_eq!(imbalance_percent([10.0, f32::NAN, 10.0]), None);
+        assert_eq!(load_shares([500.0, 250.0, 250.0]), Some([50.0, 25.0, 25.0]));
+        assert_eq!(load_shares([0.0, 0.0, 0.0]), None);
+    }
+
+    #[test]
+    fn test_imbalance_update() {
+        // Given
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut imbalance = Imbalance::default();
+        // When
+        imbalance.update([2000.0, 1000.0, 1000.0], [6.0, 3.0, 3.0], now);
+        imbalance.update([1000.0, 1000.0, 2000.0], [1.0, 1.0, 4.0], now + Duration::seconds(5));
+        imbalance.update([100.0, 0.0, 0.0], [0.5, 0.0, 0.0], now + Duration::seconds(10));
+        let summary = imbalance.summary(now + Duration::seconds(10));
+        // Then
+        assert_eq!(summary.shares, None);
+        assert_eq!(summary.imbalance_percent, None);
+        let day = summary.day.unwrap();
+        assert_eq!(day.shares, [37.5, 25.0, 37.5]);
+        assert_eq!(day.avg_imbalance_percent, 75.0);
+        assert_eq!(day.max_imbalance_percent, 100.0);
+        assert_eq!(day.most_loaded_percent, [50.0, 0.0, 50.0]);
+        assert_eq!(summary.month, Some(day));
+        // When
+        let summary = imbalance.summary(now + Duration::days(1));
+        // Then
+        assert_eq!(summary.day, None);
+        assert!(summary.month.is_some());
+    }
+}
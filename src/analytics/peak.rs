@@ -0,0 +1,134 @@
+use chrono::{DateTime, Datelike, Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PeakPeriod {
+    Day,
+    Month,
+    AllTime,
+}
+
+impl PeakPeriod {
+    /// Identifies the period a timestamp belongs to, in local time. All-time has a single period.
+    pub(crate) fn start(&self, timestamp: DateTime<Utc>) -> Option<NaiveDate> {
+        let date = timestamp.with_timezone(&Local).date_naive();
+        match self {
+            PeakPeriod::Day => Some(date),
+            PeakPeriod::Month => date.with_day(1),
+            PeakPeriod::AllTime => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Peak {
+    pub value: f32,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Highest value seen on each line during a period.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct PeriodPeaks {
+    pub start: Option<NaiveDate>,
+    pub lines: [Option<Peak>; 3],
+}
+
+impl PeriodPeaks {
+    fn update(&mut self, period: PeakPeriod, values: [f32; 3], timestamp: DateTime<Utc>) -> bool {
+        let start = period.start(timestamp);
+        let mut changed = false;
+        if start != self.start {
+            *self = PeriodPeaks {
+                start,
+                ..Self::default()
+            };
+            changed = true;
+        }
+        for (peak, value) in self.lines.iter_mut().zip(values) {
+            if value.is_finite() && peak.as_ref().is_none_or(|peak| value > peak.value) {
+                *peak = Some(Peak { value, timestamp });
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    pub fn values(&self) -> [f32; 3] {
+        self.lines
+            .each_ref()
+            .map(|peak| peak.as_ref().map_or(0.0, |peak| peak.value))
+    }
+}
+
+/// Line apparent power peaks, tracked over the day, the month and all-time.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct Peaks {
+    pub day: PeriodPeaks,
+    pub month: PeriodPeaks,
+    pub all_time: PeriodPeaks,
+}
+
+impl Peaks {
+    /// Records new line values, returns whether any peak changed.
+    pub fn update(&mut self, values: [f32; 3], timestamp: DateTime<Utc>) -> bool {
+        let day = self.day.update(PeakPeriod::Day, values, timestamp);
+        let month = self.month.update(PeakPeriod::Month, values, timestamp);
+        let all_time = self.all_time.update(PeakPeriod::AllTime, values, timestamp);
+        day || month || all_time
+    }
+
+    pub fn get(&self, period: PeakPeriod) -> &PeriodPeaks {
+        match period {
+            PeakPeriod::Day => &self.day,
+            PeakPeriod::Month => &self.month,
+            PeakPeriod::AllTime => &self.all_time,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    #[test]
+    fn test_peaks_update() {
+        // Given
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut peaks = Peaks::default();
+        // When
+        let first = peaks.update([100.0, 200.0, 300.0], now);
+        let second = peaks.update([150.0, 100.0, f32::NAN], now + Duration::minutes(1));
+        let third = peaks.update([50.0, 50.0, 50.0], now + Duration::minutes(2));
+        // Then
+        assert!(first && second && !third);
+        assert_eq!(peaks.day.values(), [150.0, 200.0, 300.0]);
+        assert_eq!(peaks.all_time.values(), [150.0, 200.0, 300.0]);
+        assert_eq!(
+            peaks.day.lines[0].as_ref().unwrap().timestamp,
+            now + Duration::minutes(1)
+        );
+    }
+
+    #[test]
+    fn test_peaks_period_rollover() {
+        // Given
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut peaks = Peaks::default();
+        peaks.update([100.0, 200.0, 300.0], now);
+        // When
+        let changed = peaks.update([10.0, 20.0, 30.0], now + Duration::days(1));
+        // Then
+        assert!(changed);
+        assert_eq!(peaks.get(PeakPeriod::Day).values(), [10.0, 20.0, 30.0]);
+        assert_eq!(peaks.get(PeakPeriod::Month).values(), [100.0, 200.0, 300.0]);
+        // When
+        peaks.update([1.0, 2.0, 3.0], now + Duration::days(31));
+        // Then
+        assert_eq!(peaks.get(PeakPeriod::Month).values(), [1.0, 2.0, 3.0]);
+        assert_eq!(peaks.get(PeakPeriod::AllTime).values(), [100.0, 200.0, 300.0]);
+    }
+}
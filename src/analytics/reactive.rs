@@ -0,0 +1,187 @@
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::analytics::peak::PeakPeriod;
+
+/// Apparent power below which the power factor is left out, being mostly measurement noise.
+const MIN_APPARENT_POWER_VA: f32 = 20.0;
+/// Longest gap between two frames counted in the average, a longer one meaning frames were lost.
+const MAX_GAP_MILLIS: i64 = 10_000;
+
+/// Reactive power magnitude, the RPICT not telling inductive from capacitive loads.
+pub fn reactive_power(real_power: f32, apparent_power: f32) -> f32 {
+    (apparent_power.powi(2) - real_power.powi(2)).max(0.0).sqrt()
+}
+
+/// Power factor of a line, none while it is near idle. Without harmonics measurement, the current is assumed
+/// sinusoidal, the displacement power factor being then |P| / S.
+pub fn displacement_power_factor(real_power: f32, apparent_power: f32) -> Option<f32> {
+    (apparent_power.is_finite() && real_power.is_finite() && apparent_power >= MIN_APPARENT_POWER_VA)
+        .then(|| (real_power.abs() / apparent_power).min(1.0))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Drawn from the grid
+    Import,
+    /// Fed back to the grid, eg. by solar panels
+    Export,
+}
+
+impl Direction {
+    pub fn of(real_power: f32) -> Self {
+        if real_power < 0.0 {
+            Direction::Export
+        } else {
+            Direction::Import
+        }
+    }
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::Import => write!(f, "import"),
+            Direction::Export => write!(f, "export"),
+        }
+    }
+}
+
+/// Power factor of the lines weighted by time over a day.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct PeriodPowerFactor {
+    pub start: Option<NaiveDate>,
+    pub weighted_sums: [f64; 3],
+    pub secs: [f64; 3],
+}
+
+impl PeriodPowerFactor {
+    fn add(&mut self, start: Option<NaiveDate>, power_factors: [Option<f32>; 3], secs: f64) {
+        if start != self.start {
+            *self = PeriodPowerFactor {
+                start,
+                ..Self::default()
+            };
+        }
+        for (index, power_factor) in power_factors.iter().enumerate() {
+            if let Some(power_factor) = power_factor {
+                self.weighted_sums[index] += *power_factor as f64 * secs;
+                self.secs[index] += secs;
+            }
+        }
+    }
+
+    fn averages(&self, start: Option<NaiveDate>) -> [Option<f32>; 3] {
+        if start != self.start {
+            return [None; 3];
+        }
+        [0, 1, 2].map(|index| (self.secs[index] > 0.0).then(|| (self.weighted_sums[index] / self.secs[index]) as f32))
+    }
+}
+
+/// Real and reactive power of a line, as published to the HMI and sinks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinePowerFactor {
+    pub real_power: f32,
+    pub reactive_power: f32,
+    pub power_factor: Option<f32>,
+    pub direction: Direction,
+    /// Time-weighted average of the day
+    pub today_avg_power_factor: Option<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PowerFactorSummary {
+    pub lines: [LinePowerFactor; 3],
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Tracks the lines power factor, each value counting for the time until the next frame.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct PowerFactor {
+    pub day: PeriodPowerFactor,
+    /// Real and apparent power of the last frame
+    #[serde(skip)]
+    last: Option<([f32; 3], [f32; 3], DateTime<Utc>)>,
+}
+
+impl PowerFactor {
+    pub fn update(&mut self, real_power: [f32; 3], apparent_power: [f32; 3], timestamp: DateTime<Utc>) {
+        if let Some((last_real, last_apparent, last_timestamp)) = self.last {
+            let elapsed = timestamp - last_timestamp;
+            if elapsed > Duration::zero() && elapsed <= Duration::milliseconds(MAX_GAP_MILLIS) {
+                let power_factors =
+                    [0, 1, 2].map(|index| displacement_power_factor(last_real[index], last_apparent[index]));
+                let start = PeakPeriod::Day.start(last_timestamp);
+                self.day
+                    .add(start, power_factors, elapsed.num_milliseconds() as f64 / 1000.0);
+            }
+        }
+        self.last = Some((real_power, apparent_power, timestamp));
+    }
+
+    /// Last frame values with the averages of the day, none until a frame is received.
+    pub fn summary(&self, now: DateTime<Utc>) -> Option<PowerFactorSummary> {
+        let (real_power, apparent_power, _) = self.last?;
+        let averages = self.day.averages(PeakPeriod::Day.start(now));
+        let lines = [0, 1, 2].map(|index| LinePowerFactor {
+            real_power: real_power[index],
+            reactive_power: reactive_power(real_power[index], apparent_power[index]),
+            power_factor: displacement_power_factor(real_power[index], apparent_power[index]),
+            direction: Direction::of(real_power[index]),
+            today_avg_power_factor: averages[index],
+        });
+        Some(PowerFactorSummary { lines, timestamp: now })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_reactive_power() {
+        assert_eq!(reactive_power(300.0, 500.0), 400.0);
+        assert_eq!(reactive_power(-300.0, 500.0), 400.0);
+        // measurement error making the real power slightly above the apparent one
+        assert_eq!(reactive_power(502.0, 500.0), 0.0);
+        assert_eq!(displacement_power_factor(-400.0, 500.0), Some(0.8));
+        assert_eq!(displacement_power_factor(5.0, 10.0), None);
+        assert_eq!(Direction::of(-400.0), Direction::Export);
+        assert_eq!(Direction::of(0.0), Direction::Import);
+    }
+
+    #[test]
+    fn test_power_factor_time_weighted() {
+        // Given
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let at = |secs| now + Duration::seconds(secs);
+        let mut power_factor = PowerFactor::default();
+        // When
+        power_factor.update([500.0, 100.0, 0.0], [500.0, 1000.0, 0.0], at(0));
+        power_factor.update([250.0, 100.0, 0.0], [1000.0, 1000.0, 0.0], at(3));
+        power_factor.update([250.0, 100.0, 0.0], [1000.0, 1000.0, 0.0], at(4));
+        // frames lost meanwhile
+        power_factor.update([-600.0, 100.0, 0.0], [1000.0, 1000.0, 0.0], at(60));
+        let summary = power_factor.summary(at(60)).unwrap();
+        // Then
+        let [l1, l2, l3] = &summary.lines;
+        // 1.0 during 3 s then 0.25 during 1 s
+        assert_eq!(l1.today_avg_power_factor, Some(0.8125));
+        assert_eq!(l1.power_factor, Some(0.6));
+        assert_eq!(l1.reactive_power, 800.0);
+        assert_eq!(l1.direction, Direction::Export);
+        assert_eq!(l2.today_avg_power_factor, Some(0.1));
+        assert_eq!(l3.power_factor, None);
+        assert_eq!(l3.today_avg_power_factor, None);
+        // When
+        let summary = power_factor.summary(now + Duration::days(1)).unwrap();
+        // Then
+        assert_eq!(summary.lines[0].today_avg_power_factor, None);
+    }
+}
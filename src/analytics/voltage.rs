@@ -0,0 +1,308 @@
+use chrono::{DateTime, Duration, DurationRound, Utc};
+
+use crate::analytics::imbalance::imbalance_percent;
+use crate::settings;
+
+/// Aggregation period of EN 50160 voltage measurements.
+pub const WINDOW_SECS: i64 = 600;
+
+/// Longest gap between two frames counted in an excursion, a longer one meaning frames were lost.
+const MAX_GAP_MILLIS: i64 = 10_000;
+
+fn max_gap() -> Duration {
+    Duration::milliseconds(MAX_GAP_MILLIS)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Level {
+    #[default]
+    Normal,
+    Over,
+    Under,
+}
+
+/// Excursions out of the voltage tolerance, an excursion spanning windows being counted in the first one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Excursions {
+    pub count: u32,
+    pub duration_secs: f64,
+}
+
+/// Voltage of a line over a window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PhaseVoltage {
+    pub min: f32,
+    pub max: f32,
+    pub avg: f32,
+    pub over: Excursions,
+    pub under: Excursions,
+}
+
+/// Voltage quality over a window, as published to the HMI and sinks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoltageSummary {
+    pub start: DateTime<Utc>,
+    /// Lines without any valid sample are none
+    pub lines: [Option<PhaseVoltage>; 3],
+    /// Largest deviation of a line average from the mean of the three, in percent of that mean
+    pub unbalance_percent: Option<f32>,
+    /// End of the window
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct PhaseWindow {
+    min: f32,
+    max: f32,
+    sum: f64,
+    samples: u32,
+    level: Level,
+    over: Excursions,
+    under: Excursions,
+}
+
+impl PhaseWindow {
+    fn add(&mut self, value: f32, level: Level) {
+        if self.samples == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.sum += value as f64;
+        self.samples += 1;
+        if level != self.level {
+            match level {
+                Level::Over => self.over.count += 1,
+                Level::Under => self.under.count += 1,
+                Level::Normal => {}
+            }
+            self.level = level;
+        }
+    }
+
+    fn accrue(&mut self, secs: f64) {
+        match self.level {
+            Level::Over => self.over.duration_secs += secs,
+            Level::Under => self.under.duration_secs += secs,
+            Level::Normal => {}
+        }
+    }
+
+    fn summary(&self) -> Option<PhaseVoltage> {
+        (self.samples > 0).then(|| PhaseVoltage {
+            min: self.min,
+            max: self.max,
+            avg: (self.sum / self.samples as f64) as f32,
+            over: self.over,
+            under: self.under,
+        })
+    }
+
+    /// Next window of the line, carrying an ongoing excursion over without counting it again.
+    fn next(&self) -> Self {
+        PhaseWindow {
+            level: self.level,
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Window {
+    start: DateTime<Utc>,
+    last_timestamp: DateTime<Utc>,
+    phases: [PhaseWindow; 3],
+}
+
+impl Window {
+    fn end(&self) -> DateTime<Utc> {
+        self.start + Duration::seconds(WINDOW_SECS)
+    }
+
+    /// Counts the time since the last frame in the ongoing excursions.
+    fn accrue(&mut self, until: DateTime<Utc>) {
+        let elapsed = until - self.last_timestamp;
+        if elapsed >= Duration::zero() && elapsed <= max_gap() {
+            let secs = elapsed.num_milliseconds() as f64 / 1000.0;
+            self.phases.iter_mut().for_each(|phase| phase.accrue(secs));
+        }
+        self.last_timestamp = until;
+    }
+
+    fn summary(&self) -> VoltageSummary {
+        let lines = self.phases.each_ref().map(PhaseWindow::summary);
+        let unbalance_percent = match &lines {
+            [Some(l1), Some(l2), Some(l3)] => imbalance_percent([l1.avg, l2.avg, l3.avg]),
+            _ => None,
+        };
+        VoltageSummary {
+            start: self.start,
+            lines,
+            unbalance_percent,
+            timestamp: self.end(),
+        }
+    }
+}
+
+/// Tracks the lines voltage over 10 minutes windows aligned on the clock, as EN 50160 does,
+/// with the excursions out of the nominal voltage tolerance.
+#[derive(Clone, Debug)]
+pub struct VoltageQuality {
+    over_volts: f32,
+    under_volts: f32,
+    window: Option<Window>,
+}
+
+impl VoltageQuality {
+    pub fn new(settings: &settings::VoltageQuality) -> Self {
+        let tolerance = settings.nominal_volts * settings.tolerance_percent / 100.0;
+        VoltageQuality {
+            over_volts: settings.nominal_volts + tolerance,
+            under_volts: settings.nominal_volts - tolerance,
+            window: None,
+        }
+    }
+
+    fn level(&self, value: f32) -> Level {
+        if value > self.over_volts {
+            Level::Over
+        } else if value < self.under_volts {
+            Level::Under
+        } else {
+            Level::Normal
+        }
+    }
+
+    /// Records the lines voltage, returns the summary of the window the frame closed, if any.
+    pub fn update(&mut self, values: [f32; 3], timestamp: DateTime<Utc>) -> Option<VoltageSummary> {
+        let start = timestamp.duration_trunc(Duration::seconds(WINDOW_SECS)).ok()?;
+        let mut closed = None;
+        let mut window = match self.window.take() {
+            Some(mut window) if timestamp >= window.end() => {
+                let end = window.end();
+                // time across the boundary is split between both windows, unless frames were lost meanwhile
+                let contiguous = start == end && timestamp - window.last_timestamp <= max_gap();
+                if contiguous {
+                    window.accrue(end);
+                }
+                closed = Some(window.summary());
+                Window {
+                    start,
+                    last_timestamp: if contiguous { end } else { timestamp },
+                    phases: window.phases.each_ref().map(PhaseWindow::next),
+                }
+            }
+            Some(window) => window,
+            None => Window {
+                start,
+                last_timestamp: timestamp,
+                phases: Default::default(),
+            },
+        };
+        window.accrue(timestamp);
+        for (phase, value) in window.phases.iter_mut().zip(values) {
+            if value.is_finite() {
+                phase.add(value, self.level(value));
+            }
+        }
+        self.window = Some(window);
+        closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn settings() -> settings::VoltageQuality {
+        settings::VoltageQuality {
+            nominal_volts: 230.0,
+            tolerance_percent: 10.0,
+        }
+    }
+
+    #[test]
+    fn test_voltage_quality_window() {
+        // Given
+        let start = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut quality = VoltageQuality::new(&settings());
+        // When
+        let mut summaries = Vec::new();
+        for (secs, values) in [
+            (5, [230.0, 258.0, 235.0]),
+            (10, [232.0, 254.0, 235.0]),
+            (15, [228.0, 252.0, f32::NAN]),
+            (20, [230.0, 256.0, 235.0]),
+            (25, [200.0, 255.0, 235.0]),
+            (30, [230.0, 240.0, 235.0]),
+            (605, [230.0, 230.0, 230.0]),
+        ] {
+            summaries.extend(quality.update(values, start + Duration::seconds(secs)));
+        }
+        // Then
+        assert_eq!(summaries.len(), 1);
+        let summary = &summaries[0];
+        assert_eq!(summary.start, start);
+        assert_eq!(summary.timestamp, start + Duration::minutes(10));
+        let l1 = summary.lines[0].as_ref().unwrap();
+        assert_eq!((l1.min, l1.max, l1.avg), (200.0, 232.0, 225.0));
+        assert_eq!(
+            l1.under,
+            Excursions {
+                count: 1,
+                duration_secs: 5.0
+            }
+        );
+        assert_eq!(l1.over, Excursions::default());
+        let l2 = summary.lines[1].as_ref().unwrap();
+        assert_eq!(
+            l2.over,
+            Excursions {
+                count: 2,
+                duration_secs: 20.0
+            }
+        );
+        let l3 = summary.lines[2].as_ref().unwrap();
+        assert_eq!((l3.min, l3.max, l3.avg), (235.0, 235.0, 235.0));
+        // lines average 225, 252.5 and 235 V, 237.5 V on average
+        let unbalance = summary.unbalance_percent.unwrap();
+        assert!((unbalance - 15.0 / 237.5 * 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_voltage_quality_excursion_across_windows() {
+        // Given
+        let start = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut quality = VoltageQuality::new(&settings());
+        quality.update([260.0, 230.0, f32::NAN], start + Duration::seconds(595));
+        // When
+        let first = quality.update([260.0, 230.0, f32::NAN], start + Duration::seconds(603));
+        let second = quality.update([230.0, 230.0, f32::NAN], start + Duration::seconds(1200));
+        // Then
+        let first = first.unwrap();
+        let l1 = first.lines[0].as_ref().unwrap();
+        assert_eq!(
+            l1.over,
+            Excursions {
+                count: 1,
+                duration_secs: 5.0
+            }
+        );
+        assert_eq!(first.lines[2], None);
+        assert_eq!(first.unbalance_percent, None);
+        let second = second.unwrap();
+        let l1 = second.lines[0].as_ref().unwrap();
+        assert_eq!(
+            l1.over,
+            Excursions {
+                count: 0,
+                duration_secs: 3.0
+            }
+        );
+        assert_eq!((l1.min, l1.max), (260.0, 260.0));
+    }
+}
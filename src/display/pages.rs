@@ -9,15 +9,29 @@ use embedded_graphics::{
     text::{Alignment, Text},
 };
 
+use crate::alert::Alert;
+use crate::analytics::consumption::ConsumptionSummary;
+use crate::analytics::cost::CostSummary;
+use crate::analytics::reactive::{Direction, PowerFactorSummary};
+use crate::analytics::voltage::VoltageSummary;
 use crate::display::icons::*;
 use crate::display::widgets::*;
 use crate::driver::linky::TariffPeriod;
+use crate::metrics::MetricsSnapshot;
+use crate::shedding::OutputState;
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub enum Page {
     Startup,
     Rpict,
     Linky,
+    Consumption,
+    Cost,
+    Voltage,
+    PowerFactor,
+    Alert,
+    Shedding,
+    Diagnostics,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -117,6 +131,7 @@ pub struct RpictPage {
     l3_sparkline: SparkLine,
     total_apparent_power: f32,
     avg_vrms: f32,
+    imbalance_percent: Option<f32>,
 }
 
 impl RpictPage {
@@ -127,9 +142,33 @@ impl RpictPage {
             l3_sparkline: SparkLine::new(Point::new(1, 22), "P3".to_string(), max_power),
             total_apparent_power: 0.0,
             avg_vrms: 0.0,
+            imbalance_percent: None,
         }
     }
 
+    pub fn set_labels(&mut self, [l1_label, l2_label, l3_label]: [String; 3]) {
+        self.l1_sparkline.set_label(l1_label);
+        self.l2_sparkline.set_label(l2_label);
+        self.l3_sparkline.set_label(l3_label);
+    }
+
+    pub fn set_max_powers(&mut self, [l1_max_power, l2_max_power, l3_max_power]: [f32; 3]) {
+        self.l1_sparkline.set_max_value(l1_max_power);
+        self.l2_sparkline.set_max_value(l2_max_power);
+        self.l3_sparkline.set_max_value(l3_max_power);
+    }
+
+    pub fn set_peaks(&mut self, [l1_peak, l2_peak, l3_peak]: [f32; 3]) {
+        self.l1_sparkline.set_value_max(l1_peak);
+        self.l2_sparkline.set_value_max(l2_peak);
+        self.l3_sparkline.set_value_max(l3_peak);
+    }
+
+    /// Current imbalance between the lines, hidden when none.
+    pub fn set_imbalance(&mut self, imbalance_percent: Option<f32>) {
+        self.imbalance_percent = imbalance_percent;
+    }
+
     pub fn update(
         &mut self,
         l1_apparent_power: f32,
@@ -171,6 +210,17 @@ impl Drawable for RpictPage {
         )
         .draw(target)?;
 
+        if let Some(imbalance_percent) = self.imbalance_percent {
+            // smaller font to fit between the power and the voltage
+            Text::with_alignment(
+                &format!("IMB {:.0}%", imbalance_percent.min(999.0)),
+                Point::new(67, 30),
+                MonoTextStyle::new(&FONT_4X6, BinaryColor::On),
+                Alignment::Center,
+            )
+            .draw(target)?;
+        }
+
         Text::with_alignment(
             &format!("{:5.2}V", self.avg_vrms),
             Point::new(127, 30),
@@ -233,17 +283,22 @@ impl Drawable for LinkyPage {
         .draw(target)?;
 
         let hchp_active = match self.ptec {
-            TariffPeriod::HP => ">",
+            TariffPeriod::HP | TariffPeriod::PM => ">",
             _ => " ",
         };
         let hchp = self.hchp.map(FORMAT).unwrap_or_else(UNKNOWN);
         let hchc_active = match self.ptec {
-            TariffPeriod::HC => ">",
+            TariffPeriod::HC | TariffPeriod::HN => ">",
             _ => " ",
         };
         let hchc = self.hchc.map(FORMAT).unwrap_or_else(UNKNOWN);
+        // EJP meters index peak days and normal hours instead
+        let (hp_label, hc_label) = match self.ptec {
+            TariffPeriod::HN | TariffPeriod::PM => ("PM", "HN"),
+            _ => ("HP", "HC"),
+        };
         Text::with_alignment(
-            &format!("{}HP {}\n{}HC {}", hchp_active, hchp, hchc_active, hchc),
+            &format!("{}{hp_label} {}\n{}{hc_label} {}", hchp_active, hchp, hchc_active, hchc),
             Point::new(64, 20),
             text_style,
             Alignment::Center,
@@ -254,6 +309,451 @@ impl Drawable for LinkyPage {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct ConsumptionPage {
+    summary: Option<ConsumptionSummary>,
+}
+
+impl ConsumptionPage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, summary: ConsumptionSummary) {
+        self.summary = Some(summary);
+    }
+}
+
+impl Drawable for ConsumptionPage {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        target.clear(BinaryColor::Off)?;
+
+        let text_style = MonoTextStyle::new(&FONT_4X6, BinaryColor::On);
+        let Some(s) = &self.summary else {
+            Text::with_alignment(
+                "waiting for Linky...",
+                Point::new(64, 18),
+                text_style,
+                Alignment::Center,
+            )
+            .draw(target)?;
+            return Ok(());
+        };
+        const KWH: fn(u64) -> f32 = |wh| wh as f32 / 1000.0;
+        let average_power = s
+            .average_power_w
+            .map(|average_power_w| format!("{average_power_w:5.0}W"))
+            .unwrap_or_else(|| "    ?W".to_string());
+        let lines = [
+            format!("HOUR  {:7.3}kWh {average_power} avg", KWH(s.last_hour.total_wh())),
+            format!(
+                "DAY   {:7.3}kWh yda {:7.3}",
+                KWH(s.today.total_wh()),
+                KWH(s.yesterday.total_wh())
+            ),
+            format!(
+                "MONTH {:7.1}kWh last {:7.1}",
+                KWH(s.this_month.total_wh()),
+                KWH(s.last_month.total_wh())
+            ),
+            format!("TODAY HC {:.3} HP {:.3}", KWH(s.today.hc_wh), KWH(s.today.hp_wh)),
+        ];
+        Text::with_alignment(&lines.join("\n"), Point::new(1, 8), text_style, Alignment::Left).draw(target)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct CostPage {
+    summary: Option<CostSummary>,
+}
+
+impl CostPage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, summary: CostSummary) {
+        self.summary = Some(summary);
+    }
+}
+
+impl Drawable for CostPage {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        target.clear(BinaryColor::Off)?;
+
+        let text_style = MonoTextStyle::new(&FONT_4X6, BinaryColor::On);
+        let Some(s) = &self.summary else {
+            Text::with_alignment(
+                "no tariff configured",
+                Point::new(64, 18),
+                text_style,
+                Alignment::Center,
+            )
+            .draw(target)?;
+            return Ok(());
+        };
+        let currency = &s.currency;
+        let prices = match (s.ejp_prices(), s.period_prices()) {
+            (Some(prices), _) => {
+                let hn_active = if s.tariff_period == TariffPeriod::HN { ">" } else { " " };
+                let pm_active = if s.tariff_period == TariffPeriod::PM { ">" } else { " " };
+                format!(
+                    "{hn_active}HN {:.4} {pm_active}PM {:.4} {currency}/kWh",
+                    prices.hn_per_kwh, prices.pm_per_kwh
+                )
+            }
+            (None, Some(prices)) => {
+                let hc_active = if s.tariff_period == TariffPeriod::HC { ">" } else { " " };
+                let hp_active = if s.tariff_period == TariffPeriod::HP { ">" } else { " " };
+                format!(
+                    "{hc_active}HC {:.4} {hp_active}HP {:.4} {currency}/kWh",
+                    prices.hc_per_kwh, prices.hp_per_kwh
+                )
+            }
+            (None, None) => "no price in effect".to_string(),
+        };
+        let lines = [
+            format!("TODAY {:8.2} {currency}", s.today),
+            format!("MONTH {:8.2} {currency}", s.this_month),
+            format!(
+                "BILL  {:8.2} est {:8.2} {currency}",
+                s.billing_period, s.billing_projection
+            ),
+            prices,
+        ];
+        Text::with_alignment(&lines.join("\n"), Point::new(1, 8), text_style, Alignment::Left).draw(target)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct VoltagePage {
+    summary: Option<VoltageSummary>,
+}
+
+impl VoltagePage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, summary: VoltageSummary) {
+        self.summary = Some(summary);
+    }
+}
+
+impl Drawable for VoltagePage {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        target.clear(BinaryColor::Off)?;
+
+        let text_style = MonoTextStyle::new(&FONT_4X6, BinaryColor::On);
+        let Some(s) = &self.summary else {
+            Text::with_alignment(
+                "voltage window in progress",
+                Point::new(64, 18),
+                text_style,
+                Alignment::Center,
+            )
+            .draw(target)?;
+            return Ok(());
+        };
+        let mut lines = vec![format!("   {:>5} {:>5} {:>5} OV UV", "MIN", "AVG", "MAX")];
+        for (index, line) in s.lines.iter().enumerate() {
+            let l = index + 1;
+            lines.push(match line {
+                Some(line) => format!(
+                    "L{l} {:5.1} {:5.1} {:5.1} {:>2} {:>2}",
+                    line.min,
+                    line.avg,
+                    line.max,
+                    line.over.count.min(99),
+                    line.under.count.min(99)
+                ),
+                None => format!("L{l} no data"),
+            });
+        }
+        let unbalance = s
+            .unbalance_percent
+            .map_or("-".to_string(), |unbalance| format!("{unbalance:.1}%"));
+        lines.push(format!("UNBALANCE {unbalance:<6} LAST 10 MIN"));
+        Text::with_alignment(&lines.join("\n"), Point::new(1, 5), text_style, Alignment::Left).draw(target)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct PowerFactorPage {
+    summary: Option<PowerFactorSummary>,
+}
+
+impl PowerFactorPage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, summary: PowerFactorSummary) {
+        self.summary = Some(summary);
+    }
+}
+
+impl Drawable for PowerFactorPage {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        target.clear(BinaryColor::Off)?;
+
+        let text_style = MonoTextStyle::new(&FONT_4X6, BinaryColor::On);
+        let Some(s) = &self.summary else {
+            Text::with_alignment("no RPICT frame yet", Point::new(64, 18), text_style, Alignment::Center)
+                .draw(target)?;
+            return Ok(());
+        };
+        const FORMAT: fn(Option<f32>) -> String =
+            |power_factor| power_factor.map_or("-".to_string(), |power_factor| format!("{power_factor:.2}"));
+        let mut lines = vec![format!("   {:>6} {:>6} {:>4} {:>4}", "P W", "Q VAR", "PF", "DAY")];
+        for (index, line) in s.lines.iter().enumerate() {
+            let direction = match line.direction {
+                Direction::Import => "IMP",
+                Direction::Export => "EXP",
+            };
+            lines.push(format!(
+                "L{} {:>6.0} {:>6.0} {:>4} {:>4} {direction}",
+                index + 1,
+                line.real_power,
+                line.reactive_power,
+                FORMAT(line.power_factor),
+                FORMAT(line.today_avg_power_factor)
+            ));
+        }
+        Text::with_alignment(&lines.join("\n"), Point::new(1, 8), text_style, Alignment::Left).draw(target)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct AlertPage {
+    /// Firing alerts, the latest last
+    alerts: Vec<Alert>,
+}
+
+impl AlertPage {
+    /// Characters of a FONT_4X6 line.
+    const LINE_LENGTH: usize = 32;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a firing alert or removes a resolved one.
+    pub fn update(&mut self, alert: Alert) {
+        self.alerts.retain(|active| active.name != alert.name);
+        if alert.is_firing() {
+            self.alerts.push(alert);
+        }
+    }
+}
+
+impl Drawable for AlertPage {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        target.clear(BinaryColor::Off)?;
+
+        let text_style = MonoTextStyle::new(&FONT_4X6, BinaryColor::On);
+        let Some(alert) = self.alerts.last() else {
+            Text::with_alignment("no active alert", Point::new(64, 18), text_style, Alignment::Center).draw(target)?;
+            return Ok(());
+        };
+        Rectangle::new(Point::zero(), Size::new(128, 9))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(target)?;
+        let header_style = MonoTextStyle::new(&FONT_5X7, BinaryColor::Off);
+        Text::with_alignment(
+            &format!("! {}", alert.name.to_uppercase()),
+            Point::new(1, 7),
+            header_style,
+            Alignment::Left,
+        )
+        .draw(target)?;
+        if self.alerts.len() > 1 {
+            Text::with_alignment(
+                &format!("+{}", self.alerts.len() - 1),
+                Point::new(126, 7),
+                header_style,
+                Alignment::Right,
+            )
+            .draw(target)?;
+        }
+        let mut lines = Vec::<String>::new();
+        for word in alert.message.split(' ') {
+            match lines.last_mut() {
+                Some(line) if line.len() + 1 + word.len() <= Self::LINE_LENGTH => {
+                    line.push(' ');
+                    line.push_str(word);
+                }
+                _ => lines.push(word.to_string()),
+            }
+        }
+        lines.truncate(3);
+        Text::with_alignment(&lines.join("\n"), Point::new(1, 16), text_style, Alignment::Left).draw(target)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct SheddingPage {
+    states: Vec<OutputState>,
+}
+
+impl SheddingPage {
+    /// FONT_4X6 lines fitting the screen.
+    const MAX_LINES: usize = 5;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, states: Vec<OutputState>) {
+        self.states = states;
+    }
+}
+
+impl Drawable for SheddingPage {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        target.clear(BinaryColor::Off)?;
+
+        let text_style = MonoTextStyle::new(&FONT_4X6, BinaryColor::On);
+        if self.states.is_empty() {
+            Text::with_alignment(
+                "no load shedding output",
+                Point::new(64, 18),
+                text_style,
+                Alignment::Center,
+            )
+            .draw(target)?;
+            return Ok(());
+        }
+        let mut lines = self
+            .states
+            .iter()
+            .map(|state| {
+                let level = if state.on { "ON" } else { "OFF" };
+                format!("{:<12} {level:<3} {}", state.name, state.reason)
+            })
+            .collect::<Vec<_>>();
+        if lines.len() > Self::MAX_LINES {
+            let more = lines.len() - Self::MAX_LINES + 1;
+            lines.truncate(Self::MAX_LINES - 1);
+            lines.push(format!("+{more} more"));
+        }
+        Text::with_alignment(&lines.join("\n"), Point::new(1, 5), text_style, Alignment::Left).draw(target)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct DiagnosticsPage {
+    snapshot: Option<MetricsSnapshot>,
+}
+
+impl DiagnosticsPage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, snapshot: MetricsSnapshot) {
+        self.snapshot = Some(snapshot);
+    }
+}
+
+impl Drawable for DiagnosticsPage {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        target.clear(BinaryColor::Off)?;
+
+        let text_style = MonoTextStyle::new(&FONT_4X6, BinaryColor::On);
+        let Some(m) = &self.snapshot else {
+            Text::with_alignment(
+                "collecting metrics...",
+                Point::new(64, 18),
+                text_style,
+                Alignment::Center,
+            )
+            .draw(target)?;
+            return Ok(());
+        };
+        const UNKNOWN: fn() -> String = || "?".to_string();
+        let rss = m
+            .process
+            .rss_kb
+            .map(|rss_kb| format!("{rss_kb}kB"))
+            .unwrap_or_else(UNKNOWN);
+        let cpu = m
+            .process
+            .cpu_percent
+            .map(|cpu_percent| format!("{cpu_percent:.1}%"))
+            .unwrap_or_else(UNKNOWN);
+        let lines = [
+            format!("RPICT  ok {} err {}", m.rpict_frames_decoded, m.rpict_frames_rejected),
+            format!(
+                "LINKY  ok {} err {} cks {}",
+                m.linky_frames_decoded, m.linky_frames_rejected, m.linky_checksum_errors
+            ),
+            format!(
+                "INFLUX {}ms fail {} lag {}",
+                m.influxdb_latency_ms, m.influxdb_failures, m.broadcast_lagged
+            ),
+            format!("RSS {rss} CPU {cpu} LCD {}ms", m.display_flush_ms),
+        ];
+        Text::with_alignment(&lines.join("\n"), Point::new(1, 8), text_style, Alignment::Left).draw(target)?;
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -20,13 +20,27 @@ impl SparkLine {
     pub fn new(bottom_left: Point, label: String, max_value: f32) -> Self {
         Self {
             bottom_left,
-            label: label[..2].to_string(),
+            label,
             max_value,
             value: 0.0,
             value_max: 0.0,
         }
     }
 
+    /// Labels longer than 4 characters leave a very short gauge.
+    pub fn set_label(&mut self, label: String) {
+        self.label = label;
+    }
+
+    pub fn set_max_value(&mut self, max_value: f32) {
+        self.max_value = max_value;
+    }
+
+    /// Replaces the max seen value, eg. with a peak tracked over a period.
+    pub fn set_value_max(&mut self, value_max: f32) {
+        self.value_max = value_max;
+    }
+
     pub fn update(&mut self, value: f32) {
         self.value = value;
         if value > self.value_max {
@@ -94,7 +108,7 @@ mod tests {
         assert!(
             matches!(actual, SparkLine { bottom_left, label, max_value, value, value_max }
             if bottom_left == point
-            && label == "la"
+            && label == "label"
             && max_value == 8000.0
             && value == 0.0
             && value_max == 0.0)
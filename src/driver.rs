@@ -1,4 +1,7 @@
+pub mod display;
 pub mod error;
 pub mod linky;
 pub mod rpict;
+pub mod serial;
 pub mod ssd1305;
+pub mod virtual_display;
@@ -0,0 +1,15 @@
+use std::convert::Infallible;
+
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+
+use crate::driver::error::CommError;
+
+/// A monochrome display the [`DisplayActor`](crate::actor::display::DisplayActor) can draw pages on.
+pub trait DisplayDriver: DrawTarget<Color = BinaryColor, Error = Infallible> {
+    fn begin(&mut self) -> Result<(), CommError>;
+    fn display_on(&mut self) -> Result<(), CommError>;
+    fn display_off(&mut self) -> Result<(), CommError>;
+    fn clear_display(&mut self) -> Result<(), CommError>;
+    fn flush(&mut self) -> Result<(), CommError>;
+}
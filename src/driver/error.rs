@@ -1,24 +1,96 @@
 use std::error::Error;
 use std::fmt::{Display, Formatter, Result};
+use std::io;
 
 #[derive(Debug, PartialEq, Eq)]
-pub struct ParseError;
+pub enum ParseError {
+    /// Data doesn't follow the expected frame structure
+    Framing(String),
+    /// A TIC group checksum doesn't match its content
+    Checksum { group: String, expected: u8, actual: u8 },
+    /// A mandatory field is absent from the frame
+    MissingField(&'static str),
+    /// A field value can't be parsed into the expected type
+    InvalidField { label: &'static str, value: String },
+    /// The frame carries more fields than expected
+    TrailingData(String),
+}
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        write!(f, "failed to parse frame")
+        match self {
+            ParseError::Framing(data) => write!(f, "malformed data {data:?}"),
+            ParseError::Checksum {
+                group,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "bad checksum for group {group:?}, expected {:?} got {:?}",
+                *expected as char, *actual as char
+            ),
+            ParseError::MissingField(label) => write!(f, "missing field {label}"),
+            ParseError::InvalidField { label, value } => write!(f, "invalid value {value:?} for field {label}"),
+            ParseError::TrailingData(data) => write!(f, "unexpected trailing data {data:?}"),
+        }
     }
 }
 
 impl Error for ParseError {}
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct CommError;
+#[derive(Debug)]
+pub enum CommError {
+    /// The driver was built without a serial port path nor a source
+    MissingPortPath,
+    /// The serial port can't be opened or configured
+    PortOpen {
+        path: String,
+        source: tokio_serial::Error,
+    },
+    Spi(rppal::spi::Error),
+    Gpio(rppal::gpio::Error),
+    /// Reading a device failed
+    Io(io::Error),
+}
 
 impl Display for CommError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        write!(f, "communication with device failed")
+        match self {
+            CommError::MissingPortPath => write!(f, "no serial port path provided"),
+            CommError::PortOpen { path, source } => write!(f, "cannot open serial port {path}: {source}"),
+            CommError::Spi(source) => write!(f, "SPI communication failed: {source}"),
+            CommError::Gpio(source) => write!(f, "GPIO access failed: {source}"),
+            CommError::Io(source) => write!(f, "I/O failed: {source}"),
+        }
+    }
+}
+
+impl Error for CommError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CommError::MissingPortPath => None,
+            CommError::PortOpen { source, .. } => Some(source),
+            CommError::Spi(source) => Some(source),
+            CommError::Gpio(source) => Some(source),
+            CommError::Io(source) => Some(source),
+        }
+    }
+}
+
+impl From<rppal::spi::Error> for CommError {
+    fn from(error: rppal::spi::Error) -> Self {
+        CommError::Spi(error)
     }
 }
 
-impl Error for CommError {}
+impl From<rppal::gpio::Error> for CommError {
+    fn from(error: rppal::gpio::Error) -> Self {
+        CommError::Gpio(error)
+    }
+}
+
+impl From<io::Error> for CommError {
+    fn from(error: io::Error) -> Self {
+        CommError::Io(error)
+    }
+}
@@ -1,98 +1,319 @@
 use std::collections::HashMap;
-use std::error::Error;
-use std::path::Path;
-use std::rc::Rc;
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::Arc;
 
+use bytes::BytesMut;
 use chrono::{DateTime, Utc};
-use rppal::uart::{Parity, Uart};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncRead;
+use tokio_serial::{DataBits, Parity};
+use tokio_util::codec::Decoder;
 
-use crate::driver::error::ParseError;
+use crate::driver::error::{CommError, ParseError};
+use crate::driver::serial;
+use crate::driver::serial::Source;
+use crate::metrics;
+use crate::metrics::METRICS;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct LinkyFrame {
-    pub adco: String, // electric meter address
-    pub ptec: String, // current tariff period
-    pub hchc: u32,    // heures creuses index, in watts
-    pub hchp: u32,    // heures pleines index, in watts
+    pub adco: String,                // electric meter address
+    pub ptec: String,                // current tariff period, with the day color on Tempo meters, eg. HPJR
+    pub hchc: u32,                   // heures creuses index, Tempo colors summed, EJPHN on EJP meters, in watts
+    pub hchp: u32,                   // heures pleines index, Tempo colors summed, EJPHPM on EJP meters, in watts
+    pub isousc: Option<u16>,         // subscribed current, in amperes
+    pub iinst: [Option<u16>; 3], // instantaneous current per phase (IINST1-3, or IINST on single-phase meters), in amperes
+    pub adps: Option<u16>,       // subscribed power overrun warning, only sent while it lasts, in amperes
+    pub tempo: Option<TempoIndices>, // indices per day color on Tempo meters
     pub timestamp: DateTime<Utc>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// Tempo indices, in watts, by day color: blue, white and red.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TempoIndices {
+    pub hc: [u32; 3],
+    pub hp: [u32; 3],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TempoColor {
+    Blue,
+    White,
+    Red,
+}
+
+impl Display for TempoColor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TempoColor::Blue => write!(f, "blue"),
+            TempoColor::White => write!(f, "white"),
+            TempoColor::Red => write!(f, "red"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
 pub enum TariffPeriod {
     HC,
     HP,
+    /// EJP normal hours
+    HN,
+    /// EJP peak day hours (pointe mobile)
+    PM,
     Unknown,
 }
 
 impl LinkyFrame {
+    const TEMPO_HC_LABELS: [&'static str; 3] = ["BBRHCJB", "BBRHCJW", "BBRHCJR"];
+    const TEMPO_HP_LABELS: [&'static str; 3] = ["BBRHPJB", "BBRHPJW", "BBRHPJR"];
+
     fn parse(map: &HashMap<String, String>, dt_gen: &dyn Fn() -> DateTime<Utc>) -> Result<Self, ParseError> {
-        fn extract<T: FromStr>(option: Option<&String>) -> Result<T, ParseError> {
-            option.ok_or(ParseError)?.parse().or(Err(ParseError))
+        let extract = |label: &'static str| -> Result<_, ParseError> {
+            let value = map.get(label).ok_or(ParseError::MissingField(label))?;
+            Ok((label, value))
+        };
+        fn parse<T: FromStr>((label, value): (&'static str, &String)) -> Result<T, ParseError> {
+            value.parse().map_err(|_| ParseError::InvalidField {
+                label,
+                value: value.clone(),
+            })
         }
+        let optional = |label: &'static str| -> Result<Option<u16>, ParseError> {
+            map.get(label).map(|value| parse((label, value))).transpose()
+        };
+        // Tempo meters split each index by day color
+        let is_tempo = !map.contains_key("HCHC") && map.contains_key("BBRHCJB");
+        let colors = |labels: [&'static str; 3]| -> Result<[u32; 3], ParseError> {
+            Ok([
+                parse(extract(labels[0])?)?,
+                parse(extract(labels[1])?)?,
+                parse(extract(labels[2])?)?,
+            ])
+        };
+        let index = |label: &'static str, tempo_labels: [&'static str; 3], ejp_label: &'static str| {
+            // EJP meters send normal hours and peak days indices instead
+            if !map.contains_key(label) && map.contains_key(ejp_label) {
+                return parse(extract(ejp_label)?);
+            }
+            if map.contains_key(label) || !map.contains_key(tempo_labels[0]) {
+                return parse(extract(label)?);
+            }
+            tempo_labels.into_iter().try_fold(0u32, |sum, label| {
+                let field = extract(label)?;
+                sum.checked_add(parse(field)?).ok_or_else(|| ParseError::InvalidField {
+                    label,
+                    value: field.1.clone(),
+                })
+            })
+        };
         let frame = LinkyFrame {
-            adco: extract(map.get("ADCO"))?,
-            ptec: extract(map.get("PTEC"))?,
-            hchc: extract(map.get("HCHC"))?,
-            hchp: extract(map.get("HCHP"))?,
+            adco: parse(extract("ADCO")?)?,
+            ptec: parse(extract("PTEC")?)?,
+            hchc: index("HCHC", Self::TEMPO_HC_LABELS, "EJPHN")?,
+            hchp: index("HCHP", Self::TEMPO_HP_LABELS, "EJPHPM")?,
+            isousc: optional("ISOUSC")?,
+            iinst: [
+                optional("IINST1")?.or(optional("IINST")?),
+                optional("IINST2")?,
+                optional("IINST3")?,
+            ],
+            adps: optional("ADPS")?,
+            tempo: match is_tempo {
+                true => Some(TempoIndices {
+                    hc: colors(Self::TEMPO_HC_LABELS)?,
+                    hp: colors(Self::TEMPO_HP_LABELS)?,
+                }),
+                false => None,
+            },
             timestamp: dt_gen(),
         };
         Ok(frame)
     }
 
     pub fn ptec(&self) -> TariffPeriod {
-        match self.ptec.as_str() {
-            "HC" => TariffPeriod::HC,
-            "HP" => TariffPeriod::HP,
+        match self.ptec.get(..2) {
+            Some("HC") => TariffPeriod::HC,
+            Some("HP") => TariffPeriod::HP,
+            Some("HN") => TariffPeriod::HN,
+            Some("PM") => TariffPeriod::PM,
             _ => TariffPeriod::Unknown,
         }
     }
+
+    /// Whether the meter is on an EJP contract, its indices being the normal hours and peak days ones.
+    pub fn is_ejp(&self) -> bool {
+        matches!(self.ptec(), TariffPeriod::HN | TariffPeriod::PM)
+    }
+
+    /// Tempo day color, from the PTEC suffix, eg. HPJR.
+    pub fn tempo_color(&self) -> Option<TempoColor> {
+        match self.ptec.get(2..) {
+            Some("JB") => Some(TempoColor::Blue),
+            Some("JW") => Some(TempoColor::White),
+            Some("JR") => Some(TempoColor::Red),
+            _ => None,
+        }
+    }
+
+    /// Whether the Tempo day color is red, the most expensive one.
+    pub fn is_tempo_red_day(&self) -> bool {
+        self.tempo_color() == Some(TempoColor::Red)
+    }
 }
 
-struct LinkyIterator {
-    uart: Uart,
-    buffer: [u8; 1],
+/// Splits a TIC mode Historique group into its label and value, once its checksum is verified.
+/// Checksum is computed on label, separator and value, see 6.2.3. Couche liaison — Page : 18/38
+fn parse_group(group: &[u8]) -> Result<(&str, &str), ParseError> {
+    let framing = || ParseError::Framing(String::from_utf8_lossy(group).into_owned());
+    let [body @ .., separator, checksum] = group else {
+        return Err(framing());
+    };
+    if !matches!(separator, b' ' | b'\t') {
+        return Err(framing());
+    }
+    let text = serial::ascii(body).map_err(|_| framing())?;
+    let expected = (body.iter().map(|b| *b as u32).sum::<u32>() & 0x3F) as u8 + 0x20;
+    if expected != *checksum {
+        return Err(ParseError::Checksum {
+            group: String::from_utf8_lossy(group).into_owned(),
+            expected,
+            actual: *checksum,
+        });
+    }
+    text.split_once([' ', '\t']).ok_or_else(framing)
 }
 
-impl Iterator for LinkyIterator {
-    type Item = char;
+/// Decodes TIC mode Historique frames (vs. new Standard mode), delimited by STX and ETX.
+pub struct LinkyCodec {
+    dt_gen: Arc<dyn Fn() -> DateTime<Utc> + Send + Sync>,
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            match self.uart.read(&mut self.buffer) {
-                Ok(size) if size > 0 => return Some(self.buffer[0].into()),
-                Ok(_size) => continue,
-                Err(_) => {
-                    log::error!("error while reading bytestream");
-                    return None;
+impl LinkyCodec {
+    const STX: u8 = 0x02; // frame start
+    const ETX: u8 = 0x03; // frame end
+    const LF: u8 = 0x0A; // group start
+    const CR: u8 = 0x0D; // group end
+    const KEYS: [&'static str; 2] = ["ADCO", "PTEC"];
+    // indices are HCHC and HCHP, BBR* on Tempo meters or EJPHN and EJPHPM on EJP ones
+    const OPTIONAL_KEYS: [&'static str; 16] = [
+        "HCHC", "HCHP", "BBRHCJB", "BBRHPJB", "BBRHCJW", "BBRHPJW", "BBRHCJR", "BBRHPJR", "EJPHN", "EJPHPM", "ISOUSC",
+        "IINST", "IINST1", "IINST2", "IINST3", "ADPS",
+    ];
+    // a frame is about 300 bytes long, anything longer is noise
+    const MAX_FRAME_LENGTH: usize = 1024;
+
+    // < LF > (0x0A) | Etiquette | < HT > (0x09) | Donnée | < HT > (0x09) | Checksum | < CR > (0x0D)
+    // See https://www.enedis.fr/sites/default/files/Enedis-NOI-CPT_54E.pdf
+    // 5.3.6. Couche liaison — Page : 13/38
+    fn parse_frame(&self, data: &[u8]) -> Option<LinkyFrame> {
+        let mut map = HashMap::<String, String>::new();
+        // the last chunk isn't terminated by CR, so it isn't a group
+        let mut chunks = data.split(|b| *b == Self::CR).collect::<Vec<_>>();
+        chunks.pop();
+        for chunk in chunks {
+            let group = chunk.rsplit(|b| *b == Self::LF).next().unwrap_or_default();
+            match parse_group(group) {
+                Ok((key, value)) if Self::KEYS.contains(&key) || Self::OPTIONAL_KEYS.contains(&key) => {
+                    // PTEC values are padded with dots, e.g. "HP.." but "HPJR" on Tempo meters
+                    let value = match key {
+                        "PTEC" => value.trim_end_matches('.'),
+                        _ => value,
+                    };
+                    map.insert(key.into(), value.into());
                 }
+                Ok(_) => {}
+                Err(e) => {
+                    if matches!(e, ParseError::Checksum { .. }) {
+                        metrics::increment(&METRICS.linky_checksum_errors);
+                    }
+                    log::warn!("skipping Linky group: {e}");
+                }
+            }
+        }
+        if !Self::KEYS.iter().all(|key| map.contains_key(*key)) {
+            return None;
+        }
+        match LinkyFrame::parse(&map, &*self.dt_gen) {
+            Ok(frame) => Some(frame),
+            Err(e) => {
+                log::warn!("couldn't extract LinkyFrame from map {map:?}: {e}");
+                None
             }
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
-enum Frame {
-    Start,
-    DataSet(String),
-    End,
+impl Default for LinkyCodec {
+    fn default() -> Self {
+        Self {
+            dt_gen: Arc::new(Utc::now),
+        }
+    }
+}
+
+impl Decoder for LinkyCodec {
+    type Item = LinkyFrame;
+    type Error = CommError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // characters are sent as 7 bits with even parity, drop the parity bit in case
+        // the serial port wasn't configured to do it
+        src.iter_mut().for_each(|b| *b &= 0x7F);
+        loop {
+            // data before a frame start belongs to a truncated frame
+            let Some(start) = src.iter().position(|b| *b == Self::STX) else {
+                src.clear();
+                return Ok(None);
+            };
+            let _ = src.split_to(start);
+            match src[1..].iter().position(|b| matches!(*b, Self::STX | Self::ETX)) {
+                Some(end) if src[end + 1] == Self::ETX => {
+                    let frame = src.split_to(end + 2);
+                    if let Some(frame) = self.parse_frame(&frame[1..end + 1]) {
+                        metrics::increment(&METRICS.linky_frames_decoded);
+                        return Ok(Some(frame));
+                    }
+                    metrics::increment(&METRICS.linky_frames_rejected);
+                }
+                // a new frame starts before the current one ends
+                Some(end) => {
+                    let _ = src.split_to(end + 1);
+                }
+                None => {
+                    if src.len() > Self::MAX_FRAME_LENGTH {
+                        log::warn!("discarding {} bytes without frame end", src.len());
+                        src.clear();
+                    }
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let frame = self.decode(src)?;
+        if frame.is_none() {
+            src.clear(); // an unterminated frame is lost
+        }
+        Ok(frame)
+    }
 }
 
 pub struct Linky {
     port_path: Option<String>,
-    source_iter: Option<Box<dyn Iterator<Item = char>>>,
-    // Using Rc because Box is not easily Copy-able
-    // See https://users.rust-lang.org/t/how-to-clone-a-boxed-closure/31035
-    dt_gen: Rc<dyn Fn() -> DateTime<Utc>>,
+    source: Option<Source>,
+    dt_gen: Arc<dyn Fn() -> DateTime<Utc> + Send + Sync>,
 }
 
 impl Linky {
     pub fn builder() -> Self {
         Self {
             port_path: None,
-            source_iter: None,
-            dt_gen: Rc::new(Utc::now),
+            source: None,
+            dt_gen: Arc::new(Utc::now),
         }
     }
 
@@ -101,91 +322,40 @@ impl Linky {
         self
     }
 
-    pub fn with_source_iter(mut self, source_iter: impl Iterator<Item = char> + 'static) -> Self {
-        self.source_iter = Some(Box::new(source_iter));
+    pub fn with_source(mut self, source: impl AsyncRead + Send + Unpin + 'static) -> Self {
+        self.source = Some(Box::new(source));
         self
     }
 
-    pub fn with_dt_gen(mut self, dt_gen: impl Fn() -> DateTime<Utc> + 'static) -> Self {
-        self.dt_gen = Rc::new(dt_gen);
+    pub fn with_dt_gen(mut self, dt_gen: impl Fn() -> DateTime<Utc> + Send + Sync + 'static) -> Self {
+        self.dt_gen = Arc::new(dt_gen);
         self
     }
 
-    pub fn build(self) -> Result<impl Iterator<Item = LinkyFrame>, Box<dyn Error>> {
-        const STX: char = '\u{02}'; // frame start
-        const ETX: char = '\u{03}'; // frame end
-        const LF: char = '\u{0A}'; // group start
-        const CR: char = '\u{0D}'; // group end
-        const KEYS: [&str; 4] = ["ADCO", "PTEC", "HCHC", "HCHP"];
+    pub fn build(self) -> Result<impl Stream<Item = LinkyFrame> + Send + Unpin, CommError> {
         let Self {
             port_path,
-            source_iter,
+            source,
             dt_gen,
         } = self;
-        let source_iter = source_iter
-            .or_else(|| {
-                let port_path = port_path.expect("no port path provided");
+        let source = match source {
+            Some(source) => source,
+            None => {
+                let path = port_path.ok_or(CommError::MissingPortPath)?;
                 // See https://www.enedis.fr/sites/default/files/Enedis-NOI-CPT_54E.pdf
                 // 5.3.5. Couche physique — Page : 12/38
-                let mut uart = Uart::with_path(Path::new(&port_path), 1_200, Parity::Even, 7, 1).unwrap();
-                uart.set_read_mode(1, Duration::default()).unwrap();
-                Some(Box::new(LinkyIterator { uart, buffer: [0u8] }))
-            })
-            .expect("no source provided");
-        // TIC mode Historique (vs. new Standard mode)
-        // < LF > (0x0A) | Etiquette | < HT > (0x09) | Donnée | < HT > (0x09) | Checksum | < CR > (0x0D)
-        // See https://www.enedis.fr/sites/default/files/Enedis-NOI-CPT_54E.pdf
-        // 5.3.6. Couche liaison — Page : 13/38
-        let iter = source_iter
-            //.skip_while(|c| future::ready(c != '\n'))
-            .scan(String::new(), |buffer, c| match c {
-                STX => Some(Some(Frame::Start)),
-                ETX => Some(Some(Frame::End)),
-                LF => {
-                    buffer.clear();
-                    Some(None)
-                }
-                CR => Some(Some(Frame::DataSet(buffer.clone()))),
-                other => {
-                    buffer.push(other);
-                    Some(None)
-                }
-            })
-            .flatten()
-            .scan(HashMap::<String, String>::new(), |buffer, frame| match frame {
-                Frame::Start => {
-                    buffer.clear();
-                    Some(None)
-                }
-                Frame::End => Some(Some(buffer.clone())),
-                Frame::DataSet(string) => match string.split_ascii_whitespace().collect::<Vec<_>>().as_slice() {
-                    [key, value, ..] if KEYS.contains(key) => {
-                        let (key, value) = (*key, *value);
-                        let value = match key {
-                            "PTEC" => &value[..2],
-                            _ => value,
-                        };
-                        buffer.insert(key.into(), value.into());
-                        Some(None)
-                    }
-                    _ => Some(None),
-                },
-            })
-            .flatten()
-            .filter(|map| KEYS.iter().all(|key| map.contains_key(&key.to_string())))
-            .filter_map(move |map| {
-                LinkyFrame::parse(&map, &*dt_gen.clone()).ok().or_else(|| {
-                    log::warn!("couldn't extract LinkyFrame from map: {map:?}");
-                    None
-                })
-            });
-
-        Ok(iter)
+                serial::open(path, 1_200, DataBits::Seven, Parity::Even)?
+            }
+        };
+        Ok(serial::frames(source, LinkyCodec { dt_gen }))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use futures::StreamExt;
+    use std::io::Cursor;
+
     use super::*;
 
     const ADCO: &str = "\nADCO 041876097767 U\r";
@@ -195,7 +365,7 @@ mod tests {
                          \nISOUSC 30 9\r\
                          \nHCHC 019650909 -\r\
                          \nHCHP 043280553 1\r\
-                         \nPTEC HP..\r\
+                         \nPTEC HP..  \r\
                          \nIINST1 018 Q\r\
                          \nIINST2 019 S\r\
                          \nIINST3 017 R\r\
@@ -215,68 +385,307 @@ mod tests {
             ptec: "HP".to_string(),
             hchc: 19_650_909,
             hchp: 43_280_553,
+            isousc: Some(30),
+            iinst: [Some(18), Some(19), Some(17)],
+            adps: None,
+            tempo: None,
             timestamp: now,
         }
     }
 
-    #[test]
-    fn test_linky_iterator() {
+    #[tokio::test]
+    async fn test_linky_stream() {
         // Given
         let now = Utc::now();
-        let input = FRAME.chars();
+        let input = FRAME.as_bytes();
         // When
         let frames = Linky::builder()
-            .with_source_iter(input)
+            .with_source(input)
             .with_dt_gen(move || now)
             .build()
             .unwrap();
         // Then
-        assert_eq!(frames.collect::<Vec<_>>(), vec![frame(now)]);
+        assert_eq!(frames.collect::<Vec<_>>().await, vec![frame(now)]);
     }
 
-    #[test]
-    fn test_linky_iterator_two_frames() {
+    #[tokio::test]
+    async fn test_linky_stream_two_frames() {
         // Given
         let now = Utc::now();
-        let input = FRAME.chars().cycle().take(FRAME.len() * 2);
+        let input = Cursor::new(FRAME.repeat(2));
         // When
         let frames = Linky::builder()
-            .with_source_iter(input)
+            .with_source(input)
             .with_dt_gen(move || now)
             .build()
             .unwrap();
         // Then
-        assert_eq!(frames.collect::<Vec<_>>(), vec![frame(now), frame(now)]);
+        assert_eq!(frames.collect::<Vec<_>>().await, vec![frame(now), frame(now)]);
     }
 
-    #[test]
-    fn test_linky_iterator_frame_truncated_left() {
+    #[tokio::test]
+    async fn test_linky_stream_frame_truncated_left() {
+        // Given
+        let now = Utc::now();
+        let input = Cursor::new(format!("{}{ADCO}", &FRAME[10..]));
+        // When
+        let frames = Linky::builder()
+            .with_source(input)
+            .with_dt_gen(move || now)
+            .build()
+            .unwrap();
+        // Then
+        assert_eq!(frames.collect::<Vec<_>>().await, Vec::new());
+    }
+
+    #[tokio::test]
+    async fn test_linky_stream_frame_truncated_right() {
+        // Given
+        let now = Utc::now();
+        let input = Cursor::new(format!("{}{ADCO}", &FRAME[..10]));
+        // When
+        let frames = Linky::builder()
+            .with_source(input)
+            .with_dt_gen(move || now)
+            .build()
+            .unwrap();
+        // Then
+        assert_eq!(frames.collect::<Vec<_>>().await, Vec::new());
+    }
+
+    #[tokio::test]
+    async fn test_linky_stream_bad_checksum() {
+        // Given
+        let now = Utc::now();
+        let input = Cursor::new(FRAME.replace("HCHC 019650909 -", "HCHC 019650908 -"));
+        // When
+        let frames = Linky::builder()
+            .with_source(input)
+            .with_dt_gen(move || now)
+            .build()
+            .unwrap();
+        // Then
+        assert_eq!(frames.collect::<Vec<_>>().await, Vec::new());
+    }
+
+    #[tokio::test]
+    async fn test_linky_stream_with_leading_noise() {
         // Given
         let now = Utc::now();
-        let input = FRAME[10..].chars().chain(ADCO.chars());
+        let input = Cursor::new(format!("{ADCO}\u{03}\u{02}{ADCO}{FRAME}"));
         // When
         let frames = Linky::builder()
-            .with_source_iter(input)
+            .with_source(input)
             .with_dt_gen(move || now)
             .build()
             .unwrap();
         // Then
-        assert_eq!(frames.collect::<Vec<_>>(), Vec::new());
+        assert_eq!(frames.collect::<Vec<_>>().await, vec![frame(now)]);
     }
 
     #[test]
-    fn test_linky_iterator_frame_truncated_right() {
+    fn test_linky_codec_split_reads() {
+        // Given
+        let now = Utc::now();
+        let mut codec = LinkyCodec {
+            dt_gen: Arc::new(move || now),
+        };
+        let mut buffer = BytesMut::from(&FRAME[..100]);
+        // When & Then
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+        buffer.extend_from_slice(&FRAME.as_bytes()[100..]);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(frame(now)));
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_linky_stream_with_parity_bits() {
+        // Given
+        let now = Utc::now();
+        let input = FRAME
+            .bytes()
+            .map(|b| if b.count_ones() % 2 == 1 { b | 0x80 } else { b })
+            .collect::<Vec<_>>();
+        // When
+        let frames = Linky::builder()
+            .with_source(Cursor::new(input))
+            .with_dt_gen(move || now)
+            .build()
+            .unwrap();
+        // Then
+        assert_eq!(frames.collect::<Vec<_>>().await, vec![frame(now)]);
+    }
+
+    #[tokio::test]
+    async fn test_linky_stream_with_short_ptec() {
+        // Given
+        let now = Utc::now();
+        let input = Cursor::new(FRAME.replace("PTEC HP..  ", "PTEC H 4"));
+        // When
+        let frames = Linky::builder()
+            .with_source(input)
+            .with_dt_gen(move || now)
+            .build()
+            .unwrap();
+        // Then
+        let frames = frames.collect::<Vec<_>>().await;
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].ptec(), TariffPeriod::Unknown);
+    }
+
+    #[tokio::test]
+    async fn test_linky_stream_single_phase_overrun() {
         // Given
         let now = Utc::now();
-        let input = FRAME[..10].chars().chain(ADCO.chars());
+        let input = Cursor::new(
+            FRAME
+                .replace("\nIINST1 018 Q\r\nIINST2 019 S\r\nIINST3 017 R\r", "\nIINST 032 \\\r")
+                .replace("\nIMAX1 060 6\r", "\nADPS 032 =\r"),
+        );
         // When
         let frames = Linky::builder()
-            .with_source_iter(input)
+            .with_source(input)
             .with_dt_gen(move || now)
             .build()
             .unwrap();
         // Then
-        assert_eq!(frames.collect::<Vec<_>>(), Vec::new());
+        let frames = frames.collect::<Vec<_>>().await;
+        assert_eq!(
+            frames,
+            vec![LinkyFrame {
+                iinst: [Some(32), None, None],
+                adps: Some(32),
+                ..frame(now)
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_linky_stream_tempo() {
+        // Given
+        let now = Utc::now();
+        let input = Cursor::new(
+            "\u{02}\
+             \nADCO 041876097767 U\r\
+             \nOPTARIF BBR( S\r\
+             \nISOUSC 30 9\r\
+             \nBBRHCJB 001000000 ^\r\
+             \nBBRHPJB 002000000 ,\r\
+             \nBBRHCJW 000100000 3\r\
+             \nBBRHPJW 000200000 A\r\
+             \nBBRHCJR 000010000 .\r\
+             \nBBRHPJR 000020000 <\r\
+             \nPTEC HPJR  \r\
+             \nDEMAIN ---- \"\r\
+             \nIINST 012 Z\r\
+             \u{03}",
+        );
+        // When
+        let frames = Linky::builder()
+            .with_source(input)
+            .with_dt_gen(move || now)
+            .build()
+            .unwrap();
+        // Then
+        let frames = frames.collect::<Vec<_>>().await;
+        assert_eq!(
+            frames,
+            vec![LinkyFrame {
+                ptec: "HPJR".to_string(),
+                hchc: 1_110_000,
+                hchp: 2_220_000,
+                iinst: [Some(12), None, None],
+                tempo: Some(TempoIndices {
+                    hc: [1_000_000, 100_000, 10_000],
+                    hp: [2_000_000, 200_000, 20_000],
+                }),
+                ..frame(now)
+            }]
+        );
+        assert_eq!(frames[0].ptec(), TariffPeriod::HP);
+        assert_eq!(frames[0].tempo_color(), Some(TempoColor::Red));
+        assert!(frames[0].is_tempo_red_day());
+    }
+
+    #[tokio::test]
+    async fn test_linky_stream_ejp() {
+        // Given
+        let now = Utc::now();
+        let input = Cursor::new(
+            "\u{02}\
+             \nADCO 041876097767 U\r\
+             \nOPTARIF EJP. \"\r\
+             \nISOUSC 30 9\r\
+             \nEJPHN 001234567 A\r\
+             \nEJPHPM 000123456 I\r\
+             \nPTEC PM.. %\r\
+             \nIINST 012 Z\r\
+             \u{03}",
+        );
+        // When
+        let frames = Linky::builder()
+            .with_source(input)
+            .with_dt_gen(move || now)
+            .build()
+            .unwrap();
+        // Then
+        let frames = frames.collect::<Vec<_>>().await;
+        assert_eq!(
+            frames,
+            vec![LinkyFrame {
+                ptec: "PM".to_string(),
+                hchc: 1_234_567,
+                hchp: 123_456,
+                iinst: [Some(12), None, None],
+                ..frame(now)
+            }]
+        );
+        assert_eq!(frames[0].ptec(), TariffPeriod::PM);
+        assert!(frames[0].is_ejp());
+        assert_eq!(frames[0].tempo_color(), None);
+    }
+
+    #[test]
+    fn test_linky_codec_random_bytes() {
+        // Given
+        let mut codec = LinkyCodec::default();
+        let mut seed = 0x2545_f491_u32;
+        let mut input = (0..64 * 1024)
+            .map(|_| {
+                // xorshift, biased towards protocol control bytes
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                match seed % 8 {
+                    0 => [0x02, 0x03, b'\n', b'\r', b' '][(seed >> 8) as usize % 5],
+                    _ => (seed >> 8) as u8,
+                }
+            })
+            .collect::<BytesMut>();
+        // When & Then
+        while codec.decode(&mut input).unwrap().is_some() {}
+        codec.decode_eof(&mut input).unwrap();
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_parse_group() {
+        assert_eq!(parse_group(b"ADCO 041876097767 U"), Ok(("ADCO", "041876097767")));
+        assert_eq!(parse_group(b"PTEC HP..  "), Ok(("PTEC", "HP..")));
+        assert_eq!(
+            parse_group(b"ADCO 041876097767 V"),
+            Err(ParseError::Checksum {
+                group: "ADCO 041876097767 V".to_string(),
+                expected: b'U',
+                actual: b'V'
+            })
+        );
+        assert_eq!(parse_group(b"A"), Err(ParseError::Framing("A".to_string())));
+        assert_eq!(parse_group(b"ADCOU"), Err(ParseError::Framing("ADCOU".to_string())));
+        assert_eq!(
+            parse_group(b"AD\x01CO 041876097767 V"),
+            Err(ParseError::Framing("AD\u{1}CO 041876097767 V".to_string()))
+        );
     }
 
     #[test]
@@ -288,6 +697,10 @@ mod tests {
         map.insert("PTEC".to_string(), "HP".to_string());
         map.insert("HCHC".to_string(), "019650909".to_string());
         map.insert("HCHP".to_string(), "043280553".to_string());
+        map.insert("ISOUSC".to_string(), "30".to_string());
+        map.insert("IINST1".to_string(), "018".to_string());
+        map.insert("IINST2".to_string(), "019".to_string());
+        map.insert("IINST3".to_string(), "017".to_string());
         // When
         let result = LinkyFrame::parse(&map, &|| now).unwrap();
         // Then
@@ -302,7 +715,51 @@ mod tests {
         // When
         let result = LinkyFrame::parse(&map, &|| now);
         // Then
-        assert_eq!(result, Err(ParseError));
+        assert_eq!(result, Err(ParseError::MissingField("ADCO")));
+    }
+
+    #[test]
+    fn test_linkyframe_parse_with_invalid_value() {
+        // Given
+        let now = Utc::now();
+        let mut map = HashMap::<String, String>::new();
+        map.insert("ADCO".to_string(), "041876097767".to_string());
+        map.insert("PTEC".to_string(), "HP".to_string());
+        map.insert("HCHC".to_string(), "0196509O9".to_string());
+        map.insert("HCHP".to_string(), "043280553".to_string());
+        // When
+        let result = LinkyFrame::parse(&map, &|| now);
+        // Then
+        assert_eq!(
+            result,
+            Err(ParseError::InvalidField {
+                label: "HCHC",
+                value: "0196509O9".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_linkyframe_parse_with_overflowing_tempo_index() {
+        // Given
+        let now = Utc::now();
+        let mut map = HashMap::<String, String>::new();
+        map.insert("ADCO".to_string(), "041876097767".to_string());
+        map.insert("PTEC".to_string(), "HCJW".to_string());
+        map.insert("BBRHCJB".to_string(), "4000000000".to_string());
+        map.insert("BBRHCJW".to_string(), "4000000000".to_string());
+        map.insert("BBRHCJR".to_string(), "000000000".to_string());
+        map.insert("HCHP".to_string(), "043280553".to_string());
+        // When
+        let result = LinkyFrame::parse(&map, &|| now);
+        // Then
+        assert_eq!(
+            result,
+            Err(ParseError::InvalidField {
+                label: "BBRHCJW",
+                value: "4000000000".to_string()
+            })
+        );
     }
 
     #[test]
@@ -318,7 +775,14 @@ mod tests {
         // When
         let result = LinkyFrame::parse(&map, &|| now).unwrap();
         // Then
-        assert_eq!(result, frame(now));
+        assert_eq!(
+            result,
+            LinkyFrame {
+                isousc: None,
+                iinst: [None; 3],
+                ..frame(now)
+            }
+        );
     }
 
     #[test]
@@ -342,6 +806,22 @@ mod tests {
             }
             .ptec()
         );
+        assert_eq!(
+            TariffPeriod::HN,
+            LinkyFrame {
+                ptec: String::from("HN"),
+                ..frame.clone()
+            }
+            .ptec()
+        );
+        assert_eq!(
+            TariffPeriod::PM,
+            LinkyFrame {
+                ptec: String::from("PM"),
+                ..frame.clone()
+            }
+            .ptec()
+        );
         assert_eq!(
             TariffPeriod::Unknown,
             LinkyFrame {
@@ -351,4 +831,129 @@ mod tests {
             .ptec()
         );
     }
+
+    mod properties {
+        use proptest::prelude::*;
+
+        use super::*;
+
+        fn decode_all(input: &[u8], now: DateTime<Utc>) -> Vec<LinkyFrame> {
+            let mut codec = LinkyCodec {
+                dt_gen: Arc::new(move || now),
+            };
+            let mut buffer = BytesMut::from(input);
+            let mut frames = Vec::new();
+            while let Some(frame) = codec.decode_eof(&mut buffer).unwrap() {
+                frames.push(frame);
+            }
+            frames
+        }
+
+        fn group(label: &str, value: &str) -> String {
+            let body = format!("{label} {value}");
+            let checksum = (body.bytes().map(|b| b as u32).sum::<u32>() & 0x3F) as u8 + 0x20;
+            format!("\n{body} {}\r", checksum as char)
+        }
+
+        fn arb_frame() -> impl Strategy<Value = LinkyFrame> {
+            (
+                "[0-9]{12}",
+                prop::sample::select(vec!["HC", "HP"]),
+                0..1_000_000_000u32,
+                0..1_000_000_000u32,
+            )
+                .prop_map(|(adco, ptec, hchc, hchp)| LinkyFrame {
+                    adco,
+                    ptec: ptec.to_string(),
+                    hchc,
+                    hchp,
+                    isousc: Some(30),
+                    ..LinkyFrame::default()
+                })
+        }
+
+        fn to_bytes(frame: &LinkyFrame) -> Vec<u8> {
+            let groups = [
+                group("ADCO", &frame.adco),
+                group("OPTARIF", "HC.."),
+                group("ISOUSC", "30"),
+                group("HCHC", &format!("{:09}", frame.hchc)),
+                group("HCHP", &format!("{:09}", frame.hchp)),
+                group("PTEC", &format!("{}..", frame.ptec)),
+                group("PAPP", "12690"),
+            ];
+            format!("\u{02}{}\u{03}", groups.concat()).into_bytes()
+        }
+
+        fn tempo_bytes(indices: [u32; 6]) -> Vec<u8> {
+            let labels = ["BBRHCJB", "BBRHPJB", "BBRHCJW", "BBRHPJW", "BBRHCJR", "BBRHPJR"];
+            let groups = [group("ADCO", "041876097767"), group("PTEC", "HPJW")]
+                .into_iter()
+                .chain(
+                    labels
+                        .iter()
+                        .zip(indices)
+                        .map(|(label, index)| group(label, &format!("{index:09}"))),
+                )
+                .collect::<String>();
+            format!("\u{02}{groups}\u{03}").into_bytes()
+        }
+
+        // frame starts are excluded, with or without parity bit
+        fn arb_garbage() -> impl Strategy<Value = Vec<u8>> {
+            prop::collection::vec(any::<u8>().prop_filter("STX", |b| b & 0x7F != LinkyCodec::STX), 0..512)
+        }
+
+        proptest! {
+            #[test]
+            fn decoding_arbitrary_bytes_never_panics(
+                input in prop::collection::vec(any::<u8>(), 0..4096),
+                tempo_indices in any::<[u32; 6]>()
+            ) {
+                decode_all(&input, Utc::now());
+                // Tempo indices are summed, whatever their values
+                decode_all(&tempo_bytes(tempo_indices), Utc::now());
+            }
+
+            #[test]
+            fn valid_frames_round_trip(frames in prop::collection::vec(arb_frame(), 1..8)) {
+                // Given
+                let input = frames.iter().flat_map(to_bytes).collect::<Vec<_>>();
+                // When
+                let actual = decode_all(&input, DateTime::default());
+                // Then
+                prop_assert_eq!(actual, frames);
+            }
+
+            #[test]
+            fn frames_with_parity_bits_round_trip(frame in arb_frame(), parity in prop::collection::vec(any::<bool>(), 512)) {
+                // Given
+                let input = to_bytes(&frame)
+                    .into_iter()
+                    .zip(parity.into_iter().cycle())
+                    .map(|(b, parity)| if parity { b | 0x80 } else { b })
+                    .collect::<Vec<_>>();
+                // When
+                let actual = decode_all(&input, DateTime::default());
+                // Then
+                prop_assert_eq!(actual, vec![frame]);
+            }
+
+            #[test]
+            fn frames_interleaved_with_garbage_decode(
+                chunks in prop::collection::vec((arb_garbage(), arb_frame()), 1..8)
+            ) {
+                // Given
+                let input = chunks
+                    .iter()
+                    .flat_map(|(garbage, frame)| [garbage.clone(), to_bytes(frame)])
+                    .flatten()
+                    .collect::<Vec<_>>();
+                // When
+                let actual = decode_all(&input, DateTime::default());
+                // Then
+                prop_assert_eq!(actual, chunks.into_iter().map(|(_, frame)| frame).collect::<Vec<_>>());
+            }
+        }
+    }
 }
@@ -1,13 +1,18 @@
-use std::error::Error;
-use std::path::Path;
-use std::rc::Rc;
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::Arc;
 
+use bytes::BytesMut;
 use chrono::{DateTime, Utc};
-use rppal::uart::{Parity, Uart};
+use futures::Stream;
+use tokio::io::AsyncRead;
+use tokio_serial::{DataBits, Parity};
+use tokio_util::codec::Decoder;
 
-use crate::driver::error::ParseError;
+use crate::driver::error::{CommError, ParseError};
+use crate::driver::serial;
+use crate::driver::serial::Source;
+use crate::metrics;
+use crate::metrics::METRICS;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct RpictFrame {
@@ -32,70 +37,110 @@ pub struct RpictFrame {
 
 impl RpictFrame {
     fn parse(input: &str, dt_gen: &dyn Fn() -> DateTime<Utc>) -> Result<Self, ParseError> {
-        fn consume<'a, T: FromStr>(iter: &mut impl Iterator<Item = &'a str>) -> Result<T, ParseError> {
-            iter.next().ok_or(ParseError)?.parse().or(Err(ParseError))
+        fn consume<'a, T: FromStr>(
+            iter: &mut impl Iterator<Item = &'a str>,
+            label: &'static str,
+        ) -> Result<T, ParseError> {
+            let value = iter.next().ok_or(ParseError::MissingField(label))?;
+            value.parse().map_err(|_| ParseError::InvalidField {
+                label,
+                value: value.to_string(),
+            })
         }
         let mut split = input.split_ascii_whitespace().fuse();
         let frame = RpictFrame {
-            node_id: consume(&mut split)?,
-            l1_real_power: consume(&mut split)?,
-            l1_apparent_power: consume(&mut split)?,
-            l1_irms: consume(&mut split)?,
-            l1_vrms: consume(&mut split)?,
-            l1_power_factor: consume(&mut split)?,
-            l2_real_power: consume(&mut split)?,
-            l2_apparent_power: consume(&mut split)?,
-            l2_irms: consume(&mut split)?,
-            l2_vrms: consume(&mut split)?,
-            l2_power_factor: consume(&mut split)?,
-            l3_real_power: consume(&mut split)?,
-            l3_apparent_power: consume(&mut split)?,
-            l3_irms: consume(&mut split)?,
-            l3_vrms: consume(&mut split)?,
-            l3_power_factor: consume(&mut split)?,
+            node_id: consume(&mut split, "node_id")?,
+            l1_real_power: consume(&mut split, "l1_real_power")?,
+            l1_apparent_power: consume(&mut split, "l1_apparent_power")?,
+            l1_irms: consume(&mut split, "l1_irms")?,
+            l1_vrms: consume(&mut split, "l1_vrms")?,
+            l1_power_factor: consume(&mut split, "l1_power_factor")?,
+            l2_real_power: consume(&mut split, "l2_real_power")?,
+            l2_apparent_power: consume(&mut split, "l2_apparent_power")?,
+            l2_irms: consume(&mut split, "l2_irms")?,
+            l2_vrms: consume(&mut split, "l2_vrms")?,
+            l2_power_factor: consume(&mut split, "l2_power_factor")?,
+            l3_real_power: consume(&mut split, "l3_real_power")?,
+            l3_apparent_power: consume(&mut split, "l3_apparent_power")?,
+            l3_irms: consume(&mut split, "l3_irms")?,
+            l3_vrms: consume(&mut split, "l3_vrms")?,
+            l3_power_factor: consume(&mut split, "l3_power_factor")?,
             timestamp: dt_gen(),
         };
         match split.next() {
             None => Ok(frame),
-            Some(_) => Err(ParseError),
+            Some(data) => Err(ParseError::TrailingData(data.to_string())),
         }
     }
 }
 
-struct RpictIterator {
-    uart: Uart,
-    buffer: [u8; 1],
+/// Decodes RPICT frames, sent as space separated values lines.
+pub struct RpictCodec {
+    dt_gen: Arc<dyn Fn() -> DateTime<Utc> + Send + Sync>,
+}
+
+impl RpictCodec {
+    // a frame is about 100 bytes long, anything longer is noise
+    const MAX_LINE_LENGTH: usize = 512;
+}
+
+impl Default for RpictCodec {
+    fn default() -> Self {
+        Self {
+            dt_gen: Arc::new(Utc::now),
+        }
+    }
 }
 
-impl Iterator for RpictIterator {
-    type Item = char;
+impl Decoder for RpictCodec {
+    type Item = RpictFrame;
+    type Error = CommError;
 
-    fn next(&mut self) -> Option<Self::Item> {
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         loop {
-            match self.uart.read(&mut self.buffer) {
-                Ok(size) if size > 0 => return Some(self.buffer[0].into()),
-                Ok(_size) => continue,
-                Err(_) => {
-                    log::error!("error while reading bytestream");
-                    return None;
+            let Some(position) = src.iter().position(|b| *b == b'\n') else {
+                if src.len() > Self::MAX_LINE_LENGTH {
+                    log::warn!("discarding {} bytes without line feed", src.len());
+                    src.clear();
+                }
+                return Ok(None);
+            };
+            let line = src.split_to(position + 1);
+            let line = line[..position].strip_suffix(b"\r").unwrap_or(&line[..position]);
+            match serial::ascii(line).and_then(|s| RpictFrame::parse(s, &*self.dt_gen)) {
+                Ok(frame) => {
+                    metrics::increment(&METRICS.rpict_frames_decoded);
+                    return Ok(Some(frame));
+                }
+                Err(e) => {
+                    metrics::increment(&METRICS.rpict_frames_rejected);
+                    log::warn!("couldn't extract RpictFrame from line: {e}");
                 }
             }
         }
     }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let frame = self.decode(src)?;
+        if frame.is_none() {
+            src.clear(); // an unterminated line can't be a frame
+        }
+        Ok(frame)
+    }
 }
 
 pub struct Rpict {
     port_path: Option<String>,
-    source_iter: Option<Box<dyn Iterator<Item = char>>>,
-    dt_gen: Rc<dyn Fn() -> DateTime<Utc>>,
+    source: Option<Source>,
+    dt_gen: Arc<dyn Fn() -> DateTime<Utc> + Send + Sync>,
 }
 
 impl Rpict {
     pub fn builder() -> Self {
         Self {
             port_path: None,
-            source_iter: None,
-            dt_gen: Rc::new(Utc::now),
+            source: None,
+            dt_gen: Arc::new(Utc::now),
         }
     }
 
@@ -104,56 +149,38 @@ impl Rpict {
         self
     }
 
-    pub fn with_source_iter(mut self, source_iter: impl Iterator<Item = char> + 'static) -> Self {
-        self.source_iter = Some(Box::new(source_iter));
+    pub fn with_source(mut self, source: impl AsyncRead + Send + Unpin + 'static) -> Self {
+        self.source = Some(Box::new(source));
         self
     }
 
-    pub fn with_dt_gen(mut self, dt_gen: impl Fn() -> DateTime<Utc> + 'static) -> Self {
-        self.dt_gen = Rc::new(dt_gen);
+    pub fn with_dt_gen(mut self, dt_gen: impl Fn() -> DateTime<Utc> + Send + Sync + 'static) -> Self {
+        self.dt_gen = Arc::new(dt_gen);
         self
     }
 
-    pub fn build(self) -> Result<impl Iterator<Item = RpictFrame>, Box<dyn Error>> {
+    pub fn build(self) -> Result<impl Stream<Item = RpictFrame> + Send + Unpin, CommError> {
         let Self {
             port_path,
-            source_iter,
+            source,
             dt_gen,
         } = self;
-        let source_iter = source_iter
-            .or_else(|| {
-                let port_path = port_path.expect("no port path provided");
-                let mut uart = Uart::with_path(Path::new(&port_path), 38_400, Parity::None, 8, 1).unwrap();
-                uart.set_read_mode(1, Duration::default()).unwrap();
-                Some(Box::new(RpictIterator { uart, buffer: [0u8] }))
-            })
-            .expect("no source provided");
-        let iter = source_iter
-            //.skip_while(|c| future::ready(c != '\n'))
-            .scan(String::new(), |buffer, c| {
-                if c == '\n' {
-                    let line = buffer.clone();
-                    buffer.clear();
-                    Some(Some(line))
-                } else {
-                    buffer.push(c);
-                    Some(None)
-                }
-            })
-            .flatten()
-            .filter_map(move |s| match RpictFrame::parse(&s, &*dt_gen.clone()) {
-                Ok(frame) => Some(frame),
-                Err(_) => {
-                    log::warn!("couldn't extract RpictFrame from string {}", s);
-                    None
-                }
-            });
-        Ok(iter)
+        let source = match source {
+            Some(source) => source,
+            None => {
+                let path = port_path.ok_or(CommError::MissingPortPath)?;
+                serial::open(path, 38_400, DataBits::Eight, Parity::None)?
+            }
+        };
+        Ok(serial::frames(source, RpictCodec { dt_gen }))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use futures::StreamExt;
+    use std::io::Cursor;
+
     use super::*;
 
     const FRAME: &str =
@@ -180,50 +207,120 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_rpictiterator() {
+    #[tokio::test]
+    async fn test_rpict_stream() {
         // Given
         let now = Utc::now();
-        let input = FRAME.chars().chain(vec!['\n']);
+        let input = Cursor::new(format!("{FRAME}\n"));
         // When
         let frames = Rpict::builder()
-            .with_source_iter(input)
+            .with_source(input)
             .with_dt_gen(move || now)
             .build()
             .unwrap();
         // Then
-        assert_eq!(frames.collect::<Vec<RpictFrame>>(), vec![frame(now)]);
+        assert_eq!(frames.collect::<Vec<RpictFrame>>().await, vec![frame(now)]);
     }
 
-    #[test]
-    fn test_rpictiterator_with_two_frames() {
+    #[tokio::test]
+    async fn test_rpict_stream_with_two_frames() {
         // Given
         let now = Utc::now();
-        let input = FRAME.chars().chain(vec!['\n']);
-        let input_len = FRAME.len() + 1;
+        let input = Cursor::new(format!("{FRAME}\n").repeat(2));
         // When
         let frames = Rpict::builder()
-            .with_source_iter(input.clone().cycle().take(input_len * 2))
+            .with_source(input)
             .with_dt_gen(move || now)
             .build()
             .unwrap();
         // Then
-        assert_eq!(frames.collect::<Vec<RpictFrame>>(), vec![frame(now), frame(now)]);
+        assert_eq!(frames.collect::<Vec<RpictFrame>>().await, vec![frame(now), frame(now)]);
     }
 
-    #[test]
-    fn test_rpictiterator_with_corrupted_frame() {
+    #[tokio::test]
+    async fn test_rpict_stream_with_corrupted_frame() {
         // Given
         let now = Utc::now();
-        let input = FRAME[..10].chars().chain(vec!['\n']);
+        let input = Cursor::new(format!("{}\n", &FRAME[..10]));
         // When
         let frames = Rpict::builder()
-            .with_source_iter(input)
+            .with_source(input)
             .with_dt_gen(move || now)
             .build()
             .unwrap();
         // Then
-        assert_eq!(frames.collect::<Vec<RpictFrame>>(), Vec::new());
+        assert_eq!(frames.collect::<Vec<RpictFrame>>().await, Vec::new());
+    }
+
+    #[tokio::test]
+    async fn test_rpict_stream_with_unterminated_frame() {
+        // Given
+        let now = Utc::now();
+        let input = Cursor::new(format!("{FRAME}\n{FRAME}"));
+        // When
+        let frames = Rpict::builder()
+            .with_source(input)
+            .with_dt_gen(move || now)
+            .build()
+            .unwrap();
+        // Then
+        assert_eq!(frames.collect::<Vec<RpictFrame>>().await, vec![frame(now)]);
+    }
+
+    #[tokio::test]
+    async fn test_rpict_stream_with_non_ascii_line() {
+        // Given
+        let now = Utc::now();
+        let mut input = FRAME.replacen(' ', "\u{e9}", 1).into_bytes();
+        input.extend_from_slice(b"\n\xff\xfe\n");
+        input.extend_from_slice(format!("{FRAME}\r\n").as_bytes());
+        // When
+        let frames = Rpict::builder()
+            .with_source(Cursor::new(input))
+            .with_dt_gen(move || now)
+            .build()
+            .unwrap();
+        // Then
+        assert_eq!(frames.collect::<Vec<RpictFrame>>().await, vec![frame(now)]);
+    }
+
+    #[test]
+    fn test_rpict_codec_random_bytes() {
+        // Given
+        let mut codec = RpictCodec::default();
+        let mut seed = 0x2545_f491_u32;
+        let mut input = (0..64 * 1024)
+            .map(|_| {
+                // xorshift, biased towards separators and digits
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                match seed % 4 {
+                    0 => b"\n \r-.0123456789"[(seed >> 8) as usize % 15],
+                    _ => (seed >> 8) as u8,
+                }
+            })
+            .collect::<BytesMut>();
+        // When & Then
+        while codec.decode(&mut input).unwrap().is_some() {}
+        codec.decode_eof(&mut input).unwrap();
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_rpict_codec_split_reads() {
+        // Given
+        let now = Utc::now();
+        let mut codec = RpictCodec {
+            dt_gen: Arc::new(move || now),
+        };
+        let mut buffer = BytesMut::from(&FRAME[..50]);
+        // When & Then
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+        buffer.extend_from_slice(&FRAME.as_bytes()[50..]);
+        buffer.extend_from_slice(b"\n");
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(frame(now)));
+        assert!(buffer.is_empty());
     }
 
     #[test]
@@ -243,7 +340,13 @@ mod tests {
         // When
         let result = RpictFrame::parse(input, &Utc::now);
         // Then
-        assert_eq!(result, Err(ParseError))
+        assert_eq!(
+            result,
+            Err(ParseError::InvalidField {
+                label: "node_id",
+                value: "422.95".to_string()
+            })
+        )
     }
 
     #[test]
@@ -253,7 +356,7 @@ mod tests {
         // When
         let result = RpictFrame::parse(input, &Utc::now);
         // Then
-        assert_eq!(result, Err(ParseError))
+        assert_eq!(result, Err(ParseError::MissingField("l1_apparent_power")))
     }
 
     #[test]
@@ -263,6 +366,114 @@ mod tests {
         // When
         let result = RpictFrame::parse(&input, &Utc::now);
         // Then
-        assert_eq!(result, Err(ParseError))
+        assert_eq!(result, Err(ParseError::TrailingData("259.70".to_string())))
+    }
+
+    mod properties {
+        use proptest::prelude::*;
+
+        use super::*;
+
+        fn decode_all(input: &[u8], now: DateTime<Utc>) -> Vec<RpictFrame> {
+            let mut codec = RpictCodec {
+                dt_gen: Arc::new(move || now),
+            };
+            let mut buffer = BytesMut::from(input);
+            let mut frames = Vec::new();
+            while let Some(frame) = codec.decode_eof(&mut buffer).unwrap() {
+                frames.push(frame);
+            }
+            frames
+        }
+
+        fn arb_frame() -> impl Strategy<Value = RpictFrame> {
+            let value = (-10_000_000i32..10_000_000).prop_map(|v| v as f32 / 1000.0);
+            (any::<u8>(), prop::collection::vec(value, 15)).prop_map(|(node_id, v)| RpictFrame {
+                node_id,
+                l1_real_power: v[0],
+                l1_apparent_power: v[1],
+                l1_irms: v[2],
+                l1_vrms: v[3],
+                l1_power_factor: v[4],
+                l2_real_power: v[5],
+                l2_apparent_power: v[6],
+                l2_irms: v[7],
+                l2_vrms: v[8],
+                l2_power_factor: v[9],
+                l3_real_power: v[10],
+                l3_apparent_power: v[11],
+                l3_irms: v[12],
+                l3_vrms: v[13],
+                l3_power_factor: v[14],
+                timestamp: DateTime::default(),
+            })
+        }
+
+        fn to_line(frame: &RpictFrame) -> String {
+            format!(
+                "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {}\r\n",
+                frame.node_id,
+                frame.l1_real_power,
+                frame.l1_apparent_power,
+                frame.l1_irms,
+                frame.l1_vrms,
+                frame.l1_power_factor,
+                frame.l2_real_power,
+                frame.l2_apparent_power,
+                frame.l2_irms,
+                frame.l2_vrms,
+                frame.l2_power_factor,
+                frame.l3_real_power,
+                frame.l3_apparent_power,
+                frame.l3_irms,
+                frame.l3_vrms,
+                frame.l3_power_factor,
+            )
+        }
+
+        // digits are excluded so that garbage can't form a valid frame by chance
+        fn arb_garbage_line() -> impl Strategy<Value = Vec<u8>> {
+            prop::collection::vec(
+                any::<u8>().prop_filter("digit or LF", |b| !b.is_ascii_digit() && *b != b'\n'),
+                0..256,
+            )
+            .prop_map(|mut line| {
+                line.push(b'\n');
+                line
+            })
+        }
+
+        proptest! {
+            #[test]
+            fn decoding_arbitrary_bytes_never_panics(input in prop::collection::vec(any::<u8>(), 0..4096)) {
+                decode_all(&input, Utc::now());
+            }
+
+            #[test]
+            fn valid_frames_round_trip(frames in prop::collection::vec(arb_frame(), 1..8)) {
+                // Given
+                let input = frames.iter().map(to_line).collect::<String>();
+                // When
+                let actual = decode_all(input.as_bytes(), DateTime::default());
+                // Then
+                prop_assert_eq!(actual, frames);
+            }
+
+            #[test]
+            fn frames_interleaved_with_garbage_decode(
+                chunks in prop::collection::vec((arb_garbage_line(), arb_frame()), 1..8)
+            ) {
+                // Given
+                let input = chunks
+                    .iter()
+                    .flat_map(|(garbage, frame)| [garbage.clone(), to_line(frame).into_bytes()])
+                    .flatten()
+                    .collect::<Vec<_>>();
+                // When
+                let actual = decode_all(&input, DateTime::default());
+                // Then
+                prop_assert_eq!(actual, chunks.into_iter().map(|(_, frame)| frame).collect::<Vec<_>>());
+            }
+        }
     }
 }
@@ -0,0 +1,45 @@
+use futures::{future, Stream, StreamExt};
+use tokio::io::AsyncRead;
+use tokio_serial::{DataBits, Parity, SerialPortBuilderExt, StopBits};
+use tokio_util::codec::{Decoder, FramedRead};
+
+use crate::driver::error::{CommError, ParseError};
+
+/// Byte source frames are decoded from, a serial port or any reader in tests.
+pub type Source = Box<dyn AsyncRead + Send + Unpin>;
+
+pub(crate) fn open(path: String, baud_rate: u32, data_bits: DataBits, parity: Parity) -> Result<Source, CommError> {
+    let port = tokio_serial::new(&path, baud_rate)
+        .data_bits(data_bits)
+        .parity(parity)
+        .stop_bits(StopBits::One)
+        .open_native_async()
+        .map_err(|source| CommError::PortOpen { path, source })?;
+    Ok(Box::new(port))
+}
+
+/// Checks that raw bytes are printable ASCII, as both RPICT and TIC protocols only use it.
+pub(crate) fn ascii(data: &[u8]) -> Result<&str, ParseError> {
+    if data.iter().all(|b| b.is_ascii_graphic() || matches!(b, b' ' | b'\t')) {
+        // ASCII is valid UTF-8
+        Ok(std::str::from_utf8(data).unwrap_or_default())
+    } else {
+        Err(ParseError::Framing(String::from_utf8_lossy(data).into_owned()))
+    }
+}
+
+/// Decodes frames from a source, until it is exhausted or a read error occurs.
+pub(crate) fn frames<C>(source: Source, codec: C) -> impl Stream<Item = C::Item> + Send + Unpin
+where
+    C: Decoder<Error = CommError> + Send,
+    C::Item: Send,
+{
+    FramedRead::new(source, codec)
+        .take_while(|result| {
+            if let Err(e) = result {
+                log::error!("{e}");
+            }
+            future::ready(result.is_ok())
+        })
+        .filter_map(|result| future::ready(result.ok()))
+}
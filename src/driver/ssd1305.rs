@@ -1,4 +1,3 @@
-use std::error::Error;
 use std::thread;
 use std::time::Duration;
 
@@ -7,10 +6,13 @@ use embedded_graphics::prelude::*;
 use rppal::gpio;
 use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
 
+use crate::driver::display::DisplayDriver;
 use crate::driver::error::CommError;
 
-const GPIO_DC: u8 = 24;
-const GPIO_RST: u8 = 25;
+pub(crate) const GPIO_DC: u8 = 24;
+pub(crate) const GPIO_RST: u8 = 25;
+/// SPI0 CE0, MISO, MOSI and SCLK
+pub(crate) const SPI0_BCM_PINS: [u8; 4] = [8, 9, 10, 11];
 pub const DISPLAY_WIDTH: usize = 128;
 pub const DISPLAY_HEIGHT: usize = 32;
 
@@ -25,7 +27,7 @@ pub struct Ssd1305 {
 }
 
 impl Ssd1305 {
-    pub fn new() -> Result<Self, Box<dyn Error>> {
+    pub fn new() -> Result<Self, CommError> {
         let buffer = [0x00; DISPLAY_WIDTH * DISPLAY_HEIGHT / 8];
         let spi = Spi::new(Bus::Spi0, SlaveSelect::Ss0, 8_000_000, Mode::Mode0)?;
         let gpio = gpio::Gpio::new()?;
@@ -50,11 +52,19 @@ impl Ssd1305 {
 
     fn command(&mut self, cmd: u8) -> Result<(), CommError> {
         self.gpio_dc.write(gpio::Level::Low);
-        self.spi.write(&[cmd]).map_err(|_| CommError)?;
+        self.spi.write(&[cmd])?;
         Ok(())
     }
 
-    pub fn begin(&mut self) -> Result<(), CommError> {
+    fn display_contrast(&mut self, value: u8) -> Result<(), CommError> {
+        self.command(SET_CONTRAST)?;
+        self.command(value)?;
+        Ok(())
+    }
+}
+
+impl DisplayDriver for Ssd1305 {
+    fn begin(&mut self) -> Result<(), CommError> {
         self.reset();
         self.display_off()?;
         self.command(0x04)?; //--Set Lower Column Start Address for Page Addressing Mode
@@ -81,29 +91,23 @@ impl Ssd1305 {
         Ok(())
     }
 
-    pub fn display_on(&mut self) -> Result<(), CommError> {
+    fn display_on(&mut self) -> Result<(), CommError> {
         self.command(SET_DISP | 0x01)?;
         Ok(())
     }
 
-    pub fn display_off(&mut self) -> Result<(), CommError> {
+    fn display_off(&mut self) -> Result<(), CommError> {
         self.command(SET_DISP)?;
         Ok(())
     }
 
-    pub fn clear(&mut self) -> Result<(), CommError> {
+    fn clear_display(&mut self) -> Result<(), CommError> {
         self.buffer.fill(0x00);
         self.flush()?;
         Ok(())
     }
 
-    fn display_contrast(&mut self, value: u8) -> Result<(), CommError> {
-        self.command(SET_CONTRAST)?;
-        self.command(value)?;
-        Ok(())
-    }
-
-    pub fn flush(&mut self) -> Result<(), CommError> {
+    fn flush(&mut self) -> Result<(), CommError> {
         for page in 0..4 {
             self.command(0xB0 + page)?; // Set page address
             self.command(0x04)?; // Set low column address
@@ -115,7 +119,7 @@ impl Ssd1305 {
             let end_index: usize = start_index + DISPLAY_WIDTH;
             let page_slice = &self.buffer[start_index..end_index];
 
-            self.spi.write(page_slice).map_err(|_| CommError)?;
+            self.spi.write(page_slice)?;
         }
         Ok(())
     }
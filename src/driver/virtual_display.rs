@@ -0,0 +1,218 @@
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use image::{imageops::resize, ImageBuffer, Luma};
+
+use crate::driver::display::DisplayDriver;
+use crate::driver::error::CommError;
+use crate::driver::ssd1305::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+// Inspired from https://github.com/embedded-graphics/embedded-graphics/blob/657fb4b/tools/png-target/src/lib.rs
+
+pub struct PngTarget {
+    image: ImageBuffer<Luma<u8>, Vec<u8>>,
+}
+
+impl PngTarget {
+    pub fn new(size: Size) -> Self {
+        Self {
+            image: ImageBuffer::new(size.width, size.height),
+        }
+    }
+
+    pub fn save<PATH: AsRef<Path>>(&self, path: PATH) -> image::ImageResult<()> {
+        let scale = 3;
+        let resized = resize(
+            &self.image,
+            self.image.width() * scale,
+            self.image.height() * scale,
+            image::imageops::FilterType::Nearest,
+        );
+        resized.save_with_format(path, image::ImageFormat::Png)
+    }
+
+    pub fn is_on(&self, x: u32, y: u32) -> bool {
+        x < self.image.width() && y < self.image.height() && self.image.get_pixel(x, y).0[0] > 0
+    }
+
+    /// Renders the image with Unicode half blocks, each character holding two pixel rows.
+    pub fn to_terminal(&self) -> String {
+        let (width, height) = (self.image.width(), self.image.height());
+        let mut output = format!("┌{}┐\n", "─".repeat(width as usize));
+        for y in (0..height).step_by(2) {
+            output.push('│');
+            for x in 0..width {
+                output.push(match (self.is_on(x, y), self.is_on(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            output.push_str("│\n");
+        }
+        output.push_str(&format!("└{}┘\n", "─".repeat(width as usize)));
+        output
+    }
+}
+
+impl DrawTarget for PngTarget {
+    type Color = BinaryColor;
+    type Error = std::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(p, c) in pixels {
+            if let (Ok(x), Ok(y)) = (u32::try_from(p.x), u32::try_from(p.y)) {
+                if x < self.image.width() && y < self.image.height() {
+                    self.image
+                        .put_pixel(p.x as u32, p.y as u32, Luma([if c.is_on() { 255 } else { 0 }]));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl OriginDimensions for PngTarget {
+    fn size(&self) -> Size {
+        Size::new(self.image.width(), self.image.height())
+    }
+}
+
+/// Moves the cursor to the top left corner of the terminal, so each frame overwrites the previous one.
+const TERMINAL_CURSOR_HOME: &str = "\x1b[H";
+/// Clears the whole terminal, to start drawing on a blank screen.
+const TERMINAL_CLEAR: &str = "\x1b[2J";
+
+#[derive(Clone, Debug)]
+pub enum VirtualOutput {
+    Png(PathBuf),
+    Terminal,
+}
+
+/// A display backend with the same geometry as the SSD1305, for development without hardware.
+/// Every flush writes the current frame to the configured output.
+pub struct VirtualDisplay {
+    output: VirtualOutput,
+    target: PngTarget,
+    is_on: bool,
+}
+
+impl VirtualDisplay {
+    pub fn new(output: VirtualOutput) -> Self {
+        Self {
+            output,
+            target: PngTarget::new(Size::new(DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32)),
+            is_on: false,
+        }
+    }
+
+    fn render(&self) -> Result<(), CommError> {
+        // a display turned off shows a blank frame, whatever the buffer content
+        let blank = PngTarget::new(self.target.size());
+        let frame = if self.is_on { &self.target } else { &blank };
+        match &self.output {
+            VirtualOutput::Png(path) => frame.save(path).map_err(|e| CommError::Io(io::Error::other(e))),
+            VirtualOutput::Terminal => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(TERMINAL_CURSOR_HOME.as_bytes())?;
+                stdout.write_all(frame.to_terminal().as_bytes())?;
+                Ok(stdout.flush()?)
+            }
+        }
+    }
+}
+
+impl DisplayDriver for VirtualDisplay {
+    fn begin(&mut self) -> Result<(), CommError> {
+        self.is_on = false;
+        if let VirtualOutput::Terminal = self.output {
+            io::stdout().lock().write_all(TERMINAL_CLEAR.as_bytes())?;
+        }
+        self.render()
+    }
+
+    fn display_on(&mut self) -> Result<(), CommError> {
+        self.is_on = true;
+        self.render()
+    }
+
+    fn display_off(&mut self) -> Result<(), CommError> {
+        self.is_on = false;
+        self.render()
+    }
+
+    fn clear_display(&mut self) -> Result<(), CommError> {
+        self.target.clear(BinaryColor::Off).unwrap_or_default();
+        self.render()
+    }
+
+    fn flush(&mut self) -> Result<(), CommError> {
+        self.render()
+    }
+}
+
+impl DrawTarget for VirtualDisplay {
+    type Color = BinaryColor;
+    type Error = std::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.target.draw_iter(pixels)
+    }
+}
+
+impl OriginDimensions for VirtualDisplay {
+    fn size(&self) -> Size {
+        self.target.size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics::primitives::{Line, PrimitiveStyle};
+
+    use super::*;
+
+    #[test]
+    fn test_png_target_to_terminal() {
+        // Given
+        let mut target = PngTarget::new(Size::new(4, 2));
+        Line::new(Point::new(0, 0), Point::new(1, 0))
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(&mut target)
+            .unwrap();
+        Line::new(Point::new(1, 1), Point::new(2, 1))
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(&mut target)
+            .unwrap();
+        // When
+        let actual = target.to_terminal();
+        // Then
+        assert_eq!(actual, "┌────┐\n│▀█▄ │\n└────┘\n");
+    }
+
+    #[test]
+    fn test_virtual_display_png_output() {
+        // Given
+        let path = std::env::temp_dir().join("energy-monitor-test-virtual-display.png");
+        let mut display = VirtualDisplay::new(VirtualOutput::Png(path.clone()));
+        // When
+        display.begin().unwrap();
+        display.display_on().unwrap();
+        // Then
+        let image = image::open(&path).unwrap();
+        assert_eq!(image.width(), DISPLAY_WIDTH as u32 * 3);
+        assert_eq!(image.height(), DISPLAY_HEIGHT as u32 * 3);
+        std::fs::remove_file(path).unwrap();
+    }
+}
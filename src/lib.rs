@@ -2,7 +2,12 @@
 extern crate lazy_static;
 
 pub mod actor;
+pub mod alert;
+pub mod analytics;
 pub mod display;
 pub mod driver;
+pub mod metrics;
 pub mod service;
 pub mod settings;
+pub mod shedding;
+pub mod state;
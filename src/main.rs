@@ -1,40 +1,288 @@
 use std::error::Error;
 use std::fs;
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
 
-use clap::{arg, command, value_parser};
-use tokio::signal;
+use clap::{arg, command, value_parser, Command};
+use tokio::runtime::Runtime;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::timeout;
 
-use energy_monitor::actor::datalogger::DataLoggerActor;
-use energy_monitor::actor::hmi::HmiActor;
+use energy_monitor::actor::alert::AlertActor;
+use energy_monitor::actor::analytics::AnalyticsActor;
+use energy_monitor::actor::datalogger::{DataLoggerActor, DataLoggerHandle};
+use energy_monitor::actor::hmi::{HmiActor, HmiActorHandle};
 use energy_monitor::actor::linky::LinkyActor;
+use energy_monitor::actor::metrics::MetricsActor;
+use energy_monitor::actor::notifier::NotifierActor;
 use energy_monitor::actor::rpict::RpictActor;
+use energy_monitor::actor::shedding::{SheddingActor, SheddingActorHandle};
+use energy_monitor::actor::systemd::SystemdActor;
+use energy_monitor::service::systemd::{unit_file, Notifier};
 use energy_monitor::settings::Settings;
+use energy_monitor::state::RuntimeState;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+/// Time given to actors to flush their data and blank the display once a stop signal is received.
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(10);
+/// Exit status when actors did not stop before [`SHUTDOWN_DEADLINE`], some data may be lost.
+const EXIT_SHUTDOWN_TIMEOUT: u8 = 2;
+
+fn main() -> ExitCode {
     let matches = command!()
         .arg(
             arg!(-c --config <FILE> "Sets a custom YAML config file")
                 .required(false)
+                .global(true)
                 .value_parser(value_parser!(PathBuf)),
         )
+        .subcommand(Command::new("print-unit").about("Prints a sample systemd unit file"))
+        .subcommand(Command::new("check-config").about("Validates and prints the effective configuration"))
+        .subcommand(Command::new("reset-energy").about("Clears energy totals from the state file, service stopped"))
         .get_matches();
-    let config_file = matches
-        .get_one::<PathBuf>("config")
-        .map(|path| fs::read_to_string(path).unwrap());
-    let settings = Settings::new(config_file).expect("Can't load settings");
+    if matches.subcommand_matches("print-unit").is_some() {
+        print_unit(matches.get_one::<PathBuf>("config"));
+        return ExitCode::SUCCESS;
+    }
+    let config_file = match matches.get_one::<PathBuf>("config").map(fs::read_to_string).transpose() {
+        Ok(config_file) => config_file,
+        Err(e) => {
+            eprintln!("Can't read config file: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if matches.subcommand_matches("check-config").is_some() {
+        return check_config(config_file);
+    }
+    if matches.subcommand_matches("reset-energy").is_some() {
+        return reset_energy(config_file);
+    }
+    let settings = match Settings::new(config_file) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Can't load settings: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
     env_logger::Builder::new().parse_filters(&settings.log_level).init();
     log::debug!("{:?}", settings);
 
+    let runtime = Runtime::new().expect("Can't start async runtime");
+    let config_path = matches.get_one::<PathBuf>("config").cloned();
+    let exit_code = runtime.block_on(run(settings, config_path)).unwrap_or_else(|e| {
+        log::error!("energy-monitor failed: {e}");
+        ExitCode::FAILURE
+    });
+    // the display thread may still be busy with an SPI transfer, don't wait for it
+    runtime.shutdown_timeout(Duration::from_millis(100));
+    exit_code
+}
+
+async fn run(settings: Settings, config_path: Option<PathBuf>) -> Result<ExitCode, Box<dyn Error>> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+
     let rpict = RpictActor::create(&settings.serial.rpict);
     let linky = LinkyActor::create(&settings.serial.linky);
-    let datalogger = DataLoggerActor::create(&settings.influxdb, &rpict, &linky)?;
-    let hmi = HmiActor::create(&settings.hmi, &rpict, &linky, &datalogger)?;
+    let metrics = MetricsActor::create();
+    let analytics = AnalyticsActor::create(
+        &settings.state,
+        &settings.tariff,
+        &settings.voltage_quality,
+        &rpict,
+        &linky,
+    );
+    let alert = AlertActor::create(&settings.alerts, &rpict, &linky);
+    let shedding = SheddingActor::create(
+        &settings.load_shedding,
+        settings.alerts.subscribed_current_amps,
+        &rpict,
+        &linky,
+    )?;
+    let datalogger = DataLoggerActor::create(
+        &settings.influxdb,
+        &settings.channels,
+        &rpict,
+        &linky,
+        &metrics,
+        &analytics,
+        &alert,
+        &shedding,
+    )?;
+    let notifiers = NotifierActor::create(&settings.notifiers, &alert, &rpict, &linky, &datalogger);
+    let hmi = if settings.hmi.enabled {
+        HmiActor::create(
+            &settings.hmi,
+            &settings.channels,
+            &rpict,
+            &linky,
+            &datalogger,
+            &metrics,
+            &analytics,
+            &alert,
+            &shedding,
+        )
+        .map_err(|e| log::error!("Cannot start HMI, running headless: {e}"))
+        .ok()
+    } else {
+        log::info!("HMI disabled, running headless");
+        None
+    };
+    let notifier = Notifier::from_env();
+    if let Some(notifier) = &notifier {
+        SystemdActor::create(notifier, &rpict, &linky, &datalogger);
+        notifier
+            .notify("READY=1")
+            .unwrap_or_else(|e| log::warn!("Cannot notify systemd: {e}"));
+    }
     log::info!("energy-monitor started");
 
-    let _ = signal::ctrl_c().await;
-    log::info!("energy-monitor stopping");
-    hmi.shutdown().await;
-    Ok(())
+    // settings last handed to actors, each reload is compared against them
+    let mut current = settings;
+    let signal = loop {
+        tokio::select! {
+            _ = sigterm.recv() => break "SIGTERM",
+            _ = sigint.recv() => break "SIGINT",
+            _ = sighup.recv() => {
+                if let Some(notifier) = &notifier {
+                    notifier
+                        .notify("RELOADING=1")
+                        .unwrap_or_else(|e| log::warn!("Cannot notify systemd: {e}"));
+                }
+                reload(&mut current, config_path.as_ref(), &datalogger, &shedding, hmi.as_ref()).await;
+                if let Some(notifier) = &notifier {
+                    notifier
+                        .notify("READY=1")
+                        .unwrap_or_else(|e| log::warn!("Cannot notify systemd: {e}"));
+                }
+            }
+        }
+    };
+    log::info!("energy-monitor stopping ({signal})");
+    if let Some(notifier) = &notifier {
+        notifier
+            .notify("STOPPING=1")
+            .unwrap_or_else(|e| log::warn!("Cannot notify systemd: {e}"));
+    }
+    let shutdown = async {
+        rpict.shutdown().await;
+        linky.shutdown().await;
+        // producers before the datalogger, so it publishes what they send on shutdown
+        analytics.shutdown().await;
+        alert.shutdown().await;
+        shedding.shutdown().await;
+        datalogger.shutdown().await;
+        if let Some(hmi) = hmi {
+            hmi.shutdown().await;
+        }
+        // last, as retries may take until the deadline
+        if let Some(notifiers) = notifiers {
+            notifiers.shutdown().await;
+        }
+    };
+    match timeout(SHUTDOWN_DEADLINE, shutdown).await {
+        Ok(()) => {
+            log::info!("energy-monitor stopped");
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(_) => {
+            log::error!("energy-monitor did not stop within {SHUTDOWN_DEADLINE:?}, some data may be lost");
+            Ok(ExitCode::from(EXIT_SHUTDOWN_TIMEOUT))
+        }
+    }
+}
+
+/// Re-reads the configuration and hands it to actors, settings only read at startup are reported.
+/// On success, `current` is replaced with the reloaded settings.
+async fn reload(
+    current: &mut Settings,
+    config_path: Option<&PathBuf>,
+    datalogger: &DataLoggerHandle,
+    shedding: &SheddingActorHandle,
+    hmi: Option<&HmiActorHandle>,
+) {
+    log::info!("energy-monitor reloading configuration (SIGHUP)");
+    let reloaded = match config_path
+        .map(fs::read_to_string)
+        .transpose()
+        .map_err(|e| e.to_string())
+        .and_then(|config_file| Settings::new(config_file).map_err(|e| e.to_string()))
+    {
+        Ok(reloaded) => reloaded,
+        Err(e) => {
+            log::error!("Cannot reload configuration, keeping the current one: {e}");
+            return;
+        }
+    };
+    log::debug!("{:?}", reloaded);
+    let changes = current.restart_required_changes(&reloaded);
+    if !changes.is_empty() {
+        log::warn!("Restart required to apply changes to: {}", changes.join(", "));
+    }
+    datalogger.reload(&reloaded.influxdb, &reloaded.channels).await;
+    shedding.reload(&reloaded.load_shedding).await;
+    if let Some(hmi) = hmi {
+        hmi.reload(&reloaded.hmi, &reloaded.channels).await;
+    }
+    *current = reloaded;
+    log::info!("energy-monitor configuration reloaded");
+}
+
+fn print_unit(config: Option<&PathBuf>) {
+    let exe = std::env::current_exe().unwrap_or_else(|_| PathBuf::from("/usr/local/bin/energy-monitor"));
+    let mut exec_start = exe.display().to_string();
+    if let Some(config) = config {
+        let config = fs::canonicalize(config).unwrap_or_else(|_| config.clone());
+        exec_start.push_str(&format!(" -c {}", config.display()));
+    }
+    print!("{}", unit_file(&exec_start));
+}
+
+/// Clears day, month and lifetime energy totals. A running service would overwrite the state file, so it must be stopped.
+fn reset_energy(config_file: Option<String>) -> ExitCode {
+    let settings = match Settings::new(config_file) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Can't load settings: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut state = RuntimeState::load(&settings.state.path);
+    state.energy.reset();
+    match state.save(&settings.state.path) {
+        Ok(()) => {
+            eprintln!("Energy totals cleared in {}", settings.state.path.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Can't save state to {}: {e}", settings.state.path.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Prints settings merged from defaults, config file and environment, then reports invalid values.
+fn check_config(config_file: Option<String>) -> ExitCode {
+    let settings = match Settings::load(config_file) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Can't load settings: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match serde_yaml::to_string(&settings) {
+        Ok(yaml) => print!("{yaml}"),
+        Err(e) => eprintln!("Can't print settings: {e}"),
+    }
+    match settings.validate() {
+        Ok(()) => {
+            eprintln!("Configuration is valid");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,179 @@
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+/// Counters and gauges describing the health of the acquisition pipeline.
+/// They are updated from any thread, without locking.
+pub struct Metrics {
+    pub rpict_frames_decoded: AtomicU64,
+    pub rpict_frames_rejected: AtomicU64,
+    pub linky_frames_decoded: AtomicU64,
+    pub linky_frames_rejected: AtomicU64,
+    pub linky_checksum_errors: AtomicU64,
+    pub broadcast_lagged: AtomicU64,
+    pub influxdb_writes: AtomicU64,
+    pub influxdb_failures: AtomicU64,
+    pub influxdb_latency_ms: AtomicU64,
+    pub display_flushes: AtomicU64,
+    pub display_flush_ms: AtomicU64,
+}
+
+pub static METRICS: Metrics = Metrics::new();
+
+pub fn increment(counter: &AtomicU64) {
+    add(counter, 1);
+}
+
+pub fn add(counter: &AtomicU64, value: u64) {
+    counter.fetch_add(value, Ordering::Relaxed);
+}
+
+pub fn set(gauge: &AtomicU64, value: Duration) {
+    gauge.store(value.as_millis() as u64, Ordering::Relaxed);
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            rpict_frames_decoded: AtomicU64::new(0),
+            rpict_frames_rejected: AtomicU64::new(0),
+            linky_frames_decoded: AtomicU64::new(0),
+            linky_frames_rejected: AtomicU64::new(0),
+            linky_checksum_errors: AtomicU64::new(0),
+            broadcast_lagged: AtomicU64::new(0),
+            influxdb_writes: AtomicU64::new(0),
+            influxdb_failures: AtomicU64::new(0),
+            influxdb_latency_ms: AtomicU64::new(0),
+            display_flushes: AtomicU64::new(0),
+            display_flush_ms: AtomicU64::new(0),
+        }
+    }
+
+    pub fn snapshot(&self, process: ProcessStats, timestamp: DateTime<Utc>) -> MetricsSnapshot {
+        let get = |value: &AtomicU64| value.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            rpict_frames_decoded: get(&self.rpict_frames_decoded),
+            rpict_frames_rejected: get(&self.rpict_frames_rejected),
+            linky_frames_decoded: get(&self.linky_frames_decoded),
+            linky_frames_rejected: get(&self.linky_frames_rejected),
+            linky_checksum_errors: get(&self.linky_checksum_errors),
+            broadcast_lagged: get(&self.broadcast_lagged),
+            influxdb_writes: get(&self.influxdb_writes),
+            influxdb_failures: get(&self.influxdb_failures),
+            influxdb_latency_ms: get(&self.influxdb_latency_ms),
+            display_flushes: get(&self.display_flushes),
+            display_flush_ms: get(&self.display_flush_ms),
+            process,
+            timestamp,
+        }
+    }
+}
+
+/// Resources used by this process, unknown on systems without procfs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcessStats {
+    pub rss_kb: Option<u64>,
+    pub cpu_percent: Option<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricsSnapshot {
+    pub rpict_frames_decoded: u64,
+    pub rpict_frames_rejected: u64,
+    pub linky_frames_decoded: u64,
+    pub linky_frames_rejected: u64,
+    pub linky_checksum_errors: u64,
+    pub broadcast_lagged: u64,
+    pub influxdb_writes: u64,
+    pub influxdb_failures: u64,
+    pub influxdb_latency_ms: u64,
+    pub display_flushes: u64,
+    pub display_flush_ms: u64,
+    pub process: ProcessStats,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Samples CPU time between two calls to compute the process CPU usage.
+#[derive(Default)]
+pub struct ProcessSampler {
+    last_cpu_ticks: Option<u64>,
+}
+
+impl ProcessSampler {
+    // USER_HZ, fixed to 100 by the Linux ABI
+    const TICKS_PER_SEC: f32 = 100.0;
+
+    pub fn sample(&mut self, elapsed: Duration) -> ProcessStats {
+        let rss_kb = fs::read_to_string("/proc/self/status")
+            .ok()
+            .and_then(|status| parse_rss_kb(&status));
+        let cpu_ticks = fs::read_to_string("/proc/self/stat")
+            .ok()
+            .and_then(|stat| parse_cpu_ticks(&stat));
+        let cpu_percent = match (self.last_cpu_ticks, cpu_ticks) {
+            (Some(last), Some(current)) if !elapsed.is_zero() => {
+                Some(current.saturating_sub(last) as f32 / Self::TICKS_PER_SEC / elapsed.as_secs_f32() * 100.0)
+            }
+            _ => None,
+        };
+        self.last_cpu_ticks = cpu_ticks;
+        ProcessStats { rss_kb, cpu_percent }
+    }
+}
+
+/// Reads the resident set size from `/proc/self/status`, see `man proc`.
+fn parse_rss_kb(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|value| value.trim().strip_suffix("kB"))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+/// Reads user and system CPU time from `/proc/self/stat`, see `man proc`.
+fn parse_cpu_ticks(stat: &str) -> Option<u64> {
+    // the command name may contain spaces, fields are counted after it, from the 3rd one (state)
+    let (_, fields) = stat.rsplit_once(')')?;
+    let mut fields = fields.split_ascii_whitespace().skip(11);
+    let utime = fields.next()?.parse::<u64>().ok()?;
+    let stime = fields.next()?.parse::<u64>().ok()?;
+    Some(utime + stime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rss_kb() {
+        // Given
+        let status = "Name:\tenergy-monitor\nVmPeak:\t   12000 kB\nVmRSS:\t    5260 kB\nThreads:\t5\n";
+        // When & Then
+        assert_eq!(parse_rss_kb(status), Some(5260));
+        assert_eq!(parse_rss_kb("Name:\tenergy-monitor\n"), None);
+    }
+
+    #[test]
+    fn test_parse_cpu_ticks() {
+        // Given
+        let stat = "1234 (energy monitor) S 1 1234 1234 0 -1 4194560 1500 0 0 0 250 130 0 0 20 0 5 0 100 0 0";
+        // When & Then
+        assert_eq!(parse_cpu_ticks(stat), Some(380));
+        assert_eq!(parse_cpu_ticks("1234 (energy-monitor) S 1"), None);
+    }
+
+    #[test]
+    fn test_process_sampler() {
+        // Given
+        let mut sampler = ProcessSampler::default();
+        // When
+        let first = sampler.sample(Duration::from_secs(10));
+        let second = sampler.sample(Duration::from_secs(10));
+        // Then
+        assert!(first.rss_kb.is_some_and(|rss_kb| rss_kb > 0));
+        assert_eq!(first.cpu_percent, None);
+        assert!(second.cpu_percent.is_some_and(|cpu_percent| cpu_percent >= 0.0));
+    }
+}
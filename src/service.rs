@@ -1 +1,5 @@
 pub mod influxdb;
+pub mod notification;
+pub mod smtp;
+pub mod systemd;
+pub mod webhook;
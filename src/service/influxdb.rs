@@ -1,10 +1,20 @@
 use std::error::Error;
+use std::time::Duration;
 
 use reqwest;
 
+use crate::alert::Alert;
+use crate::analytics::consumption::ConsumptionSummary;
+use crate::analytics::cost::CostSummary;
+use crate::analytics::energy::Energy;
+use crate::analytics::imbalance::{ImbalanceStats, ImbalanceSummary};
+use crate::analytics::reactive::{reactive_power, Direction, PowerFactorSummary};
+use crate::analytics::voltage::VoltageSummary;
 use crate::driver::linky::LinkyFrame;
 use crate::driver::rpict::RpictFrame;
+use crate::metrics::MetricsSnapshot;
 use crate::settings;
+use crate::shedding::OutputState;
 
 pub struct InfluxDBClient {
     client: reqwest::Client,
@@ -21,20 +31,23 @@ pub enum InfluxDBClientError {
 impl InfluxDBClient {
     pub fn new(settings: &settings::InfluxDB) -> Result<InfluxDBClient, Box<dyn Error>> {
         let settings = settings.clone();
-        let client = reqwest::Client::new();
+        // bounded so that a stalled server can't hold back frames nor shutdown
+        let client = reqwest::Client::builder().timeout(Duration::from_secs(5)).build()?;
         Ok(InfluxDBClient { client, settings })
     }
 
-    pub async fn publish(&self, payload: &impl InfluxDbSerialize) -> Result<(), InfluxDBClientError> {
+    pub async fn publish(&self, payload: &(impl InfluxDbSerialize + ?Sized)) -> Result<(), InfluxDBClientError> {
         // https://docs.influxdata.com/influxdb/v1.8/tools/api/#write-http-endpoint
         let write_url = format!("{}/write", self.settings.base_url());
-        let request = self
+        let mut request = self
             .client
             .post(write_url)
             .query(&[("db", &self.settings.database.as_str()), ("precision", &"ms")])
-            .body(payload.to_line_data(&self.settings.prefix))
-            .send();
-        match request.await {
+            .body(payload.to_line_data(&self.settings.prefix));
+        if let (Some(username), Some(password)) = (&self.settings.username, &self.settings.password) {
+            request = request.basic_auth(username, Some(password.expose()));
+        }
+        match request.send().await {
             Ok(res) if res.status() == 204 => Ok(()),
             Ok(res) => {
                 log::error!("Unexpected response: {res:?}");
@@ -53,7 +66,7 @@ pub trait InfluxDbSerialize {
 
 impl InfluxDbSerialize for RpictFrame {
     fn to_line_data(&self, prefix: &Option<String>) -> String {
-        let measurement = vec![prefix.clone(), Some("rpict".to_string())]
+        let measurement = [prefix.clone(), Some("rpict".to_string())]
             .iter()
             .filter_map(|s| s.clone())
             .collect::<Vec<String>>()
@@ -85,15 +98,101 @@ impl InfluxDbSerialize for RpictFrame {
     }
 }
 
+/// An RPICT frame with the metadata of its lines: the frame point, kept for existing queries,
+/// is followed by one `rpict_channel` point per line, tagged with the channel settings.
+pub struct LabelledRpictFrame<'a> {
+    pub frame: &'a RpictFrame,
+    pub channels: &'a [settings::Channel; 3],
+}
+
+impl InfluxDbSerialize for LabelledRpictFrame<'_> {
+    fn to_line_data(&self, prefix: &Option<String>) -> String {
+        let measurement = [prefix.clone(), Some("rpict_channel".to_string())]
+            .iter()
+            .filter_map(|s| s.clone())
+            .collect::<Vec<String>>()
+            .join(".");
+        let frame = self.frame;
+        let lines = [
+            (
+                frame.l1_real_power,
+                frame.l1_apparent_power,
+                frame.l1_irms,
+                frame.l1_vrms,
+                frame.l1_power_factor,
+            ),
+            (
+                frame.l2_real_power,
+                frame.l2_apparent_power,
+                frame.l2_irms,
+                frame.l2_vrms,
+                frame.l2_power_factor,
+            ),
+            (
+                frame.l3_real_power,
+                frame.l3_apparent_power,
+                frame.l3_irms,
+                frame.l3_vrms,
+                frame.l3_power_factor,
+            ),
+        ];
+        let timestamp = frame.timestamp.timestamp_millis();
+        let mut points = vec![frame.to_line_data(prefix)];
+        for (channel, (real_power, apparent_power, irms, vrms, power_factor)) in self.channels.iter().zip(lines) {
+            let mut tags = vec![format!("channel={}", escape_tag(&channel.label))];
+            // empty tag values are not allowed by the line protocol
+            if !channel.description.is_empty() {
+                tags.push(format!("description={}", escape_tag(&channel.description)));
+            }
+            tags.push(format!("node_id={}", frame.node_id));
+            tags.extend(
+                channel
+                    .tags
+                    .iter()
+                    .map(|(k, v)| format!("{}={}", escape_tag(k), escape_tag(v))),
+            );
+            let tags = tags.join(",");
+            let mut fields = [
+                ("real_power", real_power),
+                ("apparent_power", apparent_power),
+                ("irms", irms),
+                ("vrms", vrms),
+                ("power_factor", power_factor),
+                ("reactive_power", reactive_power(real_power, apparent_power)),
+            ]
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<String>>();
+            fields.push(format!("export={}", Direction::of(real_power) == Direction::Export));
+            let fields = fields.join(",");
+            points.push(format!("{measurement},{tags} {fields} {timestamp}"));
+        }
+        points.join("\n")
+    }
+}
+
+/// Escapes a tag key or value, see the line protocol special characters.
+fn escape_tag(tag: &str) -> String {
+    tag.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+/// Escapes a string field value, which is double quoted.
+fn escape_string_field(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
 impl InfluxDbSerialize for LinkyFrame {
     fn to_line_data(&self, prefix: &Option<String>) -> String {
-        let measurement = vec![prefix.clone(), Some("linky".to_string())]
+        let measurement = [prefix.clone(), Some("linky".to_string())]
             .iter()
             .filter_map(|s| s.clone())
             .collect::<Vec<String>>()
             .join(".");
         let tags = format!("adco={}", self.adco);
-        let fields = vec![("hc_index", self.hchc), ("hp_index", self.hchp)]
+        let fields = [("hc_index", self.hchc), ("hp_index", self.hchp)]
             .iter()
             .map(|(k, v)| format!("{k}={v}"))
             .collect::<Vec<String>>()
@@ -103,11 +202,274 @@ impl InfluxDbSerialize for LinkyFrame {
     }
 }
 
+impl InfluxDbSerialize for Energy {
+    fn to_line_data(&self, prefix: &Option<String>) -> String {
+        let measurement = [prefix.clone(), Some("energy".to_string())]
+            .iter()
+            .filter_map(|s| s.clone())
+            .collect::<Vec<String>>()
+            .join(".");
+        let mut fields = Vec::new();
+        for (period, energy) in [("day", &self.day), ("month", &self.month), ("lifetime", &self.lifetime)] {
+            let lines = energy
+                .lines
+                .iter()
+                .enumerate()
+                .map(|(i, line)| (format!("l{}", i + 1), *line));
+            for (line, line_energy) in lines.chain([("total".to_string(), energy.total())]) {
+                fields.push(format!("{line}_import_kwh_{period}={}", line_energy.import_wh / 1000.0));
+                fields.push(format!("{line}_export_kwh_{period}={}", line_energy.export_wh / 1000.0));
+            }
+        }
+        let fields = fields.join(",");
+        let timestamp = self.timestamp().map_or(0, |timestamp| timestamp.timestamp_millis());
+        format!("{measurement} {fields} {timestamp}")
+    }
+}
+
+impl InfluxDbSerialize for ConsumptionSummary {
+    fn to_line_data(&self, prefix: &Option<String>) -> String {
+        let measurement = [prefix.clone(), Some("consumption".to_string())]
+            .iter()
+            .filter_map(|s| s.clone())
+            .collect::<Vec<String>>()
+            .join(".");
+        let mut fields = [
+            ("last_hour", self.last_hour),
+            ("today", self.today),
+            ("yesterday", self.yesterday),
+            ("this_month", self.this_month),
+            ("last_month", self.last_month),
+        ]
+        .iter()
+        .map(|(period, energy)| format!("{period}_hc_wh={},{period}_hp_wh={}", energy.hc_wh, energy.hp_wh))
+        .collect::<Vec<String>>();
+        if let Some(average_power_w) = self.average_power_w {
+            fields.push(format!("average_power_w={average_power_w}"));
+        }
+        let fields = fields.join(",");
+        let timestamp = self.timestamp.timestamp_millis();
+        format!("{measurement} {fields} {timestamp}")
+    }
+}
+
+impl InfluxDbSerialize for CostSummary {
+    fn to_line_data(&self, prefix: &Option<String>) -> String {
+        let measurement = [prefix.clone(), Some("cost".to_string())]
+            .iter()
+            .filter_map(|s| s.clone())
+            .collect::<Vec<String>>()
+            .join(".");
+        let tags = format!("currency={}", escape_tag(&self.currency));
+        let mut fields = [
+            ("today", self.today),
+            ("this_month", self.this_month),
+            ("billing_period", self.billing_period),
+            ("billing_projection", self.billing_projection),
+        ]
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<String>>();
+        if let Some(prices) = self.period_prices() {
+            fields.push(format!("hc_per_kwh={}", prices.hc_per_kwh));
+            fields.push(format!("hp_per_kwh={}", prices.hp_per_kwh));
+        }
+        if let Some(prices) = self.ejp_prices() {
+            fields.push(format!("hn_per_kwh={}", prices.hn_per_kwh));
+            fields.push(format!("pm_per_kwh={}", prices.pm_per_kwh));
+        }
+        if let Some(color) = self.tempo_color {
+            fields.push(format!("tempo_color=\"{color}\""));
+        }
+        let fields = fields.join(",");
+        let timestamp = self.timestamp.timestamp_millis();
+        format!("{measurement},{tags} {fields} {timestamp}")
+    }
+}
+
+impl InfluxDbSerialize for ImbalanceSummary {
+    fn to_line_data(&self, prefix: &Option<String>) -> String {
+        let measurement = [prefix.clone(), Some("imbalance".to_string())]
+            .iter()
+            .filter_map(|s| s.clone())
+            .collect::<Vec<String>>()
+            .join(".");
+        let mut fields = Vec::new();
+        if let (Some([l1, l2, l3]), Some(imbalance_percent)) = (self.shares, self.imbalance_percent) {
+            fields.push(format!(
+                "l1_share={l1},l2_share={l2},l3_share={l3},imbalance_percent={imbalance_percent}"
+            ));
+        }
+        let stats = |period: &str, stats: &ImbalanceStats| {
+            let [l1, l2, l3] = stats.shares;
+            let [l1_most, l2_most, l3_most] = stats.most_loaded_percent;
+            format!(
+                "{period}_l1_share={l1},{period}_l2_share={l2},{period}_l3_share={l3},\
+                {period}_avg_imbalance_percent={},{period}_max_imbalance_percent={},\
+                {period}_l1_most_loaded_percent={l1_most},{period}_l2_most_loaded_percent={l2_most},\
+                {period}_l3_most_loaded_percent={l3_most}",
+                stats.avg_imbalance_percent, stats.max_imbalance_percent
+            )
+        };
+        if let Some(day) = &self.day {
+            fields.push(stats("today", day));
+        }
+        if let Some(month) = &self.month {
+            fields.push(stats("this_month", month));
+        }
+        let fields = fields.join(",");
+        let timestamp = self.timestamp.timestamp_millis();
+        format!("{measurement} {fields} {timestamp}")
+    }
+}
+
+impl InfluxDbSerialize for PowerFactorSummary {
+    fn to_line_data(&self, prefix: &Option<String>) -> String {
+        let measurement = [prefix.clone(), Some("power_factor".to_string())]
+            .iter()
+            .filter_map(|s| s.clone())
+            .collect::<Vec<String>>()
+            .join(".");
+        let mut fields = Vec::new();
+        for (index, line) in self.lines.iter().enumerate() {
+            let l = index + 1;
+            fields.push(format!("l{l}_reactive_power={}", line.reactive_power));
+            if let Some(power_factor) = line.power_factor {
+                fields.push(format!("l{l}_power_factor={power_factor}"));
+            }
+            if let Some(today_avg_power_factor) = line.today_avg_power_factor {
+                fields.push(format!("l{l}_today_avg_power_factor={today_avg_power_factor}"));
+            }
+        }
+        let fields = fields.join(",");
+        let timestamp = self.timestamp.timestamp_millis();
+        format!("{measurement} {fields} {timestamp}")
+    }
+}
+
+impl InfluxDbSerialize for VoltageSummary {
+    fn to_line_data(&self, prefix: &Option<String>) -> String {
+        let measurement = [prefix.clone(), Some("voltage".to_string())]
+            .iter()
+            .filter_map(|s| s.clone())
+            .collect::<Vec<String>>()
+            .join(".");
+        let mut fields = Vec::new();
+        for (index, line) in self.lines.iter().enumerate() {
+            if let Some(line) = line {
+                let l = index + 1;
+                fields.push(format!(
+                    "l{l}_min={},l{l}_avg={},l{l}_max={}",
+                    line.min, line.avg, line.max
+                ));
+                fields.push(format!(
+                    "l{l}_over_count={},l{l}_over_secs={}",
+                    line.over.count, line.over.duration_secs
+                ));
+                fields.push(format!(
+                    "l{l}_under_count={},l{l}_under_secs={}",
+                    line.under.count, line.under.duration_secs
+                ));
+            }
+        }
+        if let Some(unbalance_percent) = self.unbalance_percent {
+            fields.push(format!("unbalance_percent={unbalance_percent}"));
+        }
+        let fields = fields.join(",");
+        let timestamp = self.timestamp.timestamp_millis();
+        format!("{measurement} {fields} {timestamp}")
+    }
+}
+
+impl InfluxDbSerialize for Alert {
+    fn to_line_data(&self, prefix: &Option<String>) -> String {
+        let measurement = [prefix.clone(), Some("alert".to_string())]
+            .iter()
+            .filter_map(|s| s.clone())
+            .collect::<Vec<String>>()
+            .join(".");
+        let tags = format!("name={},severity={}", escape_tag(&self.name), self.severity);
+        let fields = format!(
+            "firing={},state=\"{}\",message=\"{}\"",
+            self.is_firing(),
+            self.state,
+            escape_string_field(&self.message)
+        );
+        let timestamp = self.timestamp.timestamp_millis();
+        format!("{measurement},{tags} {fields} {timestamp}")
+    }
+}
+
+/// One point per load shedding output, tagged with its name.
+impl InfluxDbSerialize for [OutputState] {
+    fn to_line_data(&self, prefix: &Option<String>) -> String {
+        let measurement = [prefix.clone(), Some("shedding".to_string())]
+            .iter()
+            .filter_map(|s| s.clone())
+            .collect::<Vec<String>>()
+            .join(".");
+        self.iter()
+            .map(|state| {
+                format!(
+                    "{measurement},output={} on={},reason=\"{}\" {}",
+                    escape_tag(&state.name),
+                    state.on,
+                    state.reason,
+                    state.timestamp.timestamp_millis()
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl InfluxDbSerialize for MetricsSnapshot {
+    fn to_line_data(&self, prefix: &Option<String>) -> String {
+        let measurement = [prefix.clone(), Some("metrics".to_string())]
+            .iter()
+            .filter_map(|s| s.clone())
+            .collect::<Vec<String>>()
+            .join(".");
+        let mut fields = [
+            ("rpict_frames_decoded", self.rpict_frames_decoded),
+            ("rpict_frames_rejected", self.rpict_frames_rejected),
+            ("linky_frames_decoded", self.linky_frames_decoded),
+            ("linky_frames_rejected", self.linky_frames_rejected),
+            ("linky_checksum_errors", self.linky_checksum_errors),
+            ("broadcast_lagged", self.broadcast_lagged),
+            ("influxdb_writes", self.influxdb_writes),
+            ("influxdb_failures", self.influxdb_failures),
+            ("influxdb_latency_ms", self.influxdb_latency_ms),
+            ("display_flushes", self.display_flushes),
+            ("display_flush_ms", self.display_flush_ms),
+        ]
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<String>>();
+        if let Some(rss_kb) = self.process.rss_kb {
+            fields.push(format!("rss_kb={rss_kb}"));
+        }
+        if let Some(cpu_percent) = self.process.cpu_percent {
+            fields.push(format!("cpu_percent={cpu_percent}"));
+        }
+        let fields = fields.join(",");
+        let timestamp = self.timestamp.timestamp_millis();
+        format!("{measurement} {fields} {timestamp}")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::{TimeZone, Utc};
 
     use super::*;
+    use crate::alert::{AlertState, Severity};
+    use crate::analytics::consumption::TariffEnergy;
+    use crate::analytics::reactive::LinePowerFactor;
+    use crate::analytics::voltage::{Excursions, PhaseVoltage};
+    use crate::driver::linky::{TariffPeriod, TempoColor};
+    use crate::metrics::ProcessStats;
+    use crate::shedding::OutputReason;
 
     #[test]
     fn test_influxdb_serialization_rpictframe() {
@@ -144,6 +506,65 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_influxdb_serialization_labelled_rpictframe() {
+        // Given
+        let now = Utc.timestamp_millis_opt(1657113606).unwrap();
+        let frame = RpictFrame {
+            node_id: 11,
+            l1_real_power: -82.96,
+            l1_apparent_power: 422.95,
+            l1_irms: 1.64,
+            l1_vrms: 257.65,
+            l1_power_factor: 0.194,
+            l2_real_power: -50.23,
+            l2_apparent_power: 144.52,
+            l2_irms: 0.56,
+            l2_vrms: 259.95,
+            l2_power_factor: 0.346,
+            l3_real_power: 24.55,
+            l3_apparent_power: 47.17,
+            l3_irms: 0.18,
+            l3_vrms: 259.70,
+            l3_power_factor: 0.509,
+            timestamp: now,
+        };
+        let channel = |label: &str, description: &str, tags: &[(&str, &str)]| settings::Channel {
+            label: label.to_string(),
+            description: description.to_string(),
+            max_power_watts: None,
+            tags: tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        };
+        let channels = [
+            channel("KIT", "kitchen + oven", &[("room", "kitchen")]),
+            channel("HEAT", "heat pump", &[]),
+            channel("P3", "", &[("circuit", "a=1,b")]),
+        ];
+        // When
+        let actual = LabelledRpictFrame {
+            frame: &frame,
+            channels: &channels,
+        }
+        .to_line_data(&Some("prefix".to_string()));
+        // Then
+        let lines = actual.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], frame.to_line_data(&Some("prefix".to_string())));
+        assert_eq!(
+            lines[1..],
+            [
+                "prefix.rpict_channel,channel=KIT,description=kitchen\\ +\\ oven,node_id=11,room=kitchen \
+                real_power=-82.96,apparent_power=422.95,irms=1.64,vrms=257.65,power_factor=0.194,\
+                reactive_power=414.73407,export=true 1657113606",
+                "prefix.rpict_channel,channel=HEAT,description=heat\\ pump,node_id=11 \
+                real_power=-50.23,apparent_power=144.52,irms=0.56,vrms=259.95,power_factor=0.346,\
+                reactive_power=135.51007,export=true 1657113606",
+                "prefix.rpict_channel,channel=P3,node_id=11,circuit=a\\=1\\,b \
+                real_power=24.55,apparent_power=47.17,irms=0.18,vrms=259.7,power_factor=0.509,\
+                reactive_power=40.277866,export=false 1657113606",
+            ]
+        );
+    }
+
     #[test]
     fn test_influxdb_serialization_linkyframe() {
         // Given
@@ -154,6 +575,7 @@ mod tests {
             hchc: 19_650_909,
             hchp: 43_280_553,
             timestamp: now,
+            ..LinkyFrame::default()
         };
         // When
         let actual = frame.to_line_data(&Some("prefix".to_string()));
@@ -163,4 +585,285 @@ mod tests {
             "prefix.linky,adco=041876097767 hc_index=19650909,hp_index=43280553 1657113606"
         );
     }
+
+    #[test]
+    fn test_influxdb_serialization_energy() {
+        // Given
+        let now = Utc.timestamp_millis_opt(1657113606).unwrap();
+        let mut energy = Energy::default();
+        energy.update([1000.0, -500.0, 0.0], now - chrono::Duration::seconds(36));
+        energy.update([1000.0, -500.0, 0.0], now);
+        // When
+        let actual = energy.to_line_data(&Some("prefix".to_string()));
+        // Then
+        assert!(actual.starts_with(
+            "prefix.energy l1_import_kwh_day=0.01,l1_export_kwh_day=0,\
+            l2_import_kwh_day=0,l2_export_kwh_day=0.005,l3_import_kwh_day=0,l3_export_kwh_day=0,\
+            total_import_kwh_day=0.01,total_export_kwh_day=0.005,l1_import_kwh_month=0.01,"
+        ));
+        assert!(actual.ends_with(",total_import_kwh_lifetime=0.01,total_export_kwh_lifetime=0.005 1657113606"));
+    }
+
+    #[test]
+    fn test_influxdb_serialization_consumption() {
+        // Given
+        let now = Utc.timestamp_millis_opt(1657113606).unwrap();
+        let energy = |hc_wh, hp_wh| TariffEnergy { hc_wh, hp_wh };
+        let summary = ConsumptionSummary {
+            last_hour: energy(0, 1234),
+            today: energy(4200, 8145),
+            yesterday: energy(5100, 9012),
+            this_month: energy(98_765, 131_402),
+            last_month: energy(120_034, 180_250),
+            average_power_w: Some(1234.5),
+            timestamp: now,
+        };
+        // When
+        let actual = summary.to_line_data(&Some("prefix".to_string()));
+        // Then
+        assert_eq!(
+            actual,
+            "prefix.consumption last_hour_hc_wh=0,last_hour_hp_wh=1234,today_hc_wh=4200,today_hp_wh=8145,\
+        yesterday_hc_wh=5100,yesterday_hp_wh=9012,this_month_hc_wh=98765,this_month_hp_wh=131402,\
+        last_month_hc_wh=120034,last_month_hp_wh=180250,average_power_w=1234.5 1657113606"
+        );
+    }
+
+    #[test]
+    fn test_influxdb_serialization_cost() {
+        // Given
+        let now = Utc.timestamp_millis_opt(1657113606).unwrap();
+        let summary = CostSummary {
+            currency: "EUR".to_string(),
+            today: 1.5,
+            this_month: 34.25,
+            billing_period: 56.75,
+            billing_projection: 98.5,
+            billing_start: "2023-01-15".parse().unwrap(),
+            billing_end: "2023-03-15".parse().unwrap(),
+            prices: Some(settings::TariffPrices {
+                from: "2023-02-01".parse().unwrap(),
+                subscription_per_month: 15.65,
+                hc_per_kwh: 0.1828,
+                hp_per_kwh: 0.246,
+                tempo: Some(settings::TempoPrices {
+                    blue: settings::PeriodPrices {
+                        hc_per_kwh: 0.1296,
+                        hp_per_kwh: 0.1609,
+                    },
+                    white: settings::PeriodPrices {
+                        hc_per_kwh: 0.1486,
+                        hp_per_kwh: 0.1894,
+                    },
+                    red: settings::PeriodPrices {
+                        hc_per_kwh: 0.1568,
+                        hp_per_kwh: 0.7562,
+                    },
+                }),
+                ejp: Some(settings::EjpPrices {
+                    hn_per_kwh: 0.1974,
+                    pm_per_kwh: 1.016,
+                }),
+            }),
+            tariff_period: TariffPeriod::HC,
+            tempo_color: None,
+            timestamp: now,
+        };
+        // When
+        let actual = summary.to_line_data(&Some("prefix".to_string()));
+        let tempo = CostSummary {
+            tempo_color: Some(TempoColor::Red),
+            ..summary.clone()
+        }
+        .to_line_data(&Some("prefix".to_string()));
+        let ejp = CostSummary {
+            tariff_period: TariffPeriod::PM,
+            ..summary
+        }
+        .to_line_data(&Some("prefix".to_string()));
+        // Then
+        assert_eq!(
+            actual,
+            "prefix.cost,currency=EUR today=1.5,this_month=34.25,billing_period=56.75,billing_projection=98.5,\
+        hc_per_kwh=0.1828,hp_per_kwh=0.246 1657113606"
+        );
+        assert_eq!(
+            tempo,
+            "prefix.cost,currency=EUR today=1.5,this_month=34.25,billing_period=56.75,billing_projection=98.5,\
+        hc_per_kwh=0.1568,hp_per_kwh=0.7562,tempo_color=\"red\" 1657113606"
+        );
+        assert_eq!(
+            ejp,
+            "prefix.cost,currency=EUR today=1.5,this_month=34.25,billing_period=56.75,billing_projection=98.5,\
+        hn_per_kwh=0.1974,pm_per_kwh=1.016 1657113606"
+        );
+    }
+
+    #[test]
+    fn test_influxdb_serialization_alert() {
+        // Given
+        let now = Utc.timestamp_millis_opt(1657113606).unwrap();
+        let alert = Alert {
+            name: "overrun".to_string(),
+            severity: Severity::Critical,
+            state: AlertState::Firing,
+            message: "subscribed power exceeded, \"32\" A drawn".to_string(),
+            timestamp: now,
+        };
+        // When
+        let actual = alert.to_line_data(&Some("prefix".to_string()));
+        // Then
+        assert_eq!(
+            actual,
+            "prefix.alert,name=overrun,severity=critical \
+        firing=true,state=\"firing\",message=\"subscribed power exceeded, \\\"32\\\" A drawn\" 1657113606"
+        );
+    }
+
+    #[test]
+    fn test_influxdb_serialization_imbalance() {
+        // Given
+        let summary = ImbalanceSummary {
+            shares: Some([50.0, 25.0, 25.0]),
+            imbalance_percent: Some(75.5),
+            day: None,
+            month: Some(ImbalanceStats {
+                shares: [40.0, 35.0, 25.0],
+                avg_imbalance_percent: 20.5,
+                max_imbalance_percent: 110.0,
+                most_loaded_percent: [60.0, 40.0, 0.0],
+            }),
+            timestamp: Utc.timestamp_millis_opt(1657113606).unwrap(),
+        };
+        // When
+        let actual = summary.to_line_data(&Some("prefix".to_string()));
+        // Then
+        assert_eq!(
+            actual,
+            "prefix.imbalance l1_share=50,l2_share=25,l3_share=25,imbalance_percent=75.5,\
+            this_month_l1_share=40,this_month_l2_share=35,this_month_l3_share=25,\
+            this_month_avg_imbalance_percent=20.5,this_month_max_imbalance_percent=110,\
+            this_month_l1_most_loaded_percent=60,this_month_l2_most_loaded_percent=40,\
+            this_month_l3_most_loaded_percent=0 1657113606"
+        );
+    }
+
+    #[test]
+    fn test_influxdb_serialization_power_factor() {
+        // Given
+        let line = |reactive_power, power_factor, today_avg_power_factor| LinePowerFactor {
+            real_power: 0.0,
+            reactive_power,
+            power_factor,
+            direction: Direction::Import,
+            today_avg_power_factor,
+        };
+        let summary = PowerFactorSummary {
+            lines: [
+                line(400.0, Some(0.6), Some(0.75)),
+                line(0.0, None, Some(0.5)),
+                line(0.0, None, None),
+            ],
+            timestamp: Utc.timestamp_millis_opt(1657113606).unwrap(),
+        };
+        // When
+        let actual = summary.to_line_data(&Some("prefix".to_string()));
+        // Then
+        assert_eq!(
+            actual,
+            "prefix.power_factor l1_reactive_power=400,l1_power_factor=0.6,l1_today_avg_power_factor=0.75,\
+            l2_reactive_power=0,l2_today_avg_power_factor=0.5,l3_reactive_power=0 1657113606"
+        );
+    }
+
+    #[test]
+    fn test_influxdb_serialization_voltage() {
+        // Given
+        let line = |min, avg, max| PhaseVoltage {
+            min,
+            avg,
+            max,
+            over: Excursions {
+                count: 2,
+                duration_secs: 12.5,
+            },
+            under: Excursions::default(),
+        };
+        let summary = VoltageSummary {
+            start: Utc.timestamp_millis_opt(1657113006).unwrap(),
+            lines: [Some(line(250.5, 257.25, 259.75)), Some(line(230.0, 231.5, 233.0)), None],
+            unbalance_percent: None,
+            timestamp: Utc.timestamp_millis_opt(1657113606).unwrap(),
+        };
+        // When
+        let actual = summary.to_line_data(&Some("prefix".to_string()));
+        // Then
+        assert_eq!(
+            actual,
+            "prefix.voltage l1_min=250.5,l1_avg=257.25,l1_max=259.75,l1_over_count=2,l1_over_secs=12.5,\
+            l1_under_count=0,l1_under_secs=0,l2_min=230,l2_avg=231.5,l2_max=233,l2_over_count=2,l2_over_secs=12.5,\
+            l2_under_count=0,l2_under_secs=0 1657113606"
+        );
+    }
+
+    #[test]
+    fn test_influxdb_serialization_shedding() {
+        // Given
+        let now = Utc.timestamp_millis_opt(1657113606).unwrap();
+        let states = [
+            OutputState {
+                name: "water_heater".to_string(),
+                on: false,
+                reason: OutputReason::TempoRedDay,
+                timestamp: now,
+            },
+            OutputState {
+                name: "heaters".to_string(),
+                on: true,
+                reason: OutputReason::Auto,
+                timestamp: now,
+            },
+        ];
+        // When
+        let actual = states.to_line_data(&Some("prefix".to_string()));
+        // Then
+        assert_eq!(
+            actual,
+            "prefix.shedding,output=water_heater on=false,reason=\"red day\" 1657113606\n\
+        prefix.shedding,output=heaters on=true,reason=\"auto\" 1657113606"
+        );
+    }
+
+    #[test]
+    fn test_influxdb_serialization_metrics() {
+        // Given
+        let now = Utc.timestamp_millis_opt(1657113606).unwrap();
+        let snapshot = MetricsSnapshot {
+            rpict_frames_decoded: 120,
+            rpict_frames_rejected: 2,
+            linky_frames_decoded: 80,
+            linky_frames_rejected: 1,
+            linky_checksum_errors: 3,
+            broadcast_lagged: 0,
+            influxdb_writes: 200,
+            influxdb_failures: 4,
+            influxdb_latency_ms: 12,
+            display_flushes: 150,
+            display_flush_ms: 8,
+            process: ProcessStats {
+                rss_kb: Some(5260),
+                cpu_percent: None,
+            },
+            timestamp: now,
+        };
+        // When
+        let actual = snapshot.to_line_data(&None);
+        // Then
+        assert_eq!(
+            actual,
+            "metrics rpict_frames_decoded=120,rpict_frames_rejected=2,linky_frames_decoded=80,\
+        linky_frames_rejected=1,linky_checksum_errors=3,broadcast_lagged=0,influxdb_writes=200,influxdb_failures=4,\
+        influxdb_latency_ms=12,display_flushes=150,display_flush_ms=8,rss_kb=5260 1657113606"
+        );
+    }
 }
@@ -0,0 +1,106 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+use crate::alert::Alert;
+use crate::service::smtp::SmtpClient;
+use crate::service::webhook::WebhookClient;
+use crate::settings;
+
+#[derive(Debug, PartialEq)]
+pub enum NotificationError {
+    /// The target is unreachable or overloaded, sending again later may succeed
+    Transient(String),
+    /// The target refused the notification, eg. a wrong URL or credentials
+    Permanent(String),
+}
+
+impl Display for NotificationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NotificationError::Transient(e) => write!(f, "{e}"),
+            NotificationError::Permanent(e) => write!(f, "{e} (not retried)"),
+        }
+    }
+}
+
+/// Sends alerts to one of the configured notifier backends.
+pub enum NotificationClient {
+    Webhook(WebhookClient),
+    Smtp(SmtpClient),
+}
+
+impl NotificationClient {
+    pub fn new(settings: &settings::Notifier) -> Result<NotificationClient, Box<dyn Error>> {
+        match (&settings.webhook, &settings.smtp) {
+            (Some(webhook), _) => Ok(NotificationClient::Webhook(WebhookClient::new(webhook)?)),
+            (None, Some(smtp)) => Ok(NotificationClient::Smtp(SmtpClient::new(smtp)?)),
+            (None, None) => Err(format!("notifier {} has no backend", settings.name).into()),
+        }
+    }
+
+    pub async fn send(&self, alert: &Alert) -> Result<(), NotificationError> {
+        match self {
+            NotificationClient::Webhook(client) => client.send(alert).await,
+            NotificationClient::Smtp(client) => client.send(alert).await,
+        }
+    }
+}
+
+/// Allows at most `max_count` notifications over any sliding hour.
+pub struct RateLimiter {
+    max_count: usize,
+    sent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    const WINDOW: Duration = Duration::from_secs(3600);
+
+    pub fn new(max_per_hour: u32) -> Self {
+        Self {
+            max_count: max_per_hour as usize,
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Records a notification sent at `now`, unless the limit is reached.
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| now.saturating_duration_since(*sent) >= Self::WINDOW)
+        {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.max_count {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_sliding_window() {
+        // Given
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(2);
+        // When
+        let first = limiter.try_acquire(start);
+        let second = limiter.try_acquire(start + Duration::from_secs(1800));
+        let limited = limiter.try_acquire(start + Duration::from_secs(3599));
+        let first_expired = limiter.try_acquire(start + Duration::from_secs(3600));
+        let limited_again = limiter.try_acquire(start + Duration::from_secs(3601));
+        // Then
+        assert!(first);
+        assert!(second);
+        assert!(!limited);
+        assert!(first_expired);
+        assert!(!limited_again);
+    }
+}
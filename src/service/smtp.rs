@@ -0,0 +1,183 @@
+use std::error::Error;
+use std::time::Duration;
+
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use crate::alert::Alert;
+use crate::service::notification::NotificationError;
+use crate::settings::{self, SmtpTls};
+
+pub struct SmtpClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl SmtpClient {
+    pub fn new(settings: &settings::Smtp) -> Result<SmtpClient, Box<dyn Error>> {
+        let mut builder = match settings.tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host),
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)?,
+        }
+        .port(settings.port())
+        .timeout(Some(Duration::from_secs(10)));
+        if let (Some(username), Some(password)) = (&settings.username, &settings.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.expose().to_string()));
+        }
+        Ok(SmtpClient {
+            transport: builder.build(),
+            from: settings.from.parse()?,
+            to: settings.to.iter().map(|to| to.parse()).collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub async fn send(&self, alert: &Alert) -> Result<(), NotificationError> {
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(format!(
+                "[energy-monitor] {} {} {}",
+                alert.severity, alert.name, alert.state
+            ))
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let message = message
+            .body(format!(
+                "{}\n\n{} {} at {}\n",
+                alert.message,
+                alert.name,
+                alert.state,
+                alert.timestamp.to_rfc3339()
+            ))
+            .map_err(|e| NotificationError::Permanent(e.to_string()))?;
+        match self.transport.send(message).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.is_permanent() => Err(NotificationError::Permanent(e.to_string())),
+            Err(e) => Err(NotificationError::Transient(e.to_string())),
+        }
+    }
+}
+
+/// Checks an address the way messages are built, eg. `me@example.com` or `Me <me@example.com>`.
+pub fn is_valid_mailbox(address: &str) -> bool {
+    address.parse::<Mailbox>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    use super::*;
+    use crate::alert::{AlertState, Severity};
+
+    fn alert() -> Alert {
+        Alert {
+            name: "rpict_disconnected".to_string(),
+            severity: Severity::Critical,
+            state: AlertState::Firing,
+            message: "RPICT disconnected".to_string(),
+            timestamp: Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn settings(port: u16) -> settings::Smtp {
+        settings::Smtp {
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+            password_file: None,
+            password_env: None,
+            from: "Energy monitor <energy@example.com>".to_string(),
+            to: vec!["me@example.com".to_string()],
+        }
+    }
+
+    /// Plays a plaintext SMTP server accepting one message, or rejecting recipients with `rcpt_reply`,
+    /// handing back the commands and data received.
+    async fn serve_once(rcpt_reply: &'static str) -> (u16, oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = socket.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut session = String::new();
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            let mut in_data = false;
+            while let Ok(Some(line)) = lines.next_line().await {
+                session.push_str(&line);
+                session.push('\n');
+                let reply = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    "250 queued"
+                } else {
+                    match line.split(' ').next().unwrap_or_default().to_ascii_uppercase().as_str() {
+                        "EHLO" => "250 localhost",
+                        "RCPT" => rcpt_reply,
+                        "DATA" => {
+                            in_data = true;
+                            "354 end with <CRLF>.<CRLF>"
+                        }
+                        "QUIT" => {
+                            writer.write_all(b"221 bye\r\n").await.unwrap();
+                            break;
+                        }
+                        _ => "250 OK",
+                    }
+                };
+                writer.write_all(format!("{reply}\r\n").as_bytes()).await.unwrap();
+            }
+            tx.send(session).unwrap_or_default();
+        });
+        (port, rx)
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        // Given
+        let (port, session) = serve_once("250 OK").await;
+        let client = SmtpClient::new(&settings(port)).unwrap();
+        // When
+        let result = client.send(&alert()).await;
+        // Then
+        assert_eq!(result, Ok(()));
+        drop(client);
+        let session = session.await.unwrap();
+        assert!(session.contains("MAIL FROM:<energy@example.com>"));
+        assert!(session.contains("RCPT TO:<me@example.com>"));
+        assert!(session.contains("Subject: [energy-monitor] critical rpict_disconnected firing"));
+        assert!(session.contains("RPICT disconnected"));
+    }
+
+    #[tokio::test]
+    async fn test_send_rejected() {
+        // Given
+        let (port, _) = serve_once("550 no such user").await;
+        let client = SmtpClient::new(&settings(port)).unwrap();
+        // When
+        let result = client.send(&alert()).await;
+        // Then
+        assert!(matches!(result, Err(NotificationError::Permanent(_))));
+    }
+
+    #[test]
+    fn test_is_valid_mailbox() {
+        assert!(is_valid_mailbox("me@example.com"));
+        assert!(is_valid_mailbox("Energy monitor <energy@example.com>"));
+        assert!(!is_valid_mailbox("me"));
+    }
+}
@@ -0,0 +1,121 @@
+use std::env;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+use std::time::Duration;
+
+/// Sends service state updates to systemd, see `man sd_notify`.
+#[derive(Clone, Debug)]
+pub struct Notifier {
+    socket_path: String,
+}
+
+impl Notifier {
+    /// Returns a notifier when the process was started by systemd with `Type=notify`.
+    pub fn from_env() -> Option<Self> {
+        env::var("NOTIFY_SOCKET")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map(Self::new)
+    }
+
+    pub fn new(socket_path: String) -> Self {
+        Self { socket_path }
+    }
+
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        let socket = UnixDatagram::unbound()?;
+        match self.socket_path.strip_prefix('@') {
+            Some(name) => socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?,
+            None => socket.send_to(state.as_bytes(), &self.socket_path)?,
+        };
+        Ok(())
+    }
+}
+
+/// Returns the watchdog timeout configured with `WatchdogSec=`, if it targets this process.
+pub fn watchdog_timeout() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+    env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|usec| *usec > 0)
+        .map(Duration::from_micros)
+}
+
+/// Builds a sample unit file starting the application with the given command line.
+pub fn unit_file(exec_start: &str) -> String {
+    format!(
+        "\
+[Unit]
+Description=energy-monitor
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=notify
+ExecStart={exec_start}
+ExecReload=/bin/kill -HUP $MAINPID
+User=pi
+Restart=on-failure
+StateDirectory=energy-monitor
+RestartSec=5
+WatchdogSec=30
+TimeoutStopSec=15
+
+[Install]
+WantedBy=multi-user.target
+"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notifier_notify() {
+        // Given
+        let path = env::temp_dir().join(format!("energy-monitor-test-notify-{}.sock", process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = UnixDatagram::bind(&path).unwrap();
+        let notifier = Notifier::new(path.to_string_lossy().to_string());
+        // When
+        notifier.notify("READY=1").unwrap();
+        // Then
+        let mut buffer = [0u8; 16];
+        let size = server.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"READY=1");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_notifier_notify_abstract_socket() {
+        // Given
+        let name = format!("energy-monitor-test-notify-{}", process::id());
+        let server = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+        let notifier = Notifier::new(format!("@{name}"));
+        // When
+        notifier.notify("WATCHDOG=1").unwrap();
+        // Then
+        let mut buffer = [0u8; 16];
+        let size = server.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"WATCHDOG=1");
+    }
+
+    #[test]
+    fn test_unit_file() {
+        // When
+        let actual = unit_file("/usr/local/bin/energy-monitor -c /etc/energy-monitor.yml");
+        // Then
+        assert!(actual.contains("Type=notify\n"));
+        assert!(actual.contains("ExecStart=/usr/local/bin/energy-monitor -c /etc/energy-monitor.yml\n"));
+        assert!(actual.contains("ExecReload=/bin/kill -HUP $MAINPID\n"));
+        assert!(actual.contains("WatchdogSec=30\n"));
+    }
+}
@@ -0,0 +1,205 @@
+use std::error::Error;
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+use reqwest::header::CONTENT_TYPE;
+
+use crate::alert::{Alert, AlertState, Severity};
+use crate::service::notification::NotificationError;
+use crate::settings;
+
+/// Body sent when none is configured, with every alert field.
+const DEFAULT_BODY: &str = r#"{"name": "{{name}}", "severity": "{{severity}}", "state": "{{state}}", "message": "{{message}}", "timestamp": "{{timestamp}}"}"#;
+
+pub struct WebhookClient {
+    client: reqwest::Client,
+    settings: settings::Webhook,
+}
+
+impl WebhookClient {
+    pub fn new(settings: &settings::Webhook) -> Result<WebhookClient, Box<dyn Error>> {
+        let settings = settings.clone();
+        let client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?;
+        Ok(WebhookClient { client, settings })
+    }
+
+    pub async fn send(&self, alert: &Alert) -> Result<(), NotificationError> {
+        let body = render_body(self.settings.body.as_deref().unwrap_or(DEFAULT_BODY), alert);
+        let mut request = self
+            .client
+            .post(&self.settings.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+        for (name, value) in &self.settings.headers {
+            request = request.header(name, value.expose());
+        }
+        if let Some(token) = &self.settings.token {
+            request = request.bearer_auth(token.expose());
+        }
+        match request.send().await {
+            Ok(res) if res.status().is_success() => Ok(()),
+            // rate limited or server side failure
+            Ok(res) if res.status().is_server_error() || res.status() == 429 => Err(NotificationError::Transient(
+                format!("unexpected response {}", res.status()),
+            )),
+            Ok(res) => Err(NotificationError::Permanent(format!(
+                "unexpected response {}",
+                res.status()
+            ))),
+            Err(err) if err.is_builder() => Err(NotificationError::Permanent(err.to_string())),
+            Err(err) => Err(NotificationError::Transient(err.to_string())),
+        }
+    }
+}
+
+/// Replaces the placeholders of a JSON body, values being escaped to fit in JSON strings.
+pub fn render_body(template: &str, alert: &Alert) -> String {
+    let escape = |value: &str| {
+        let quoted = serde_json::Value::from(value).to_string();
+        quoted[1..quoted.len() - 1].to_string()
+    };
+    // the message goes last, so that placeholders it may contain are kept as is
+    template
+        .replace("{{name}}", &escape(&alert.name))
+        .replace("{{severity}}", &alert.severity.to_string())
+        .replace("{{state}}", &alert.state.to_string())
+        .replace("{{timestamp}}", &alert.timestamp.to_rfc3339())
+        .replace("{{message}}", &escape(&alert.message))
+}
+
+/// Checks that a body template renders to JSON, whatever the alert.
+pub fn is_valid_body(template: &str) -> bool {
+    let alert = Alert {
+        name: "name".to_string(),
+        severity: Severity::Warning,
+        state: AlertState::Firing,
+        message: "\"quoted\" \\ message".to_string(),
+        timestamp: Utc.timestamp_opt(0, 0).unwrap(),
+    };
+    serde_json::from_str::<serde_json::Value>(&render_body(template, &alert)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    use super::*;
+    use crate::settings::Secret;
+
+    fn alert() -> Alert {
+        Alert {
+            name: "low_voltage".to_string(),
+            severity: Severity::Critical,
+            state: AlertState::Firing,
+            message: "l2_vrms at 201.5, below \"207\"".to_string(),
+            timestamp: Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn settings(url: String, body: Option<&str>) -> settings::Webhook {
+        settings::Webhook {
+            url,
+            body: body.map(str::to_string),
+            headers: BTreeMap::from([("X-Priority".to_string(), Secret::from("5".to_string()))]),
+            headers_file: BTreeMap::new(),
+            headers_env: BTreeMap::new(),
+            token: None,
+            token_file: None,
+            token_env: None,
+        }
+    }
+
+    /// Answers a single HTTP request with the given status, handing back the raw request.
+    async fn serve_once(status: &'static str) -> (String, oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            loop {
+                let read = socket.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((headers, body)) = text.split_once("\r\n\r\n") {
+                    let length = headers
+                        .lines()
+                        .find_map(|line| {
+                            line.to_ascii_lowercase()
+                                .strip_prefix("content-length: ")
+                                .map(str::to_string)
+                        })
+                        .and_then(|length| length.trim().parse::<usize>().ok())
+                        .unwrap_or(0);
+                    if body.len() >= length || read == 0 {
+                        break;
+                    }
+                }
+            }
+            let response = format!("HTTP/1.1 {status}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+            socket.write_all(response.as_bytes()).await.unwrap();
+            tx.send(String::from_utf8_lossy(&request).to_string()).unwrap();
+        });
+        (url, rx)
+    }
+
+    #[test]
+    fn test_render_body() {
+        // Given
+        let template = r#"{"text": "{{severity}}: {{message}}", "title": "{{name}} {{state}}"}"#;
+        // When
+        let body = render_body(template, &alert());
+        // Then
+        assert_eq!(
+            body,
+            r#"{"text": "critical: l2_vrms at 201.5, below \"207\"", "title": "low_voltage firing"}"#
+        );
+        assert!(is_valid_body(DEFAULT_BODY));
+        assert!(is_valid_body(template));
+        assert!(!is_valid_body(r#"{"text": {{message}}}"#));
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        // Given
+        let (url, request) = serve_once("200 OK").await;
+        let client = WebhookClient::new(&settings(url, None)).unwrap();
+        // When
+        let result = client.send(&alert()).await;
+        // Then
+        assert_eq!(result, Ok(()));
+        let request = request.await.unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.contains("content-type: application/json\r\n"));
+        assert!(request.contains("x-priority: 5\r\n"));
+        let body = request.split_once("\r\n\r\n").unwrap().1;
+        let json: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(json["name"], "low_voltage");
+        assert_eq!(json["message"], "l2_vrms at 201.5, below \"207\"");
+        assert_eq!(json["timestamp"], "2023-03-14T12:00:00+00:00");
+    }
+
+    #[tokio::test]
+    async fn test_send_failures() {
+        // Given
+        let (server_error_url, _) = serve_once("503 Service Unavailable").await;
+        let (client_error_url, _) = serve_once("404 Not Found").await;
+        // When
+        let server_error = WebhookClient::new(&settings(server_error_url, None))
+            .unwrap()
+            .send(&alert())
+            .await;
+        let client_error = WebhookClient::new(&settings(client_error_url, None))
+            .unwrap()
+            .send(&alert())
+            .await;
+        // Then
+        assert!(matches!(server_error, Err(NotificationError::Transient(_))));
+        assert!(matches!(client_error, Err(NotificationError::Permanent(_))));
+    }
+}
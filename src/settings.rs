@@ -1,31 +1,502 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Display, Formatter};
+use std::path::{Path, PathBuf};
 use std::result::Result;
+use std::{env, fs};
 
+use chrono::{Datelike, NaiveDate};
 use config::{Config, ConfigError, Environment, File, FileFormat};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize, Serializer};
 
-#[derive(Debug, Deserialize, Clone)]
+use crate::alert::connection::Source;
+use crate::alert::imbalance::ImbalanceDetector;
+use crate::alert::overrun::OverrunDetector;
+use crate::alert::rule::Metric;
+use crate::alert::Severity;
+use crate::analytics::peak::PeakPeriod;
+use crate::driver::linky::{TariffPeriod, TempoColor};
+use crate::driver::ssd1305;
+use crate::service::{smtp, webhook};
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayBackend {
+    Ssd1305,
+    Png,
+    Terminal,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 #[allow(unused)]
 pub struct Hmi {
+    pub enabled: bool,
+    pub display_backend: DisplayBackend,
+    pub display_png_path: PathBuf,
     pub max_line_power_watts: f32,
     pub sleep_timeout_secs: u64,
     pub button_debounce_ms: u64,
     pub button_bcm_pin: u8,
+    pub peak_period: PeakPeriod,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct State {
+    pub path: PathBuf,
+    pub save_interval_secs: u64,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Metadata of an RPICT line, shared by the HMI and the data logger.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct Channel {
+    /// Short name shown next to the gauge
+    pub label: String,
+    /// What the line feeds, eg. "kitchen + oven"
+    #[serde(default)]
+    pub description: String,
+    /// Gauge scale, `hmi.max_line_power_watts` when unset
+    #[serde(default)]
+    pub max_power_watts: Option<f32>,
+    /// Extra tags added to the channel InfluxDB points
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Channel {
+    /// Longest label fitting next to a gauge.
+    pub const MAX_LABEL_LENGTH: usize = 4;
+
+    pub fn max_power_watts(&self, hmi: &Hmi) -> f32 {
+        self.max_power_watts.unwrap_or(hmi.max_line_power_watts)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 #[allow(unused)]
 pub struct Serial {
     pub rpict: String,
     pub linky: String,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Prices applying from a date until the next entry, in the tariff currency.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct TariffPrices {
+    pub from: NaiveDate,
+    pub subscription_per_month: f64,
+    pub hc_per_kwh: f64,
+    pub hp_per_kwh: f64,
+    /// Prices per day color, used instead of the HC/HP ones on Tempo meters
+    #[serde(default)]
+    pub tempo: Option<TempoPrices>,
+    /// Normal hours and peak days prices, used instead of the HC/HP ones on EJP meters
+    #[serde(default)]
+    pub ejp: Option<EjpPrices>,
+}
+
+impl TariffPrices {
+    /// HC/HP prices of a Tempo day color, or the plain ones.
+    pub fn period_prices(&self, color: Option<TempoColor>) -> PeriodPrices {
+        match (&self.tempo, color) {
+            (Some(tempo), Some(color)) => tempo.of(color).clone(),
+            _ => PeriodPrices {
+                hc_per_kwh: self.hc_per_kwh,
+                hp_per_kwh: self.hp_per_kwh,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct PeriodPrices {
+    pub hc_per_kwh: f64,
+    pub hp_per_kwh: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct TempoPrices {
+    pub blue: PeriodPrices,
+    pub white: PeriodPrices,
+    pub red: PeriodPrices,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct EjpPrices {
+    pub hn_per_kwh: f64,
+    pub pm_per_kwh: f64,
+}
+
+impl TempoPrices {
+    pub fn of(&self, color: TempoColor) -> &PeriodPrices {
+        match color {
+            TempoColor::Blue => &self.blue,
+            TempoColor::White => &self.white,
+            TempoColor::Red => &self.red,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct Tariff {
+    pub currency: String,
+    /// Start of any past billing period, the following ones being computed from it
+    pub billing_start: NaiveDate,
+    pub billing_months: u32,
+    /// Price changes, by ascending effective date
+    pub prices: Vec<TariffPrices>,
+}
+
+/// A condition on a metric, firing once it held for `for_secs`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct AlertRule {
+    pub name: String,
+    #[serde(default)]
+    pub severity: Severity,
+    pub metric: Metric,
+    /// Threshold the metric must exceed, exclusive with `below`
+    #[serde(default)]
+    pub above: Option<f32>,
+    #[serde(default)]
+    pub below: Option<f32>,
+    #[serde(default)]
+    pub for_secs: u64,
+    /// Margin past the threshold the metric must cross back to resolve the alert
+    #[serde(default)]
+    pub hysteresis: f32,
+    /// Shortest time between two firings of the rule
+    #[serde(default)]
+    pub cooldown_secs: u64,
+}
+
+impl AlertRule {
+    /// Longest rule name, shown in the alert page header.
+    pub const MAX_NAME_LENGTH: usize = 20;
+}
+
+/// Alerts raised on subscribed power overruns, from the Linky ADPS warning and the lines current,
+/// and on user defined rules.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct Alerts {
+    /// Share of the subscribed current above which a line is reported close to overrun
+    pub overrun_warning_percent: f32,
+    /// Subscribed current per phase, the Linky ISOUSC when unset
+    #[serde(default)]
+    pub subscribed_current_amps: Option<f32>,
+    /// Current imbalance between the lines above which the most loaded one is reported
+    pub imbalance_warning_percent: f32,
+    /// Time the same line stays the most loaded above the imbalance threshold before reporting it
+    pub imbalance_for_secs: u64,
+    /// Average line current below which the imbalance isn't reported
+    pub imbalance_min_current_amps: f32,
+    #[serde(default)]
+    pub rules: Vec<AlertRule>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// Driven by the load shedding rules
+    #[default]
+    Auto,
+    /// Forced on, eg. to heat water during a holiday return
+    On,
+    Off,
+}
+
+/// A relay powering a sheddable load, eg. a water heater.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct Output {
+    pub name: String,
+    pub bcm_pin: u8,
+    /// Switch the load on with a low level, as most relay boards do
+    #[serde(default)]
+    pub active_low: bool,
+    /// Outputs of the lowest priority are shed first and restored last
+    #[serde(default)]
+    pub priority: u8,
+    #[serde(default)]
+    pub mode: OutputMode,
+    /// Tariff periods the load may run in, any when empty
+    #[serde(default)]
+    pub periods: Vec<TariffPeriod>,
+    /// Keeps the load off on Tempo red days
+    #[serde(default)]
+    pub off_on_tempo_red_days: bool,
+    #[serde(default)]
+    pub min_on_secs: u64,
+    #[serde(default)]
+    pub min_off_secs: u64,
+}
+
+impl Output {
+    /// Longest output name, shown on the load shedding page.
+    pub const MAX_NAME_LENGTH: usize = 12;
+}
+
+/// Relays shed one at a time when a line draws close to the subscribed current, and restored once it dropped.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct LoadShedding {
+    /// Share of the subscribed current above which an output is shed
+    pub shed_above_percent: f32,
+    /// Share of the subscribed current below which a shed output is restored
+    pub restore_below_percent: f32,
+    /// Time between two outputs shed, for the current to settle
+    pub shed_step_secs: u64,
+    /// Time below the restore threshold before restoring an output, and between two outputs restored
+    pub restore_step_secs: u64,
+    #[serde(default)]
+    pub outputs: Vec<Output>,
+}
+
+impl LoadShedding {
+    /// Settings only read at startup, as the pins are claimed once.
+    pub fn pins(&self) -> Vec<(&str, u8, bool)> {
+        self.outputs
+            .iter()
+            .map(|output| (output.name.as_str(), output.bcm_pin, output.active_low))
+            .collect()
+    }
+}
+
+/// Voltage tolerance, EN 50160 allowing 230 V ± 10% on low voltage networks.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct VoltageQuality {
+    pub nominal_volts: f32,
+    /// Deviation from the nominal voltage beyond which an over or under-voltage is counted
+    pub tolerance_percent: f32,
+}
+
+/// A URL receiving alerts as a templated JSON body, eg. ntfy, Gotify or a Slack-compatible webhook.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct Webhook {
+    pub url: String,
+    /// JSON body with `{{name}}`, `{{severity}}`, `{{state}}`, `{{message}}` and `{{timestamp}}`
+    /// placeholders, replaced by JSON escaped values. Every field when unset.
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Extra headers, their values often holding keys, eg. `X-Gotify-Key`
+    #[serde(default)]
+    pub headers: BTreeMap<String, Secret>,
+    /// Header values read from files, by header name
+    #[serde(default)]
+    pub headers_file: BTreeMap<String, PathBuf>,
+    /// Header values read from environment variables, by header name
+    #[serde(default)]
+    pub headers_env: BTreeMap<String, String>,
+    /// Sent as a bearer token in the `Authorization` header
+    #[serde(default)]
+    pub token: Option<Secret>,
+    #[serde(default)]
+    pub token_file: Option<PathBuf>,
+    #[serde(default)]
+    pub token_env: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plaintext, for a trusted relay on the local network
+    None,
+    #[default]
+    Starttls,
+    Tls,
+}
+
+/// An SMTP server relaying alerts by email.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct Smtp {
+    pub host: String,
+    /// 25, 587 or 465 depending on `tls` when unset
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<Secret>,
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
+    #[serde(default)]
+    pub password_env: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+impl Smtp {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(match self.tls {
+            SmtpTls::None => 25,
+            SmtpTls::Starttls => 587,
+            SmtpTls::Tls => 465,
+        })
+    }
+}
+
+/// A backend receiving alerts and connection losses, exactly one of `webhook` and `smtp` being set.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[allow(unused)]
+pub struct Notifier {
+    pub name: String,
+    #[serde(default)]
+    pub webhook: Option<Webhook>,
+    #[serde(default)]
+    pub smtp: Option<Smtp>,
+    /// Alerts of a lower severity are not sent
+    #[serde(default)]
+    pub min_severity: Severity,
+    /// Names of the alerts sent, all when empty
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Notifications sent beyond this count over the last hour are dropped
+    #[serde(default = "Notifier::default_max_per_hour")]
+    pub max_per_hour: u32,
+    /// Attempts after a transient failure, with an exponential backoff
+    #[serde(default = "Notifier::default_retries")]
+    pub retries: u32,
+}
+
+impl Notifier {
+    pub const MAX_RETRIES: u32 = 8;
+
+    fn default_max_per_hour() -> u32 {
+        20
+    }
+
+    fn default_retries() -> u32 {
+        3
+    }
+}
+
+/// A sensitive setting value, redacted from `Debug` output and printed settings.
+#[derive(Deserialize, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("***")
+    }
+}
+
+/// Reads a secret given either inline, as a file path (`<key>_file`, eg. a systemd credential)
+/// or as an environment variable name (`<key>_env`), at most one of them being set.
+fn resolve_secret(
+    key: &str,
+    value: &mut Option<Secret>,
+    file: Option<&Path>,
+    env_var: Option<&str>,
+) -> Result<(), ConfigError> {
+    let keys = [key.to_string(), format!("{key}_file"), format!("{key}_env")];
+    resolve_secret_from(&keys, value, file, env_var)
+}
+
+/// Same as [`resolve_secret`], the inline, file and environment variable keys being named in errors by `keys`.
+fn resolve_secret_from(
+    [key, file_key, env_key]: &[String; 3],
+    value: &mut Option<Secret>,
+    file: Option<&Path>,
+    env_var: Option<&str>,
+) -> Result<(), ConfigError> {
+    let sources = [value.is_some(), file.is_some(), env_var.is_some()];
+    if sources.into_iter().filter(|&set| set).count() > 1 {
+        return Err(ConfigError::Message(format!(
+            "{key}: only one of {key}, {file_key} and {env_key} can be set"
+        )));
+    }
+    if let Some(file) = file {
+        let content = fs::read_to_string(file)
+            .map_err(|e| ConfigError::Message(format!("{file_key}: cannot read {}: {e}", file.display())))?;
+        *value = Some(Secret(content.trim_end_matches(['\n', '\r']).to_string()));
+    }
+    if let Some(env_var) = env_var {
+        let content = env::var(env_var)
+            .map_err(|e| ConfigError::Message(format!("{env_key}: cannot read variable {env_var}: {e}")))?;
+        *value = Some(Secret(content));
+    }
+    Ok(())
+}
+
+/// Reads the webhook header values given in `headers`, `headers_file` or `headers_env`, once per header.
+fn resolve_headers(key: &str, webhook: &mut Webhook) -> Result<(), ConfigError> {
+    let names: BTreeSet<String> = webhook
+        .headers_file
+        .keys()
+        .chain(webhook.headers_env.keys())
+        .cloned()
+        .collect();
+    for name in names {
+        let keys = [
+            format!("{key}.headers.{name}"),
+            format!("{key}.headers_file.{name}"),
+            format!("{key}.headers_env.{name}"),
+        ];
+        let mut value = webhook.headers.remove(&name);
+        resolve_secret_from(
+            &keys,
+            &mut value,
+            webhook.headers_file.get(&name).map(PathBuf::as_path),
+            webhook.headers_env.get(&name).map(String::as_str),
+        )?;
+        webhook.headers.extend(value.map(|value| (name, value)));
+    }
+    Ok(())
+}
+
+/// Resolves a relative state file path in the state directory systemd creates for the service
+/// (`StateDirectory=`, the first one if several), or leaves it relative to the working directory.
+fn state_path(path: &Path, state_directory: Option<&str>) -> PathBuf {
+    match state_directory.and_then(|directories| directories.split(':').next()) {
+        Some(directory) if path.is_relative() && !directory.is_empty() => Path::new(directory).join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 #[allow(unused)]
 pub struct InfluxDB {
     pub host: String,
     pub port: usize,
     pub database: String,
     pub prefix: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<Secret>,
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
+    #[serde(default)]
+    pub password_env: Option<String>,
 }
 
 impl InfluxDB {
@@ -34,17 +505,60 @@ impl InfluxDB {
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 #[allow(unused)]
 pub struct Settings {
     pub log_level: String,
     pub hmi: Hmi,
+    pub state: State,
+    pub channels: [Channel; 3],
     pub serial: Serial,
     pub influxdb: Option<InfluxDB>,
+    pub tariff: Option<Tariff>,
+    pub alerts: Alerts,
+    pub notifiers: Vec<Notifier>,
+    pub load_shedding: LoadShedding,
+    pub voltage_quality: VoltageQuality,
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    /// Config sources can't be read or don't match the settings structure
+    Load(ConfigError),
+    /// Settings are well-formed but some values are unusable, one message per problem
+    Invalid(Vec<String>),
+}
+
+impl Display for SettingsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsError::Load(e) => write!(f, "{e}"),
+            SettingsError::Invalid(problems) => {
+                write!(f, "{} invalid setting(s):", problems.len())?;
+                problems.iter().try_for_each(|problem| write!(f, "\n  - {problem}"))
+            }
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+impl From<ConfigError> for SettingsError {
+    fn from(error: ConfigError) -> Self {
+        SettingsError::Load(error)
+    }
 }
 
 impl Settings {
-    pub fn new(yaml_config_opt: Option<String>) -> Result<Self, ConfigError> {
+    /// Loads and validates settings from defaults, the given YAML config then `APP__` environment variables.
+    pub fn new(yaml_config_opt: Option<String>) -> Result<Self, SettingsError> {
+        let settings = Self::load(yaml_config_opt)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Loads settings without validating them.
+    pub fn load(yaml_config_opt: Option<String>) -> Result<Self, ConfigError> {
         let mut builder = Config::builder();
         const DEFAULTS: &str = include_str!("settings.default.yml");
         builder = builder.add_source(File::from_str(DEFAULTS, FileFormat::Yaml));
@@ -54,10 +568,517 @@ impl Settings {
         // See https://github.com/mehcode/config-rs/issues/391
         let env = Environment::with_prefix("app").prefix_separator("__").separator("__");
         builder = builder.add_source(env);
-        builder.build()?.try_deserialize()
+        let mut settings: Settings = builder.build()?.try_deserialize()?;
+        settings.resolve_secrets()?;
+        settings.state.path = state_path(&settings.state.path, env::var("STATE_DIRECTORY").ok().as_deref());
+        Ok(settings)
+    }
+
+    fn resolve_secrets(&mut self) -> Result<(), ConfigError> {
+        if let Some(influxdb) = &mut self.influxdb {
+            resolve_secret(
+                "influxdb.password",
+                &mut influxdb.password,
+                influxdb.password_file.as_deref(),
+                influxdb.password_env.as_deref(),
+            )?;
+        }
+        for (index, notifier) in self.notifiers.iter_mut().enumerate() {
+            if let Some(webhook) = &mut notifier.webhook {
+                resolve_secret(
+                    &format!("notifiers[{index}].webhook.token"),
+                    &mut webhook.token,
+                    webhook.token_file.as_deref(),
+                    webhook.token_env.as_deref(),
+                )?;
+                resolve_headers(&format!("notifiers[{index}].webhook"), webhook)?;
+            }
+            if let Some(smtp) = &mut notifier.smtp {
+                resolve_secret(
+                    &format!("notifiers[{index}].smtp.password"),
+                    &mut smtp.password,
+                    smtp.password_file.as_deref(),
+                    smtp.password_env.as_deref(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists the settings that differ from `other` but are only read at startup.
+    pub fn restart_required_changes(&self, other: &Settings) -> Vec<&'static str> {
+        let changes = [
+            ("log_level", self.log_level != other.log_level),
+            ("hmi.enabled", self.hmi.enabled != other.hmi.enabled),
+            (
+                "hmi.display_backend",
+                self.hmi.display_backend != other.hmi.display_backend,
+            ),
+            (
+                "hmi.display_png_path",
+                self.hmi.display_png_path != other.hmi.display_png_path,
+            ),
+            (
+                "hmi.button_bcm_pin",
+                self.hmi.button_bcm_pin != other.hmi.button_bcm_pin,
+            ),
+            ("state.path", self.state.path != other.state.path),
+            (
+                "state.save_interval_secs",
+                self.state.save_interval_secs != other.state.save_interval_secs,
+            ),
+            ("serial.rpict", self.serial.rpict != other.serial.rpict),
+            ("serial.linky", self.serial.linky != other.serial.linky),
+            ("tariff", self.tariff != other.tariff),
+            ("alerts", self.alerts != other.alerts),
+            ("notifiers", self.notifiers != other.notifiers),
+            (
+                "load_shedding.outputs",
+                self.load_shedding.pins() != other.load_shedding.pins(),
+            ),
+            ("voltage_quality", self.voltage_quality != other.voltage_quality),
+        ];
+        changes
+            .into_iter()
+            .filter_map(|(key, changed)| changed.then_some(key))
+            .collect()
+    }
+
+    /// Checks values that deserialize fine but can't work at runtime, reporting all of them at once.
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let mut problems = Vec::new();
+        let mut check = |valid: bool, problem: String| {
+            if !valid {
+                problems.push(problem);
+            }
+        };
+        check(
+            is_valid_log_filter(&self.log_level),
+            format!("log_level: {:?} is not a valid log filter", self.log_level),
+        );
+        let hmi = &self.hmi;
+        check(
+            hmi.max_line_power_watts.is_finite() && hmi.max_line_power_watts > 0.0,
+            format!(
+                "hmi.max_line_power_watts: must be positive, got {}",
+                hmi.max_line_power_watts
+            ),
+        );
+        check(
+            hmi.sleep_timeout_secs > 0,
+            "hmi.sleep_timeout_secs: must be positive, got 0".to_string(),
+        );
+        check(
+            hmi.button_debounce_ms < hmi.sleep_timeout_secs.saturating_mul(1000),
+            format!(
+                "hmi.button_debounce_ms: must be shorter than hmi.sleep_timeout_secs, got {}",
+                hmi.button_debounce_ms
+            ),
+        );
+        check(
+            hmi.button_bcm_pin <= MAX_BCM_PIN,
+            format!(
+                "hmi.button_bcm_pin: must be a GPIO between 0 and {MAX_BCM_PIN}, got {}",
+                hmi.button_bcm_pin
+            ),
+        );
+        if hmi.enabled && hmi.display_backend == DisplayBackend::Png {
+            let parent = hmi
+                .display_png_path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty());
+            check(
+                hmi.display_png_path.file_name().is_some() && parent.is_none_or(|parent| parent.is_dir()),
+                format!(
+                    "hmi.display_png_path: {} is not a file path in an existing directory",
+                    hmi.display_png_path.display()
+                ),
+            );
+        }
+        check(
+            self.state.path.file_name().is_some(),
+            format!("state.path: {} is not a file path", self.state.path.display()),
+        );
+        check(
+            self.state.save_interval_secs > 0,
+            "state.save_interval_secs: must be positive, got 0".to_string(),
+        );
+        for (index, channel) in self.channels.iter().enumerate() {
+            check(
+                (1..=Channel::MAX_LABEL_LENGTH).contains(&channel.label.len())
+                    && channel.label.chars().all(|c| c.is_ascii_graphic()),
+                format!(
+                    "channels[{index}].label: {:?} must be 1 to {} printable ASCII characters",
+                    channel.label,
+                    Channel::MAX_LABEL_LENGTH
+                ),
+            );
+            if let Some(max_power_watts) = channel.max_power_watts {
+                check(
+                    max_power_watts.is_finite() && max_power_watts > 0.0,
+                    format!("channels[{index}].max_power_watts: must be positive, got {max_power_watts}"),
+                );
+            }
+            check(
+                channel.tags.keys().all(|key| !key.is_empty())
+                    && channel.tags.values().all(|value| !value.is_empty())
+                    && !channel.tags.contains_key("channel")
+                    && !channel.tags.contains_key("description"),
+                format!(
+                    "channels[{index}].tags: keys and values must be non-empty, \"channel\" and \"description\" are reserved"
+                ),
+            );
+        }
+        check(
+            self.channels
+                .iter()
+                .enumerate()
+                .all(|(index, channel)| self.channels[..index].iter().all(|other| other.label != channel.label)),
+            "channels: labels must be distinct".to_string(),
+        );
+        for (key, path) in [
+            ("serial.rpict", &self.serial.rpict),
+            ("serial.linky", &self.serial.linky),
+        ] {
+            check(
+                path.starts_with('/'),
+                format!("{key}: {path:?} must be an absolute device path"),
+            );
+        }
+        check(
+            self.serial.rpict != self.serial.linky,
+            format!("serial: RPICT and Linky can't share port {}", self.serial.rpict),
+        );
+        if let Some(influxdb) = &self.influxdb {
+            check(
+                !influxdb.host.is_empty() && !influxdb.host.contains(['/', ' ']),
+                format!("influxdb.host: {:?} is not a host name", influxdb.host),
+            );
+            check(
+                (1..=65535).contains(&influxdb.port),
+                format!("influxdb.port: must be between 1 and 65535, got {}", influxdb.port),
+            );
+            check(
+                !influxdb.database.is_empty(),
+                "influxdb.database: must not be empty".to_string(),
+            );
+            check(
+                influxdb.username.is_some() == influxdb.password.is_some(),
+                "influxdb.username: username and password must be set together".to_string(),
+            );
+            if let Some(prefix) = &influxdb.prefix {
+                // measurement names are not quoted in line protocol
+                check(
+                    !prefix.is_empty() && !prefix.contains([' ', ',', '=']),
+                    format!("influxdb.prefix: {prefix:?} must be non-empty, without spaces, commas nor equal signs"),
+                );
+            }
+        }
+        if let Some(tariff) = &self.tariff {
+            check(
+                (1..=3).contains(&tariff.currency.len()) && tariff.currency.chars().all(|c| c.is_ascii_graphic()),
+                format!(
+                    "tariff.currency: {:?} must be 1 to 3 printable ASCII characters, eg. EUR",
+                    tariff.currency
+                ),
+            );
+            check(
+                tariff.billing_start.day() <= 28,
+                format!(
+                    "tariff.billing_start: must be on a day between 1 and 28, got {}",
+                    tariff.billing_start
+                ),
+            );
+            check(
+                (1..=12).contains(&tariff.billing_months),
+                format!(
+                    "tariff.billing_months: must be between 1 and 12, got {}",
+                    tariff.billing_months
+                ),
+            );
+            check(
+                !tariff.prices.is_empty() && tariff.prices.windows(2).all(|pair| pair[0].from < pair[1].from),
+                "tariff.prices: must be non-empty and sorted by ascending distinct dates".to_string(),
+            );
+            for (index, prices) in tariff.prices.iter().enumerate() {
+                let tempo = prices
+                    .tempo
+                    .iter()
+                    .flat_map(|tempo| [&tempo.blue, &tempo.white, &tempo.red]);
+                check(
+                    [prices.subscription_per_month, prices.hc_per_kwh, prices.hp_per_kwh]
+                        .into_iter()
+                        .chain(tempo.flat_map(|prices| [prices.hc_per_kwh, prices.hp_per_kwh]))
+                        .chain(
+                            prices
+                                .ejp
+                                .iter()
+                                .flat_map(|prices| [prices.hn_per_kwh, prices.pm_per_kwh]),
+                        )
+                        .all(|price| price.is_finite() && price >= 0.0),
+                    format!("tariff.prices[{index}]: prices must be positive or zero"),
+                );
+            }
+        }
+        let alerts = &self.alerts;
+        check(
+            alerts.overrun_warning_percent > 0.0 && alerts.overrun_warning_percent <= 100.0,
+            format!(
+                "alerts.overrun_warning_percent: must be between 0 and 100, got {}",
+                alerts.overrun_warning_percent
+            ),
+        );
+        if let Some(subscribed_current_amps) = alerts.subscribed_current_amps {
+            check(
+                subscribed_current_amps.is_finite() && subscribed_current_amps > 0.0,
+                format!("alerts.subscribed_current_amps: must be positive, got {subscribed_current_amps}"),
+            );
+        }
+        check(
+            alerts.imbalance_warning_percent > 0.0 && alerts.imbalance_warning_percent <= 200.0,
+            format!(
+                "alerts.imbalance_warning_percent: must be between 0 and 200, got {}",
+                alerts.imbalance_warning_percent
+            ),
+        );
+        check(
+            alerts.imbalance_min_current_amps.is_finite() && alerts.imbalance_min_current_amps >= 0.0,
+            format!(
+                "alerts.imbalance_min_current_amps: must be positive or zero, got {}",
+                alerts.imbalance_min_current_amps
+            ),
+        );
+        for (index, rule) in alerts.rules.iter().enumerate() {
+            check(
+                (1..=AlertRule::MAX_NAME_LENGTH).contains(&rule.name.len())
+                    && rule
+                        .name
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+                    && ![
+                        OverrunDetector::OVERRUN,
+                        OverrunDetector::NEAR_OVERRUN,
+                        ImbalanceDetector::PHASE_IMBALANCE,
+                    ]
+                    .contains(&rule.name.as_str())
+                    && Source::ALL.iter().all(|source| source.alert_name() != rule.name),
+                format!(
+                    "alerts.rules[{index}].name: {:?} must be 1 to {} lowercase letters, digits, - or _, \
+                    built-in alert names are reserved",
+                    rule.name,
+                    AlertRule::MAX_NAME_LENGTH
+                ),
+            );
+            check(
+                rule.above.is_some() != rule.below.is_some()
+                    && rule.above.or(rule.below).is_some_and(|threshold| threshold.is_finite()),
+                format!("alerts.rules[{index}]: exactly one of above and below must be set"),
+            );
+            check(
+                rule.hysteresis.is_finite() && rule.hysteresis >= 0.0,
+                format!(
+                    "alerts.rules[{index}].hysteresis: must be positive or zero, got {}",
+                    rule.hysteresis
+                ),
+            );
+        }
+        check(
+            alerts
+                .rules
+                .iter()
+                .enumerate()
+                .all(|(index, rule)| alerts.rules[..index].iter().all(|other| other.name != rule.name)),
+            "alerts.rules: names must be distinct".to_string(),
+        );
+        let mut alert_names: Vec<&str> = vec![
+            OverrunDetector::OVERRUN,
+            OverrunDetector::NEAR_OVERRUN,
+            ImbalanceDetector::PHASE_IMBALANCE,
+        ];
+        alert_names.extend(Source::ALL.iter().map(|source| source.alert_name()));
+        alert_names.extend(alerts.rules.iter().map(|rule| rule.name.as_str()));
+        for (index, notifier) in self.notifiers.iter().enumerate() {
+            check(
+                !notifier.name.is_empty()
+                    && notifier
+                        .name
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-'),
+                format!(
+                    "notifiers[{index}].name: {:?} must be lowercase letters, digits, - or _",
+                    notifier.name
+                ),
+            );
+            check(
+                notifier.webhook.is_some() != notifier.smtp.is_some(),
+                format!("notifiers[{index}]: exactly one of webhook and smtp must be set"),
+            );
+            if let Some(webhook) = &notifier.webhook {
+                check(
+                    webhook.url.starts_with("http://") || webhook.url.starts_with("https://"),
+                    format!("notifiers[{index}].webhook.url: {:?} is not an HTTP URL", webhook.url),
+                );
+                if let Some(body) = &webhook.body {
+                    check(
+                        webhook::is_valid_body(body),
+                        format!("notifiers[{index}].webhook.body: must be JSON once placeholders are replaced"),
+                    );
+                }
+                check(
+                    webhook.headers.keys().all(|key| !key.is_empty() && key.is_ascii()),
+                    format!("notifiers[{index}].webhook.headers: names must be non-empty ASCII"),
+                );
+            }
+            if let Some(smtp) = &notifier.smtp {
+                check(
+                    !smtp.host.is_empty() && !smtp.host.contains(['/', ' ']),
+                    format!("notifiers[{index}].smtp.host: {:?} is not a host name", smtp.host),
+                );
+                check(
+                    smtp.username.is_some() == smtp.password.is_some(),
+                    format!("notifiers[{index}].smtp.username: username and password must be set together"),
+                );
+                check(
+                    smtp::is_valid_mailbox(&smtp.from),
+                    format!("notifiers[{index}].smtp.from: {:?} is not an email address", smtp.from),
+                );
+                check(
+                    !smtp.to.is_empty() && smtp.to.iter().all(|to| smtp::is_valid_mailbox(to)),
+                    format!("notifiers[{index}].smtp.to: must be a non-empty list of email addresses"),
+                );
+            }
+            check(
+                notifier
+                    .events
+                    .iter()
+                    .all(|event| alert_names.contains(&event.as_str())),
+                format!(
+                    "notifiers[{index}].events: must be alert names among {}",
+                    alert_names.join(", ")
+                ),
+            );
+            check(
+                notifier.max_per_hour > 0,
+                format!("notifiers[{index}].max_per_hour: must be positive, got 0"),
+            );
+            check(
+                notifier.retries <= Notifier::MAX_RETRIES,
+                format!(
+                    "notifiers[{index}].retries: must be at most {}, got {}",
+                    Notifier::MAX_RETRIES,
+                    notifier.retries
+                ),
+            );
+        }
+        check(
+            self.notifiers
+                .iter()
+                .enumerate()
+                .all(|(index, notifier)| self.notifiers[..index].iter().all(|other| other.name != notifier.name)),
+            "notifiers: names must be distinct".to_string(),
+        );
+        let shedding = &self.load_shedding;
+        check(
+            shedding.shed_above_percent > 0.0 && shedding.shed_above_percent <= 200.0,
+            format!(
+                "load_shedding.shed_above_percent: must be between 0 and 200, got {}",
+                shedding.shed_above_percent
+            ),
+        );
+        check(
+            shedding.restore_below_percent > 0.0 && shedding.restore_below_percent < shedding.shed_above_percent,
+            format!(
+                "load_shedding.restore_below_percent: must be positive and below shed_above_percent, got {}",
+                shedding.restore_below_percent
+            ),
+        );
+        let display_pins = if hmi.enabled && hmi.display_backend == DisplayBackend::Ssd1305 {
+            [&[ssd1305::GPIO_DC, ssd1305::GPIO_RST][..], &ssd1305::SPI0_BCM_PINS].concat()
+        } else {
+            Vec::new()
+        };
+        for (index, output) in shedding.outputs.iter().enumerate() {
+            check(
+                (1..=Output::MAX_NAME_LENGTH).contains(&output.name.len())
+                    && output
+                        .name
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-'),
+                format!(
+                    "load_shedding.outputs[{index}].name: {:?} must be 1 to {} lowercase letters, digits, - or _",
+                    output.name,
+                    Output::MAX_NAME_LENGTH
+                ),
+            );
+            check(
+                output.bcm_pin <= MAX_BCM_PIN
+                    && !(hmi.enabled && output.bcm_pin == hmi.button_bcm_pin)
+                    && !display_pins.contains(&output.bcm_pin),
+                format!(
+                    "load_shedding.outputs[{index}].bcm_pin: must be a GPIO between 0 and {MAX_BCM_PIN} \
+                    other than the push button and display ones, got {}",
+                    output.bcm_pin
+                ),
+            );
+            check(
+                !output.periods.contains(&TariffPeriod::Unknown),
+                format!("load_shedding.outputs[{index}].periods: must be HC, HP, HN or PM"),
+            );
+        }
+        check(
+            shedding.outputs.iter().enumerate().all(|(index, output)| {
+                shedding.outputs[..index]
+                    .iter()
+                    .all(|other| other.name != output.name && other.bcm_pin != output.bcm_pin)
+            }),
+            "load_shedding.outputs: names and pins must be distinct".to_string(),
+        );
+        let voltage = &self.voltage_quality;
+        check(
+            voltage.nominal_volts.is_finite() && voltage.nominal_volts > 0.0,
+            format!(
+                "voltage_quality.nominal_volts: must be positive, got {}",
+                voltage.nominal_volts
+            ),
+        );
+        check(
+            voltage.tolerance_percent > 0.0 && voltage.tolerance_percent < 100.0,
+            format!(
+                "voltage_quality.tolerance_percent: must be between 0 and 100, got {}",
+                voltage.tolerance_percent
+            ),
+        );
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(SettingsError::Invalid(problems))
+        }
     }
 }
 
+/// Highest GPIO number on the Raspberry Pi 40 pins header.
+const MAX_BCM_PIN: u8 = 27;
+
+/// Checks a filter the way `env_logger` reads it, eg. `info` or `warn,energy_monitor=debug/regex`.
+fn is_valid_log_filter(filter: &str) -> bool {
+    let is_level = |level: &str| level.parse::<log::LevelFilter>().is_ok();
+    let is_module = |module: &str| {
+        !module.is_empty()
+            && module
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+    };
+    let directives = filter.split_once('/').map_or(filter, |(directives, _regex)| directives);
+    directives
+        .split(',')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .all(|directive| match directive.split_once('=') {
+            Some((module, level)) => is_module(module) && is_level(level),
+            None => is_level(directive) || is_module(directive),
+        })
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -73,5 +1094,336 @@ mod tests {
         let example_settings = include_str!("settings.example.yml").to_string();
         let settings = Settings::new(Some(example_settings)).unwrap();
         assert!(settings.influxdb.is_some());
+        assert_eq!(settings.channels[1].label, "HEAT");
+        assert_eq!(settings.channels[1].max_power_watts(&settings.hmi), 3000.0);
+        assert_eq!(settings.channels[2].max_power_watts(&settings.hmi), 6900.0);
+        let tariff = settings.tariff.unwrap();
+        assert_eq!(tariff.prices.len(), 2);
+        assert_eq!(tariff.prices[1].period_prices(Some(TempoColor::Red)).hp_per_kwh, 0.7562);
+        assert_eq!(tariff.prices[1].ejp.as_ref().unwrap().pm_per_kwh, 1.0160);
+        assert_eq!(settings.alerts.rules[1].metric, Metric::L2Vrms);
+        assert_eq!(settings.notifiers[0].min_severity, Severity::Critical);
+        assert_eq!(settings.notifiers[1].smtp.as_ref().unwrap().port(), 587);
+        assert_eq!(settings.load_shedding.outputs[0].periods, vec![TariffPeriod::HC]);
+    }
+
+    #[test]
+    fn test_validate_default_settings() {
+        // Given
+        let settings = Settings::load(None).unwrap();
+        // When & Then
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_reports_all_problems() {
+        // Given
+        let yaml = "
+log_level: info,energy_monitor=verbose
+hmi:
+  max_line_power_watts: 0
+  button_bcm_pin: 40
+channels:
+  - label: KITCHEN
+  - label: P2
+    max_power_watts: -1
+  - label: P2
+    tags:
+      channel: oven
+serial:
+  rpict: ttyAMA0
+  linky: ttyAMA0
+influxdb:
+  port: 0
+  prefix: energy monitor
+tariff:
+  currency: EUR
+  billing_start: 2023-01-31
+  billing_months: 2
+  prices:
+    - from: 2023-08-01
+      subscription_per_month: 16.16
+      hc_per_kwh: 0.2068
+      hp_per_kwh: 0.2700
+      tempo:
+        blue: { hc_per_kwh: 0.1296, hp_per_kwh: 0.1609 }
+        white: { hc_per_kwh: 0.1486, hp_per_kwh: 0.1894 }
+        red: { hc_per_kwh: 0.1568, hp_per_kwh: -0.7562 }
+    - from: 2023-02-01
+      subscription_per_month: 15.65
+      hc_per_kwh: -0.1828
+      hp_per_kwh: 0.2460
+alerts:
+  overrun_warning_percent: 120
+  subscribed_current_amps: 0
+  imbalance_warning_percent: 250
+  rules:
+    - name: overrun
+      metric: l2_vrms
+      below: 207
+    - name: high power
+      metric: total_apparent_power
+      above: 9000
+      below: 100
+      hysteresis: -1
+    - name: overrun
+      metric: linky_frame_age_secs
+notifiers:
+  - name: phone
+    webhook:
+      url: ntfy.sh/energy-monitor
+      body: '{{message}}'
+    smtp:
+      host: smtp.example.com
+      from: energy-monitor
+      to: []
+    events: [overrun, unknown]
+    max_per_hour: 0
+    retries: 20
+  - name: Phone
+    webhook:
+      url: https://ntfy.sh
+  - name: phone
+    smtp:
+      host: smtp.example.com
+      username: energy-monitor
+      from: energy-monitor@example.com
+      to: [me@example.com]
+load_shedding:
+  restore_below_percent: 100
+  outputs:
+    - name: water_heater_1
+      bcm_pin: 40
+      periods: [UNKNOWN]
+    - name: heaters
+      bcm_pin: 22
+    - name: heaters
+      bcm_pin: 22
+voltage_quality:
+  tolerance_percent: 0
+";
+        let settings = Settings::load(Some(yaml.to_string())).unwrap();
+        // When
+        let result = settings.validate();
+        // Then
+        let Err(SettingsError::Invalid(problems)) = result else {
+            panic!("expected invalid settings, got {result:?}");
+        };
+        let keys = problems
+            .iter()
+            .map(|problem| problem.split(':').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                "log_level",
+                "hmi.max_line_power_watts",
+                "hmi.button_bcm_pin",
+                "channels[0].label",
+                "channels[1].max_power_watts",
+                "channels[2].tags",
+                "channels",
+                "serial.rpict",
+                "serial.linky",
+                "serial",
+                "influxdb.port",
+                "influxdb.prefix",
+                "tariff.billing_start",
+                "tariff.prices",
+                "tariff.prices[0]",
+                "tariff.prices[1]",
+                "alerts.overrun_warning_percent",
+                "alerts.subscribed_current_amps",
+                "alerts.imbalance_warning_percent",
+                "alerts.rules[0].name",
+                "alerts.rules[1].name",
+                "alerts.rules[1]",
+                "alerts.rules[1].hysteresis",
+                "alerts.rules[2].name",
+                "alerts.rules[2]",
+                "alerts.rules",
+                "notifiers[0]",
+                "notifiers[0].webhook.url",
+                "notifiers[0].webhook.body",
+                "notifiers[0].smtp.from",
+                "notifiers[0].smtp.to",
+                "notifiers[0].events",
+                "notifiers[0].max_per_hour",
+                "notifiers[0].retries",
+                "notifiers[1].name",
+                "notifiers[2].smtp.username",
+                "notifiers",
+                "load_shedding.restore_below_percent",
+                "load_shedding.outputs[0].name",
+                "load_shedding.outputs[0].bcm_pin",
+                "load_shedding.outputs[0].periods",
+                "load_shedding.outputs",
+                "voltage_quality.tolerance_percent",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_huge_sleep_timeout() {
+        // Given
+        let yaml = "
+hmi:
+  sleep_timeout_secs: 18446744073709551615
+";
+        let settings = Settings::load(Some(yaml.to_string())).unwrap();
+        // When
+        let result = settings.validate();
+        // Then
+        assert!(result.is_ok(), "{result:?}");
+    }
+
+    #[test]
+    fn test_validate_output_on_display_pin() {
+        // Given
+        let yaml = |backend: &str| {
+            format!(
+                "
+hmi:
+  display_backend: {backend}
+load_shedding:
+  outputs:
+    - name: water_heater
+      bcm_pin: 24
+    - name: heaters
+      bcm_pin: 10
+"
+            )
+        };
+        // When
+        let ssd1305 = Settings::load(Some(yaml("ssd1305"))).unwrap().validate();
+        let terminal = Settings::load(Some(yaml("terminal"))).unwrap().validate();
+        // Then
+        let Err(SettingsError::Invalid(problems)) = ssd1305 else {
+            panic!("expected invalid settings, got {ssd1305:?}");
+        };
+        let keys = problems
+            .iter()
+            .map(|problem| problem.split(':').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec!["load_shedding.outputs[0].bcm_pin", "load_shedding.outputs[1].bcm_pin"]
+        );
+        assert!(terminal.is_ok(), "{terminal:?}");
+    }
+
+    #[test]
+    fn test_load_secrets() {
+        // Given
+        let path = env::temp_dir().join(format!("energy-monitor-test-secret-{}", std::process::id()));
+        fs::write(&path, "s3cr3t\n").unwrap();
+        env::set_var("ENERGY_MONITOR_TEST_PASSWORD", "from-env");
+        let yaml = |source: &str| format!("influxdb:\n  username: energy\n  {source}\n");
+        // When
+        let from_file = Settings::load(Some(yaml(&format!("password_file: {}", path.display())))).unwrap();
+        let from_env = Settings::load(Some(yaml("password_env: ENERGY_MONITOR_TEST_PASSWORD"))).unwrap();
+        let conflicting = Settings::load(Some(yaml(&format!(
+            "password: inline\n  password_file: {}",
+            path.display()
+        ))));
+        let missing_file = Settings::load(Some(yaml("password_file: /nonexistent/secret")));
+        // Then
+        let password = |settings: &Settings| settings.influxdb.as_ref().unwrap().password.clone();
+        assert_eq!(password(&from_file).unwrap().expose(), "s3cr3t");
+        assert_eq!(password(&from_env).unwrap().expose(), "from-env");
+        assert!(conflicting.is_err());
+        assert!(missing_file.is_err());
+        assert!(!format!("{from_file:?}").contains("s3cr3t"));
+        assert!(!serde_yaml::to_string(&from_file).unwrap().contains("s3cr3t"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_webhook_headers() {
+        // Given
+        let path = env::temp_dir().join(format!("energy-monitor-test-header-{}", std::process::id()));
+        fs::write(&path, "s3cr3t\n").unwrap();
+        env::set_var("ENERGY_MONITOR_TEST_HEADER", "from-env");
+        let yaml = |headers: &str| {
+            format!(
+                "
+notifiers:
+  - name: phone
+    webhook:
+      url: https://ntfy.sh
+{headers}"
+            )
+        };
+        // When
+        let settings = Settings::load(Some(yaml(&format!(
+            "      headers: {{x-priority: inline}}
+      headers_file: {{x-gotify-key: {}}}
+      headers_env: {{x-api-key: ENERGY_MONITOR_TEST_HEADER}}",
+            path.display()
+        ))))
+        .unwrap();
+        let conflicting = Settings::load(Some(yaml(&format!(
+            "      headers: {{x-gotify-key: inline}}
+      headers_file: {{x-gotify-key: {}}}",
+            path.display()
+        ))));
+        // Then
+        let headers = &settings.notifiers[0].webhook.as_ref().unwrap().headers;
+        let header = |name: &str| headers.get(name).map(Secret::expose);
+        assert_eq!(header("x-priority"), Some("inline"));
+        assert_eq!(header("x-gotify-key"), Some("s3cr3t"));
+        assert_eq!(header("x-api-key"), Some("from-env"));
+        assert!(conflicting.is_err());
+        let printed = format!("{settings:?}{}", serde_yaml::to_string(&settings).unwrap());
+        for value in ["inline", "s3cr3t", "from-env"] {
+            assert!(!printed.contains(value), "{value} printed");
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_state_path() {
+        // Given
+        let relative = Path::new("state.json");
+        let absolute = Path::new("/srv/energy-monitor/state.json");
+        // When & Then
+        assert_eq!(state_path(relative, None), PathBuf::from("state.json"));
+        assert_eq!(
+            state_path(relative, Some("/var/lib/energy-monitor")),
+            PathBuf::from("/var/lib/energy-monitor/state.json")
+        );
+        assert_eq!(
+            state_path(relative, Some("/var/lib/energy-monitor:/var/lib/other")),
+            PathBuf::from("/var/lib/energy-monitor/state.json")
+        );
+        assert_eq!(state_path(relative, Some("")), PathBuf::from("state.json"));
+        assert_eq!(state_path(absolute, Some("/var/lib/energy-monitor")), absolute);
+    }
+
+    #[test]
+    fn test_restart_required_changes() {
+        // Given
+        let settings = Settings::load(None).unwrap();
+        let yaml = "
+hmi:
+  max_line_power_watts: 9000
+  button_bcm_pin: 17
+serial:
+  linky: /dev/ttyUSB1
+";
+        let reloaded = Settings::load(Some(yaml.to_string())).unwrap();
+        // When
+        let changes = settings.restart_required_changes(&reloaded);
+        // Then
+        assert_eq!(changes, vec!["hmi.button_bcm_pin", "serial.linky"]);
+    }
+
+    #[test]
+    fn test_is_valid_log_filter() {
+        assert!(is_valid_log_filter("INFO"));
+        assert!(is_valid_log_filter("warn,energy_monitor::actor=debug"));
+        assert!(is_valid_log_filter("energy_monitor/frame"));
+        assert!(!is_valid_log_filter("INFO-"));
+        assert!(!is_valid_log_filter("energy_monitor=loud"));
     }
 }
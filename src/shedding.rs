@@ -0,0 +1,401 @@
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::driver::linky::{LinkyFrame, TariffPeriod};
+use crate::driver::rpict::RpictFrame;
+use crate::settings::{self, OutputMode};
+
+/// What decided the current level of an output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputReason {
+    /// Forced by the output mode
+    Manual,
+    /// Cut off while a line draws close to the subscribed current
+    Shed,
+    /// Cut off outside of the allowed tariff periods, or while the period is unknown
+    Period,
+    TempoRedDay,
+    /// Kept at its level until its minimum on or off time elapsed
+    MinTime,
+    /// On, nothing preventing the load from running
+    Auto,
+}
+
+impl Display for OutputReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputReason::Manual => write!(f, "manual"),
+            OutputReason::Shed => write!(f, "shed"),
+            OutputReason::Period => write!(f, "period"),
+            OutputReason::TempoRedDay => write!(f, "red day"),
+            OutputReason::MinTime => write!(f, "min time"),
+            OutputReason::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// Level of a relay, as published to the HMI and sinks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputState {
+    pub name: String,
+    pub on: bool,
+    pub reason: OutputReason,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Runtime state of an output.
+#[derive(Clone, Debug)]
+struct Control {
+    on: Option<bool>,
+    reason: OutputReason,
+    is_shed: bool,
+    changed_at: DateTime<Utc>,
+}
+
+/// Decides the level of the load shedding outputs from the lines current, the tariff period and the Tempo
+/// day color. Outputs are shed one at a time by ascending priority while the most loaded line stays above
+/// the shed threshold, then restored one at a time by descending priority once it stayed below the restore one.
+pub struct LoadShedder {
+    settings: settings::LoadShedding,
+    subscribed_current_amps: Option<f32>,
+    linky_amps: Option<(f32, DateTime<Utc>)>,
+    rpict_amps: Option<(f32, DateTime<Utc>)>,
+    period: TariffPeriod,
+    is_tempo_red_day: bool,
+    controls: Vec<Control>,
+    last_step: Option<DateTime<Utc>>,
+    below_restore_since: Option<DateTime<Utc>>,
+}
+
+impl LoadShedder {
+    /// Currents older than this are ignored, so that a silent source doesn't hold outputs shed.
+    const MAX_AGE_SECS: i64 = 10;
+
+    pub fn new(settings: &settings::LoadShedding, subscribed_current_amps: Option<f32>, now: DateTime<Utc>) -> Self {
+        Self {
+            settings: settings.clone(),
+            subscribed_current_amps,
+            linky_amps: None,
+            rpict_amps: None,
+            period: TariffPeriod::Unknown,
+            is_tempo_red_day: false,
+            controls: vec![
+                Control {
+                    on: None,
+                    reason: OutputReason::Auto,
+                    is_shed: false,
+                    changed_at: now,
+                };
+                settings.outputs.len()
+            ],
+            last_step: None,
+            below_restore_since: None,
+        }
+    }
+
+    /// Applies new thresholds, modes and rules, outputs being matched by position.
+    pub fn reload(&mut self, settings: &settings::LoadShedding) {
+        self.settings.shed_above_percent = settings.shed_above_percent;
+        self.settings.restore_below_percent = settings.restore_below_percent;
+        self.settings.shed_step_secs = settings.shed_step_secs;
+        self.settings.restore_step_secs = settings.restore_step_secs;
+        for (output, reloaded) in self.settings.outputs.iter_mut().zip(&settings.outputs) {
+            *output = settings::Output {
+                name: output.name.clone(),
+                bcm_pin: output.bcm_pin,
+                active_low: output.active_low,
+                ..reloaded.clone()
+            };
+        }
+    }
+
+    pub fn update_linky(&mut self, frame: &LinkyFrame) {
+        if self.subscribed_current_amps.is_none() {
+            self.subscribed_current_amps = frame.isousc.map(f32::from);
+        }
+        self.period = frame.ptec();
+        self.is_tempo_red_day = frame.is_tempo_red_day();
+        self.linky_amps = frame
+            .iinst
+            .iter()
+            .flatten()
+            .max()
+            .map(|amps| (f32::from(*amps), frame.timestamp));
+    }
+
+    pub fn update_rpict(&mut self, frame: &RpictFrame) {
+        self.rpict_amps = [frame.l1_irms, frame.l2_irms, frame.l3_irms]
+            .into_iter()
+            .filter(|irms| irms.is_finite())
+            .max_by(f32::total_cmp)
+            .map(|amps| (amps, frame.timestamp));
+    }
+
+    /// Current of the most loaded line, from the freshest sources.
+    fn line_amps(&self, now: DateTime<Utc>) -> Option<f32> {
+        [self.linky_amps, self.rpict_amps]
+            .into_iter()
+            .flatten()
+            .filter(|(_, timestamp)| now - *timestamp <= Duration::seconds(Self::MAX_AGE_SECS))
+            .map(|(amps, _)| amps)
+            .max_by(f32::total_cmp)
+    }
+
+    /// Sheds or restores at most one output.
+    fn step(&mut self, now: DateTime<Utc>) {
+        let (Some(subscribed), Some(amps)) = (self.subscribed_current_amps, self.line_amps(now)) else {
+            return;
+        };
+        let outputs = &self.settings.outputs;
+        let elapsed_since_step = |secs: u64| {
+            self.last_step
+                .is_none_or(|last| now - last >= Duration::seconds(secs as i64))
+        };
+        if amps >= subscribed * self.settings.shed_above_percent / 100.0 {
+            self.below_restore_since = None;
+            if !elapsed_since_step(self.settings.shed_step_secs) {
+                return;
+            }
+            // among the loads drawing current, the lowest priority, the last declared on ties
+            let candidate = (0..outputs.len())
+                .filter(|&index| {
+                    let control = &self.controls[index];
+                    outputs[index].mode == OutputMode::Auto && !control.is_shed && control.on == Some(true)
+                })
+                .min_by_key(|&index| (outputs[index].priority, std::cmp::Reverse(index)));
+            if let Some(index) = candidate {
+                log::info!("Shedding {} at {amps:.1} A", outputs[index].name);
+                self.controls[index].is_shed = true;
+                self.last_step = Some(now);
+            }
+        } else if amps < subscribed * self.settings.restore_below_percent / 100.0 {
+            let below_since = *self.below_restore_since.get_or_insert(now);
+            let restore_step = Duration::seconds(self.settings.restore_step_secs as i64);
+            if now - below_since < restore_step || !elapsed_since_step(self.settings.restore_step_secs) {
+                return;
+            }
+            let candidate = (0..outputs.len())
+                .filter(|&index| self.controls[index].is_shed)
+                .max_by_key(|&index| (outputs[index].priority, std::cmp::Reverse(index)));
+            if let Some(index) = candidate {
+                log::info!("Restoring {} at {amps:.1} A", outputs[index].name);
+                self.controls[index].is_shed = false;
+                self.last_step = Some(now);
+            }
+        } else {
+            self.below_restore_since = None;
+        }
+    }
+
+    /// Level an output should have and why, before minimum times are applied.
+    fn target(&self, output: &settings::Output, control: &Control) -> (bool, OutputReason) {
+        match output.mode {
+            OutputMode::On => (true, OutputReason::Manual),
+            OutputMode::Off => (false, OutputReason::Manual),
+            OutputMode::Auto if control.is_shed => (false, OutputReason::Shed),
+            OutputMode::Auto if !output.periods.is_empty() && !output.periods.contains(&self.period) => {
+                (false, OutputReason::Period)
+            }
+            OutputMode::Auto if output.off_on_tempo_red_days && self.is_tempo_red_day => {
+                (false, OutputReason::TempoRedDay)
+            }
+            OutputMode::Auto => (true, OutputReason::Auto),
+        }
+    }
+
+    /// Updates the outputs, returning the states of all of them when any changed.
+    pub fn tick(&mut self, now: DateTime<Utc>) -> Option<Vec<OutputState>> {
+        for (output, control) in self.settings.outputs.iter().zip(&mut self.controls) {
+            // a manual mode takes over a shed output
+            if output.mode != OutputMode::Auto {
+                control.is_shed = false;
+            }
+        }
+        self.step(now);
+        let mut changed = false;
+        for index in 0..self.controls.len() {
+            let output = &self.settings.outputs[index];
+            let control = &self.controls[index];
+            let (on, reason) = self.target(output, control);
+            let min_secs = if control.on == Some(true) {
+                output.min_on_secs
+            } else {
+                output.min_off_secs
+            };
+            // shedding can't wait, the breaker would trip
+            let (on, reason) = match control.on {
+                Some(current)
+                    if current != on
+                        && !matches!(reason, OutputReason::Manual | OutputReason::Shed)
+                        && now - control.changed_at < Duration::seconds(min_secs as i64) =>
+                {
+                    (current, OutputReason::MinTime)
+                }
+                _ => (on, reason),
+            };
+            let control = &mut self.controls[index];
+            if control.on != Some(on) {
+                control.changed_at = now;
+                changed = true;
+            }
+            changed |= control.reason != reason;
+            control.on = Some(on);
+            control.reason = reason;
+        }
+        changed.then(|| self.states(now))
+    }
+
+    pub fn states(&self, now: DateTime<Utc>) -> Vec<OutputState> {
+        self.settings
+            .outputs
+            .iter()
+            .zip(&self.controls)
+            .map(|(output, control)| OutputState {
+                name: output.name.clone(),
+                on: control.on.unwrap_or(false),
+                reason: control.reason,
+                timestamp: now,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn output(name: &str, priority: u8) -> settings::Output {
+        settings::Output {
+            name: name.to_string(),
+            bcm_pin: 17,
+            active_low: false,
+            priority,
+            mode: OutputMode::Auto,
+            periods: Vec::new(),
+            off_on_tempo_red_days: false,
+            min_on_secs: 0,
+            min_off_secs: 0,
+        }
+    }
+
+    fn settings(outputs: Vec<settings::Output>) -> settings::LoadShedding {
+        settings::LoadShedding {
+            shed_above_percent: 95.0,
+            restore_below_percent: 80.0,
+            shed_step_secs: 5,
+            restore_step_secs: 60,
+            outputs,
+        }
+    }
+
+    fn linky(ptec: &str, amps: u16, timestamp: DateTime<Utc>) -> LinkyFrame {
+        LinkyFrame {
+            adco: "041876097767".to_string(),
+            ptec: ptec.to_string(),
+            isousc: Some(30),
+            iinst: [Some(amps), Some(1), Some(1)],
+            timestamp,
+            ..LinkyFrame::default()
+        }
+    }
+
+    fn levels(states: &Option<Vec<OutputState>>) -> Vec<(bool, OutputReason)> {
+        states.iter().flatten().map(|state| (state.on, state.reason)).collect()
+    }
+
+    #[test]
+    fn test_shed_by_priority_then_restore() {
+        // Given
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut shedder = LoadShedder::new(
+            &settings(vec![output("heaters", 1), output("water_heater", 0)]),
+            None,
+            now,
+        );
+        shedder.update_linky(&linky("HP", 10, now));
+        let started = shedder.tick(now);
+        // When
+        let at = |secs| now + Duration::seconds(secs);
+        shedder.update_linky(&linky("HP", 29, at(1)));
+        let first_shed = shedder.tick(at(1));
+        shedder.update_linky(&linky("HP", 29, at(2)));
+        let settling = shedder.tick(at(2));
+        shedder.update_linky(&linky("HP", 29, at(6)));
+        let second_shed = shedder.tick(at(6));
+        shedder.update_linky(&linky("HP", 20, at(7)));
+        let below = shedder.tick(at(7));
+        shedder.update_linky(&linky("HP", 20, at(67)));
+        let first_restored = shedder.tick(at(67));
+        // Then
+        assert_eq!(
+            levels(&started),
+            vec![(true, OutputReason::Auto), (true, OutputReason::Auto)]
+        );
+        assert_eq!(
+            levels(&first_shed),
+            vec![(true, OutputReason::Auto), (false, OutputReason::Shed)]
+        );
+        assert_eq!(settling, None);
+        assert_eq!(
+            levels(&second_shed),
+            vec![(false, OutputReason::Shed), (false, OutputReason::Shed)]
+        );
+        assert_eq!(below, None);
+        assert_eq!(
+            levels(&first_restored),
+            vec![(true, OutputReason::Auto), (false, OutputReason::Shed)]
+        );
+    }
+
+    #[test]
+    fn test_periods_tempo_and_min_times() {
+        // Given
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let water_heater = settings::Output {
+            periods: vec![TariffPeriod::HC],
+            off_on_tempo_red_days: true,
+            min_on_secs: 600,
+            ..output("water_heater", 0)
+        };
+        let mut shedder = LoadShedder::new(&settings(vec![water_heater]), Some(45.0), now);
+        // When
+        let unknown_period = shedder.tick(now);
+        shedder.update_linky(&linky("HCJB", 10, now + Duration::seconds(1)));
+        let off_peak = shedder.tick(now + Duration::seconds(1));
+        shedder.update_linky(&linky("HPJB", 10, now + Duration::seconds(2)));
+        let peak_too_soon = shedder.tick(now + Duration::seconds(2));
+        let peak = shedder.tick(now + Duration::seconds(601));
+        shedder.update_linky(&linky("HCJR", 10, now + Duration::seconds(602)));
+        let red_day = shedder.tick(now + Duration::seconds(602));
+        // Then
+        assert_eq!(levels(&unknown_period), vec![(false, OutputReason::Period)]);
+        assert_eq!(levels(&off_peak), vec![(true, OutputReason::Auto)]);
+        assert_eq!(levels(&peak_too_soon), vec![(true, OutputReason::MinTime)]);
+        assert_eq!(levels(&peak), vec![(false, OutputReason::Period)]);
+        assert_eq!(levels(&red_day), vec![(false, OutputReason::TempoRedDay)]);
+    }
+
+    #[test]
+    fn test_manual_override() {
+        // Given
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut shedder = LoadShedder::new(&settings(vec![output("heaters", 0)]), None, now);
+        shedder.update_linky(&linky("HP", 29, now));
+        assert_eq!(levels(&shedder.tick(now)), vec![(true, OutputReason::Auto)]);
+        assert_eq!(
+            levels(&shedder.tick(now + Duration::seconds(5))),
+            vec![(false, OutputReason::Shed)]
+        );
+        // When
+        shedder.reload(&settings(vec![settings::Output {
+            mode: OutputMode::On,
+            ..output("heaters", 0)
+        }]));
+        let forced = shedder.tick(now + Duration::seconds(6));
+        // Then
+        assert_eq!(levels(&forced), vec![(true, OutputReason::Manual)]);
+    }
+}
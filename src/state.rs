@@ -0,0 +1,145 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::analytics::consumption::Consumption;
+use crate::analytics::cost::Cost;
+use crate::analytics::energy::Energy;
+use crate::analytics::imbalance::Imbalance;
+use crate::analytics::peak::Peaks;
+use crate::analytics::reactive::PowerFactor;
+use crate::driver::linky::LinkyFrame;
+
+/// Last indices read from the Linky, so that pages aren't blank until the next frame.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct LinkyIndices {
+    pub adco: String,
+    pub hchc: u32,
+    pub hchp: u32,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl From<&LinkyFrame> for LinkyIndices {
+    fn from(frame: &LinkyFrame) -> Self {
+        Self {
+            adco: frame.adco.clone(),
+            hchc: frame.hchc,
+            hchp: frame.hchp,
+            timestamp: frame.timestamp,
+        }
+    }
+}
+
+/// Values worth keeping across restarts. Missing fields take their default value,
+/// so that a state file written by an older version can still be read.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct RuntimeState {
+    pub peaks: Peaks,
+    pub energy: Energy,
+    pub consumption: Consumption,
+    pub cost: Cost,
+    pub imbalance: Imbalance,
+    pub power_factor: PowerFactor,
+    pub linky: Option<LinkyIndices>,
+}
+
+impl RuntimeState {
+    /// Reads the state file, starting afresh if it is missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                log::info!("No state file at {}, starting afresh", path.display());
+                return Self::default();
+            }
+            Err(e) => {
+                log::warn!("Cannot read state file {}, starting afresh: {e}", path.display());
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            log::warn!("Corrupted state file {}, starting afresh: {e}", path.display());
+            Self::default()
+        })
+    }
+
+    /// Writes the state next to its destination then renames it, so that a power cut
+    /// leaves either the previous or the new state, never a truncated file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let content = serde_json::to_vec_pretty(self)?;
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&content)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn state_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("energy-monitor-test-{name}-{}.json", process::id()))
+    }
+
+    #[test]
+    fn test_runtime_state_save_and_load() {
+        // Given
+        let path = state_path("state");
+        let now = Utc.with_ymd_and_hms(2023, 3, 14, 12, 0, 0).unwrap();
+        let mut state = RuntimeState::default();
+        state.peaks.update([100.0, 200.0, 300.0], now);
+        state.energy.update([100.0, -20.0, 0.0], now);
+        state
+            .energy
+            .update([100.0, -20.0, 0.0], now + chrono::Duration::seconds(36));
+        state.linky = Some(LinkyIndices {
+            adco: "041876097767".to_string(),
+            hchc: 19_650_909,
+            hchp: 43_280_553,
+            timestamp: now,
+        });
+        // When
+        state.save(&path).unwrap();
+        let actual = RuntimeState::load(&path);
+        // Then
+        assert_eq!(actual, state);
+        assert!(!path.with_extension("json.tmp").exists());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_runtime_state_load_missing_or_corrupted() {
+        // Given
+        let path = state_path("corrupted-state");
+        // When & Then
+        assert_eq!(RuntimeState::load(&path), RuntimeState::default());
+        fs::write(&path, "{\"peaks\": ").unwrap();
+        assert_eq!(RuntimeState::load(&path), RuntimeState::default());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_runtime_state_load_partial() {
+        // Given
+        let path = state_path("partial-state");
+        fs::write(&path, "{\"linky\": null}").unwrap();
+        // When
+        let actual = RuntimeState::load(&path);
+        // Then
+        assert_eq!(actual, RuntimeState::default());
+        fs::remove_file(path).unwrap();
+    }
+}
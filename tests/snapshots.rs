@@ -0,0 +1,440 @@
+//! Golden-image tests: every page is rendered in a 128×32 buffer and compared to a reference
+//! bitmap checked in `tests/snapshots` (plain PBM, one row of pixels per line).
+//! Run with `UPDATE_SNAPSHOTS=1` to regenerate the references after an intended layout change.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::{DateTime, NaiveDate};
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+
+use energy_monitor::alert::{Alert, AlertState, Severity};
+use energy_monitor::analytics::consumption::{ConsumptionSummary, TariffEnergy};
+use energy_monitor::analytics::cost::CostSummary;
+use energy_monitor::analytics::reactive::{Direction, LinePowerFactor, PowerFactorSummary};
+use energy_monitor::analytics::voltage::{Excursions, PhaseVoltage, VoltageSummary};
+use energy_monitor::display::pages::*;
+use energy_monitor::display::widgets::SparkLine;
+use energy_monitor::driver::linky::TariffPeriod;
+use energy_monitor::driver::ssd1305::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use energy_monitor::driver::virtual_display::PngTarget;
+use energy_monitor::metrics::{MetricsSnapshot, ProcessStats};
+use energy_monitor::settings::{EjpPrices, TariffPrices};
+use energy_monitor::shedding::{OutputReason, OutputState};
+
+fn render(drawable: &impl Drawable<Color = BinaryColor>) -> String {
+    let mut target = PngTarget::new(Size::new(DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32));
+    drawable.draw(&mut target).unwrap();
+    let mut pbm = format!("P1\n{DISPLAY_WIDTH} {DISPLAY_HEIGHT}\n");
+    for y in 0..DISPLAY_HEIGHT as u32 {
+        for x in 0..DISPLAY_WIDTH as u32 {
+            pbm.push(if target.is_on(x, y) { '1' } else { '0' });
+        }
+        pbm.push('\n');
+    }
+    pbm
+}
+
+fn preview(pbm: &str) -> String {
+    pbm.lines()
+        .skip(2)
+        .map(|line| line.replace('0', ".").replace('1', "#"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn assert_snapshot(name: &str, drawable: &impl Drawable<Color = BinaryColor>) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("{name}.pbm"));
+    let actual = render(drawable);
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("missing snapshot {path:?}, run with UPDATE_SNAPSHOTS=1 to create it"));
+    assert!(
+        expected == actual,
+        "snapshot {name} differs\nexpected:\n{}\nactual:\n{}",
+        preview(&expected),
+        preview(&actual)
+    );
+}
+
+#[test]
+fn test_snapshot_startup_page_disconnected() {
+    let page = StartupPage::new("1.2.3");
+    assert_snapshot("startup-disconnected", &page);
+}
+
+#[test]
+fn test_snapshot_startup_page_connected() {
+    let mut page = StartupPage::new("1.2.3");
+    page.rpict_status(true);
+    page.linky_status(true);
+    page.influxdb_status(true);
+    assert_snapshot("startup-connected", &page);
+}
+
+#[test]
+fn test_snapshot_rpict_page_unknown() {
+    let page = RpictPage::new(6900.0);
+    assert_snapshot("rpict-unknown", &page);
+}
+
+#[test]
+fn test_snapshot_rpict_page_max_gauge() {
+    let mut page = RpictPage::new(6900.0);
+    page.update(3076.0, 2229.0, 6403.0, 232.0, 232.0, 232.0);
+    page.update(232.0, 1540.0, 5670.0, 231.0, 232.0, 233.0);
+    assert_snapshot("rpict-max-gauge", &page);
+}
+
+#[test]
+fn test_snapshot_rpict_page_overflow() {
+    let mut page = RpictPage::new(6900.0);
+    page.update(12000.0, 6900.0, 99999.0, 259.95, 259.95, 259.95);
+    assert_snapshot("rpict-overflow", &page);
+}
+
+#[test]
+fn test_snapshot_rpict_page_negative_power() {
+    let mut page = RpictPage::new(6900.0);
+    page.update(-82.96, -50.23, 24.55, 257.65, 259.95, 259.70);
+    assert_snapshot("rpict-negative-power", &page);
+}
+
+#[test]
+fn test_snapshot_rpict_page_imbalance() {
+    let mut page = RpictPage::new(6900.0);
+    page.update(3076.0, 2229.0, 640.0, 232.0, 232.0, 232.0);
+    page.set_imbalance(Some(64.6));
+    assert_snapshot("rpict-imbalance", &page);
+}
+
+#[test]
+fn test_snapshot_rpict_page_custom_channels() {
+    let mut page = RpictPage::new(6900.0);
+    page.set_labels(["KIT".to_string(), "HEAT".to_string(), "P3".to_string()]);
+    page.set_max_powers([6900.0, 3000.0, 6900.0]);
+    page.update(3076.0, 2229.0, 640.0, 232.0, 232.0, 232.0);
+    assert_snapshot("rpict-custom-channels", &page);
+}
+
+#[test]
+fn test_snapshot_linky_page_unknown() {
+    let page = LinkyPage::new();
+    assert_snapshot("linky-unknown", &page);
+}
+
+#[test]
+fn test_snapshot_linky_page_hp() {
+    let mut page = LinkyPage::new();
+    page.update("005215329642".to_string(), 22_965_852, 7_431_234, TariffPeriod::HP);
+    assert_snapshot("linky-hp", &page);
+}
+
+#[test]
+fn test_snapshot_linky_page_ejp() {
+    let mut page = LinkyPage::new();
+    page.update("005215329642".to_string(), 1_234_567, 9_876_543, TariffPeriod::PM);
+    assert_snapshot("linky-ejp", &page);
+}
+
+#[test]
+fn test_snapshot_linky_page_overflow() {
+    let mut page = LinkyPage::new();
+    page.update("005215329642".to_string(), u32::MAX, u32::MAX, TariffPeriod::HC);
+    assert_snapshot("linky-overflow", &page);
+}
+
+#[test]
+fn test_snapshot_sparkline_unknown() {
+    let sparkline = SparkLine::new(Point::new(1, 14), "P1".to_string(), 6900.0);
+    assert_snapshot("sparkline-unknown", &sparkline);
+}
+
+#[test]
+fn test_snapshot_sparkline_max_gauge() {
+    let mut sparkline = SparkLine::new(Point::new(1, 14), "P1".to_string(), 6900.0);
+    sparkline.update(6900.0);
+    sparkline.update(1200.0);
+    assert_snapshot("sparkline-max-gauge", &sparkline);
+}
+
+#[test]
+fn test_snapshot_sparkline_overflow() {
+    let mut sparkline = SparkLine::new(Point::new(1, 14), "P1".to_string(), 6900.0);
+    sparkline.update(12000.0);
+    assert_snapshot("sparkline-overflow", &sparkline);
+}
+
+#[test]
+fn test_snapshot_sparkline_negative_power() {
+    let mut sparkline = SparkLine::new(Point::new(1, 14), "P1".to_string(), 6900.0);
+    sparkline.update(-82.96);
+    assert_snapshot("sparkline-negative-power", &sparkline);
+}
+
+#[test]
+fn test_snapshot_consumption_page_empty() {
+    let page = ConsumptionPage::new();
+    assert_snapshot("consumption-empty", &page);
+}
+
+#[test]
+fn test_snapshot_consumption_page() {
+    let mut page = ConsumptionPage::new();
+    page.update(ConsumptionSummary {
+        last_hour: TariffEnergy { hc_wh: 0, hp_wh: 1234 },
+        today: TariffEnergy {
+            hc_wh: 4200,
+            hp_wh: 8145,
+        },
+        yesterday: TariffEnergy {
+            hc_wh: 5100,
+            hp_wh: 9012,
+        },
+        this_month: TariffEnergy {
+            hc_wh: 98_765,
+            hp_wh: 131_402,
+        },
+        last_month: TariffEnergy {
+            hc_wh: 120_034,
+            hp_wh: 180_250,
+        },
+        average_power_w: Some(1234.0),
+        timestamp: DateTime::default(),
+    });
+    assert_snapshot("consumption", &page);
+}
+
+#[test]
+fn test_snapshot_cost_page_empty() {
+    let page = CostPage::new();
+    assert_snapshot("cost-empty", &page);
+}
+
+#[test]
+fn test_snapshot_cost_page() {
+    let mut page = CostPage::new();
+    page.update(CostSummary {
+        currency: "EUR".to_string(),
+        today: 3.27,
+        this_month: 45.81,
+        billing_period: 112.4,
+        billing_projection: 171.05,
+        billing_start: NaiveDate::from_ymd_opt(2023, 1, 15).unwrap(),
+        billing_end: NaiveDate::from_ymd_opt(2023, 3, 15).unwrap(),
+        prices: Some(TariffPrices {
+            from: NaiveDate::from_ymd_opt(2023, 2, 1).unwrap(),
+            subscription_per_month: 15.65,
+            hc_per_kwh: 0.1828,
+            hp_per_kwh: 0.246,
+            tempo: None,
+            ejp: None,
+        }),
+        tariff_period: TariffPeriod::HP,
+        tempo_color: None,
+        timestamp: DateTime::default(),
+    });
+    assert_snapshot("cost", &page);
+}
+
+#[test]
+fn test_snapshot_cost_page_ejp() {
+    let mut page = CostPage::new();
+    page.update(CostSummary {
+        currency: "EUR".to_string(),
+        today: 3.27,
+        this_month: 45.81,
+        billing_period: 112.4,
+        billing_projection: 171.05,
+        billing_start: NaiveDate::from_ymd_opt(2023, 1, 15).unwrap(),
+        billing_end: NaiveDate::from_ymd_opt(2023, 3, 15).unwrap(),
+        prices: Some(TariffPrices {
+            from: NaiveDate::from_ymd_opt(2023, 2, 1).unwrap(),
+            subscription_per_month: 15.65,
+            hc_per_kwh: 0.1828,
+            hp_per_kwh: 0.246,
+            tempo: None,
+            ejp: Some(EjpPrices {
+                hn_per_kwh: 0.1974,
+                pm_per_kwh: 1.016,
+            }),
+        }),
+        tariff_period: TariffPeriod::PM,
+        tempo_color: None,
+        timestamp: DateTime::default(),
+    });
+    assert_snapshot("cost-ejp", &page);
+}
+
+#[test]
+fn test_snapshot_voltage_page_empty() {
+    let page = VoltagePage::new();
+    assert_snapshot("voltage-empty", &page);
+}
+
+#[test]
+fn test_snapshot_voltage_page() {
+    let mut page = VoltagePage::new();
+    page.update(VoltageSummary {
+        start: DateTime::default(),
+        lines: [
+            Some(PhaseVoltage {
+                min: 250.2,
+                avg: 257.6,
+                max: 259.9,
+                over: Excursions {
+                    count: 12,
+                    duration_secs: 410.0,
+                },
+                under: Excursions::default(),
+            }),
+            Some(PhaseVoltage {
+                min: 228.4,
+                avg: 236.1,
+                max: 254.3,
+                over: Excursions {
+                    count: 1,
+                    duration_secs: 5.0,
+                },
+                under: Excursions::default(),
+            }),
+            None,
+        ],
+        unbalance_percent: Some(4.3),
+        timestamp: DateTime::default(),
+    });
+    assert_snapshot("voltage", &page);
+}
+
+#[test]
+fn test_snapshot_power_factor_page_empty() {
+    let page = PowerFactorPage::new();
+    assert_snapshot("power-factor-empty", &page);
+}
+
+#[test]
+fn test_snapshot_power_factor_page() {
+    let mut page = PowerFactorPage::new();
+    page.update(PowerFactorSummary {
+        lines: [
+            LinePowerFactor {
+                real_power: 1843.0,
+                reactive_power: 612.4,
+                power_factor: Some(0.95),
+                direction: Direction::Import,
+                today_avg_power_factor: Some(0.91),
+            },
+            LinePowerFactor {
+                real_power: -2210.0,
+                reactive_power: 180.2,
+                power_factor: Some(0.99),
+                direction: Direction::Export,
+                today_avg_power_factor: Some(0.97),
+            },
+            LinePowerFactor {
+                real_power: 4.0,
+                reactive_power: 9.2,
+                power_factor: None,
+                direction: Direction::Import,
+                today_avg_power_factor: None,
+            },
+        ],
+        timestamp: DateTime::default(),
+    });
+    assert_snapshot("power-factor", &page);
+}
+
+#[test]
+fn test_snapshot_alert_page_empty() {
+    let page = AlertPage::new();
+    assert_snapshot("alert-empty", &page);
+}
+
+#[test]
+fn test_snapshot_alert_page() {
+    let mut page = AlertPage::new();
+    page.update(Alert {
+        name: "near_overrun".to_string(),
+        severity: Severity::Warning,
+        state: AlertState::Firing,
+        message: "line 2 at 27.5 A, 92% of the 30 A subscribed".to_string(),
+        timestamp: DateTime::default(),
+    });
+    page.update(Alert {
+        name: "overrun".to_string(),
+        severity: Severity::Critical,
+        state: AlertState::Firing,
+        message: "subscribed power exceeded, 32 A drawn".to_string(),
+        timestamp: DateTime::default(),
+    });
+    assert_snapshot("alert", &page);
+}
+
+#[test]
+fn test_snapshot_shedding_page_empty() {
+    let page = SheddingPage::new();
+    assert_snapshot("shedding-empty", &page);
+}
+
+#[test]
+fn test_snapshot_shedding_page() {
+    let mut page = SheddingPage::new();
+    page.update(vec![
+        OutputState {
+            name: "water_heater".to_string(),
+            on: false,
+            reason: OutputReason::TempoRedDay,
+            timestamp: DateTime::default(),
+        },
+        OutputState {
+            name: "heaters".to_string(),
+            on: true,
+            reason: OutputReason::Auto,
+            timestamp: DateTime::default(),
+        },
+        OutputState {
+            name: "dryer".to_string(),
+            on: false,
+            reason: OutputReason::Shed,
+            timestamp: DateTime::default(),
+        },
+    ]);
+    assert_snapshot("shedding", &page);
+}
+
+#[test]
+fn test_snapshot_diagnostics_page_empty() {
+    let page = DiagnosticsPage::new();
+    assert_snapshot("diagnostics-empty", &page);
+}
+
+#[test]
+fn test_snapshot_diagnostics_page() {
+    let mut page = DiagnosticsPage::new();
+    page.update(MetricsSnapshot {
+        rpict_frames_decoded: 123_456,
+        rpict_frames_rejected: 12,
+        linky_frames_decoded: 45_678,
+        linky_frames_rejected: 3,
+        linky_checksum_errors: 7,
+        broadcast_lagged: 2,
+        influxdb_writes: 169_134,
+        influxdb_failures: 41,
+        influxdb_latency_ms: 18,
+        display_flushes: 98_765,
+        display_flush_ms: 9,
+        process: ProcessStats {
+            rss_kb: Some(5260),
+            cpu_percent: Some(1.7),
+        },
+        timestamp: DateTime::default(),
+    });
+    assert_snapshot("diagnostics", &page);
+}